@@ -0,0 +1,123 @@
+//! The allocator interface for map nodes.
+//!
+//! With the `allocator-api2` feature, `Allocator` is `allocator_api2`'s copy of the standard
+//! library's unstable trait, so maps can allocate their nodes from any allocator. Without it,
+//! `Allocator` is a sealed trait that only `Global` implements, and node storage is a plain
+//! `Vec`, so the crate needs no extra dependency.
+
+#[cfg(feature = "allocator-api2")]
+pub use allocator_api2::alloc::{Allocator, Global};
+#[cfg(feature = "allocator-api2")]
+pub(crate) use allocator_api2::vec;
+
+#[cfg(not(feature = "allocator-api2"))]
+pub use self::global::{Allocator, Global};
+#[cfg(not(feature = "allocator-api2"))]
+pub(crate) use self::global as vec;
+
+#[cfg(not(feature = "allocator-api2"))]
+pub(crate) mod global {
+    use alloc::collections::TryReserveError;
+    use alloc::vec;
+    use core::ops;
+
+    mod sealed {
+        pub trait Sealed {}
+    }
+
+    /// An allocator that map nodes can be allocated from.
+    ///
+    /// Only the global allocator is available unless the `allocator-api2` feature is enabled.
+    pub trait Allocator: sealed::Sealed {}
+
+    /// The global memory allocator.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Global;
+
+    impl sealed::Sealed for Global {}
+    impl Allocator for Global {}
+
+    // The subset of `allocator_api2::vec::Vec` that maps use, for the global allocator.
+    #[derive(Clone)]
+    pub struct Vec<T, A> {
+        vec: vec::Vec<T>,
+        alloc: A,
+    }
+
+    impl<T, A> Vec<T, A> {
+        pub fn new_in(alloc: A) -> Vec<T, A> {
+            Vec { vec: vec::Vec::new(), alloc }
+        }
+
+        pub fn with_capacity_in(capacity: usize, alloc: A) -> Vec<T, A> {
+            Vec { vec: vec::Vec::with_capacity(capacity), alloc }
+        }
+
+        pub fn allocator(&self) -> &A {
+            &self.alloc
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.vec.capacity()
+        }
+
+        pub fn push(&mut self, value: T) {
+            self.vec.push(value)
+        }
+
+        pub fn pop(&mut self) -> Option<T> {
+            self.vec.pop()
+        }
+
+        pub fn clear(&mut self) {
+            self.vec.clear()
+        }
+
+        pub fn reserve(&mut self, additional: usize) {
+            self.vec.reserve(additional)
+        }
+
+        pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+            self.vec.try_reserve(additional)
+        }
+    }
+
+    impl<T, A> ops::Deref for Vec<T, A> {
+        type Target = [T];
+        fn deref(&self) -> &[T] { &self.vec }
+    }
+
+    impl<T, A> ops::DerefMut for Vec<T, A> {
+        fn deref_mut(&mut self) -> &mut [T] { &mut self.vec }
+    }
+
+    impl<T, A> Extend<T> for Vec<T, A> {
+        fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+            self.vec.extend(iter)
+        }
+    }
+
+    impl<T, A> IntoIterator for Vec<T, A> {
+        type Item = T;
+        type IntoIter = vec::IntoIter<T>;
+        fn into_iter(self) -> vec::IntoIter<T> { self.vec.into_iter() }
+    }
+}
+
+// An allocator that is not `Global` but passes every request on to it, for testing code that is
+// generic over the allocator.
+#[cfg(all(test, feature = "allocator-api2"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Forwarding;
+
+#[cfg(all(test, feature = "allocator-api2"))]
+unsafe impl Allocator for Forwarding {
+    fn allocate(&self, layout: core::alloc::Layout)
+                -> Result<core::ptr::NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: core::alloc::Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,271 @@
+//! Looking up many keys of a map at once.
+//!
+//! A lookup in a large trie spends most of its time waiting for each node on the key's path to
+//! be loaded from memory, and it cannot ask for the next node before the current one arrives.
+//! `Map::get_many` walks the paths of several keys in lockstep instead. It prefetches each
+//! key's next node, then moves on to the other keys while that node is being loaded, so the
+//! memory latency of the walks overlaps.
+//!
+//! `Map::get_many_mut` borrows the values of several keys mutably at once.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use super::map::{chunk, Allocator, InternalNode, Map};
+use super::map::TrieNode::*;
+
+// The number of walks that are interleaved.
+const LANES: usize = 16;
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Returns a reference to the value of each key in `keys`, in the same order, or `None`
+    /// for keys that are not in the map.
+    ///
+    /// This gives the same results as calling `get` for each key, but is faster for large maps
+    /// whose nodes are not in the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<_> = vec![(1, "a"), (5, "e"), (100, "z")].into_iter().collect();
+    /// assert_eq!(map.get_many(&[5, 2, 100]), [Some(&"e"), None, Some(&"z")]);
+    /// ```
+    pub fn get_many(&self, keys: &[usize]) -> Vec<Option<&T>> {
+        let mut values = vec![None; keys.len()];
+        self.get_batch(keys, &mut values);
+        values
+    }
+
+    /// Looks up each key in `keys` like `get_many`, writing the results to the corresponding
+    /// positions of `values` rather than to a new vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` and `values` have different lengths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<_> = vec![(1, "a"), (5, "e"), (100, "z")].into_iter().collect();
+    /// let mut values = [None; 3];
+    /// for keys in [[1, 2, 5], [100, 101, 1]].iter() {
+    ///     map.get_batch(keys, &mut values);
+    ///     assert_eq!(values[0], map.get(&keys[0]));
+    /// }
+    /// assert_eq!(values, [Some(&"z"), None, Some(&"a")]);
+    /// ```
+    pub fn get_batch<'a>(&'a self, keys: &[usize], values: &mut [Option<&'a T>]) {
+        assert_eq!(keys.len(), values.len(), "keys and values have different lengths");
+        for (keys, values) in keys.chunks(LANES).zip(values.chunks_mut(LANES)) {
+            self.get_lanes(keys, values);
+        }
+    }
+
+    // Looks up at most `LANES` keys, descending one level for every key in turn.
+    fn get_lanes<'a>(&'a self, keys: &[usize], values: &mut [Option<&'a T>]) {
+        // The node each walk has reached, or `None` once it has finished. All walks are at the
+        // same depth, `idx`.
+        let mut nodes: [Option<&InternalNode<T>>; LANES] = [None; LANES];
+        for node in nodes[..keys.len()].iter_mut() {
+            *node = Some(&self.root);
+        }
+        let mut active = keys.len();
+        let mut idx = 0;
+        while active > 0 {
+            for (lane, &key) in keys.iter().enumerate() {
+                let node = match nodes[lane] {
+                    Some(node) => node,
+                    None => continue,
+                };
+                match node.children[chunk(key, idx)] {
+                    Internal(id) => {
+                        let next = &self.nodes[id];
+                        prefetch(&next.children[chunk(key, idx + 1)]);
+                        nodes[lane] = Some(next);
+                        continue;
+                    }
+                    External(stored, ref value) if stored == key => values[lane] = Some(value),
+                    _ => values[lane] = None,
+                }
+                nodes[lane] = None;
+                active -= 1;
+            }
+            idx += 1;
+        }
+    }
+
+    /// Returns mutable references to the values of several keys at once, in the same order as
+    /// `keys`. Returns `None` if any key is not in the map or appears more than once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut balances: trie::Map<_> = vec![(1, 100), (2, 50)].into_iter().collect();
+    ///
+    /// if let Some([from, to]) = balances.get_many_mut([1, 2]) {
+    ///     *from -= 30;
+    ///     *to += 30;
+    /// }
+    /// assert_eq!(balances[&1], 70);
+    /// assert_eq!(balances[&2], 80);
+    ///
+    /// assert!(balances.get_many_mut([1, 3]).is_none());
+    /// assert!(balances.get_many_mut([1, 1]).is_none());
+    /// ```
+    pub fn get_many_mut<const N: usize>(&mut self, keys: [usize; N]) -> Option<[&mut T; N]> {
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return None;
+            }
+        }
+        let root: *mut InternalNode<T> = &mut self.root;
+        let nodes = self.nodes.as_mut_ptr();
+        let mut values = [ptr::null_mut(); N];
+        for (value, &key) in values.iter_mut().zip(keys.iter()) {
+            *value = unsafe { find_mut(root, nodes, key)? };
+        }
+        // Distinct keys are stored in distinct external nodes, so the references do not alias.
+        Some(values.map(|value| unsafe { &mut *value }))
+    }
+}
+
+// Finds the value of `key` without creating references to any nodes, so that the values found
+// by earlier calls stay valid.
+//
+// Safety: `root` and `nodes` must belong to the same map, which must be borrowed mutably.
+unsafe fn find_mut<T>(root: *mut InternalNode<T>, nodes: *mut InternalNode<T>, key: usize)
+                      -> Option<*mut T> {
+    let mut node = root;
+    let mut idx = 0;
+    loop {
+        match (*node).children[chunk(key, idx)] {
+            Internal(id) => node = nodes.add(id as usize),
+            External(stored, ref mut value) if stored == key => return Some(value as *mut T),
+            _ => return None,
+        }
+        idx += 1;
+    }
+}
+
+// Asks for the cache line holding `p` to be loaded, without waiting for it.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn prefetch<T>(p: *const T) {
+    use core::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+    // Prefetching never faults, whatever the address.
+    unsafe { _mm_prefetch::<{ _MM_HINT_T0 }>(p as *const i8) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+fn prefetch<T>(_: *const T) {}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rand::{weak_rng, Rng};
+
+    use crate::Map;
+    use crate::map::USIZE_BITS;
+
+    #[test]
+    fn test_get_many() {
+        let mut rng = weak_rng();
+        let mut map = Map::new();
+        for _ in 0..1000 {
+            let key: usize = rng.gen();
+            map.insert(key, !key);
+        }
+        // Small keys sit at the bottom of a long chain.
+        for key in 0..100 {
+            map.insert(key, !key);
+        }
+
+        let mut keys: Vec<usize> = map.keys().step_by(2).collect();
+        keys.extend((0..200).map(|_| rng.gen::<usize>()));
+        keys.extend(95..105);
+        keys.extend([0, !0, 1 << (USIZE_BITS - 4)].iter().cloned());
+        rng.shuffle(&mut keys);
+
+        let expected: Vec<Option<&usize>> = keys.iter().map(|key| map.get(key)).collect();
+        assert_eq!(map.get_many(&keys), expected);
+
+        let mut values = vec![Some(&0); keys.len()];
+        map.get_batch(&keys, &mut values);
+        assert_eq!(values, expected);
+
+        assert_eq!(map.get_many(&[]), []);
+        assert_eq!(Map::<usize>::new().get_many(&[0, 1, 2]), [None, None, None]);
+    }
+
+    #[test]
+    fn test_get_many_mut() {
+        let mut map: Map<usize> = (0..100).map(|key| (key * 7, key)).collect();
+        map.insert(!0, 0);
+
+        let [a, b, c] = map.get_many_mut([7, !0, 0]).unwrap();
+        std::mem::swap(a, b);
+        *c += 10;
+        assert_eq!((map[&7], map[&!0], map[&0]), (0, 1, 10));
+
+        assert!(map.get_many_mut([7, 8]).is_none());
+        assert!(map.get_many_mut([!0, 14, !0]).is_none());
+        assert_eq!(map.get_many_mut([]), Some([]));
+
+        // Every value of the map at once.
+        let keys: [usize; 100] = core::array::from_fn(|i| i * 7);
+        for value in map.get_many_mut(keys).unwrap() {
+            *value += 1;
+        }
+        assert_eq!(map[&693], 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "different lengths")]
+    fn test_get_batch_lengths() {
+        let map: Map<usize> = Map::new();
+        map.get_batch(&[1, 2], &mut [None]);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use std::vec::Vec;
+
+    use rand::{weak_rng, Rng};
+    use test::{Bencher, black_box};
+
+    use crate::Map;
+
+    const MAP_SIZE: usize = 1_000_000;
+    const BATCH: usize = 100_000;
+
+    fn setup() -> (Map<usize>, Vec<usize>) {
+        let mut rng = weak_rng();
+        let keys: Vec<usize> = (0..MAP_SIZE).map(|_| rng.gen()).collect();
+        let map = keys.iter().map(|&key| (key, key)).collect();
+        let lookups = (0..BATCH).map(|_| keys[rng.gen::<usize>() % MAP_SIZE]).collect();
+        (map, lookups)
+    }
+
+    #[bench]
+    fn get_each(b: &mut Bencher) {
+        let (map, keys) = setup();
+        b.iter(|| {
+            for key in keys.iter() {
+                black_box(map.get(key));
+            }
+        });
+    }
+
+    #[bench]
+    fn get_batch(b: &mut Bencher) {
+        let (map, keys) = setup();
+        let mut values = vec![None; BATCH];
+        b.iter(|| {
+            map.get_batch(&keys, &mut values);
+            black_box(&values);
+        });
+    }
+}
@@ -0,0 +1,413 @@
+//! A succinct, immutable representation of a `Set`.
+//!
+//! `Set::to_compact` encodes a set with the Elias–Fano scheme. Each value is split into its low
+//! `l` bits, where `l` is about the logarithm of the average gap between values, and its remaining
+//! high bits. The low bits are packed one after another, and the high bits are stored in unary,
+//! as a bit vector in which the `i`th value sets bit `i + (value >> l)`. This takes a little over
+//! `2 + l` bits per value, however the values are spread out.
+//!
+//! Positions of every 256th set and clear bit in the high bit vector are sampled, so that `select`
+//! and `rank` only need to scan a few words of it.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+
+use super::Set;
+use super::map::USIZE_BITS;
+
+// Every `SAMPLE`th set bit and clear bit of the high bits has its position recorded.
+const SAMPLE: usize = 256;
+
+/// An immutable set of `usize` stored in close to the minimum space, created by
+/// `Set::to_compact`.
+///
+/// # Examples
+///
+/// ```
+/// let set: trie::Set = (0..1000).map(|i| i * 37).collect();
+/// let compact = set.to_compact();
+///
+/// assert_eq!(compact.len(), 1000);
+/// assert!(compact.contains(&370));
+/// assert!(!compact.contains(&371));
+/// assert_eq!(compact.rank(371), 11);
+/// assert_eq!(compact.select(11), Some(407));
+/// assert_eq!(compact.lower_bound(371).next(), Some(407));
+/// assert_eq!(compact.to_set(), set);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct CompactSet {
+    len: usize,
+    // The number of low bits of each value kept in `low`.
+    low_bits: usize,
+    // One more than the largest high part, and so the number of clear bits in `high`.
+    buckets: usize,
+    low: Vec<u64>,
+    high: Vec<u64>,
+    // `ones[k]` is the position of the `k * SAMPLE`th set bit in `high`, and `zeros[k]` that of
+    // the `k * SAMPLE`th clear bit.
+    ones: Vec<usize>,
+    zeros: Vec<usize>,
+}
+
+impl Set {
+    /// Returns a compact, read-only copy of the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..100_000).filter(|i| i % 3 == 0).collect();
+    /// let compact = set.to_compact();
+    /// assert!(compact.iter().eq(set.iter()));
+    /// ```
+    pub fn to_compact(&self) -> CompactSet {
+        let len = self.len();
+        let mut max = 0;
+        self.each_reverse(|&value| { max = value; false });
+        let low_bits = match max.checked_div(len) {
+            Some(gap) if gap > 0 => USIZE_BITS - 1 - gap.leading_zeros() as usize,
+            _ => 0,
+        };
+        let buckets = if len == 0 { 0 } else { (max >> low_bits) + 1 };
+
+        let mut low = vec![0; words(len * low_bits)];
+        let mut high = vec![0; words(len + buckets)];
+        for (i, value) in self.iter().enumerate() {
+            if low_bits > 0 {
+                set_bits(&mut low, i * low_bits, low_bits, value as u64);
+            }
+            let pos = (value >> low_bits) + i;
+            high[pos / 64] |= 1 << (pos % 64);
+        }
+        let ones = samples(&high, false, len);
+        let zeros = samples(&high, true, buckets);
+        CompactSet { len, low_bits, buckets, low, high, ones, zeros }
+    }
+}
+
+impl CompactSet {
+    /// Returns the number of values in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the set contains a value.
+    pub fn contains(&self, value: &usize) -> bool {
+        self.select(self.rank(*value)) == Some(*value)
+    }
+
+    /// Returns the number of values in the set that are less than `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let compact = [2, 4, 6].iter().cloned().collect::<trie::Set>().to_compact();
+    /// assert_eq!(compact.rank(0), 0);
+    /// assert_eq!(compact.rank(4), 1);
+    /// assert_eq!(compact.rank(5), 2);
+    /// assert_eq!(compact.rank(100), 3);
+    /// ```
+    pub fn rank(&self, value: usize) -> usize {
+        let bucket = value >> self.low_bits;
+        if bucket >= self.buckets {
+            return self.len;
+        }
+        // Each value in an earlier bucket has a set bit before the start of this one, and each
+        // earlier bucket ends with a clear bit.
+        let mut pos = match bucket {
+            0 => 0,
+            _ => select(&self.high, &self.zeros, true, bucket - 1) + 1,
+        };
+        let mut rank = pos - bucket;
+        let low = self.low_part(value);
+        while rank < self.len && bit(&self.high, pos) && self.low(rank) < low {
+            rank += 1;
+            pos += 1;
+        }
+        rank
+    }
+
+    /// Returns the value at position `index` in ascending order, or `None` if `index` is not
+    /// less than the length of the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let compact = [2, 4, 6].iter().cloned().collect::<trie::Set>().to_compact();
+    /// assert_eq!(compact.select(0), Some(2));
+    /// assert_eq!(compact.select(2), Some(6));
+    /// assert_eq!(compact.select(3), None);
+    /// ```
+    pub fn select(&self, index: usize) -> Option<usize> {
+        if index >= self.len {
+            return None;
+        }
+        let pos = select(&self.high, &self.ones, false, index);
+        Some(self.value(index, pos))
+    }
+
+    /// Gets an iterator over the values in the set, in sorted order.
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Gets an iterator pointing to the first value that is not less than `val`.
+    pub fn lower_bound(&self, val: usize) -> Iter<'_> {
+        self.iter_from(self.rank(val))
+    }
+
+    /// Gets an iterator pointing to the first value that is greater than `val`.
+    pub fn upper_bound(&self, val: usize) -> Iter<'_> {
+        if val == usize::MAX {
+            self.iter_from(self.len)
+        } else {
+            self.iter_from(self.rank(val + 1))
+        }
+    }
+
+    /// Converts the set back into a `Set`.
+    pub fn to_set(&self) -> Set {
+        self.iter().collect()
+    }
+
+    /// Returns the number of bytes of heap memory used by the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..100_000).map(|i| i * 1000).collect();
+    /// assert!(set.to_compact().heap_size() < 100_000 * 2);
+    /// ```
+    pub fn heap_size(&self) -> usize {
+        8 * (self.low.len() + self.high.len() + self.ones.len() + self.zeros.len())
+    }
+
+    fn iter_from(&self, index: usize) -> Iter<'_> {
+        let pos = if index < self.len { select(&self.high, &self.ones, false, index) } else { 0 };
+        Iter { set: self, front: index, back: self.len, pos }
+    }
+
+    // Returns the value at `index`, whose set bit in `high` is at `pos`.
+    fn value(&self, index: usize, pos: usize) -> usize {
+        (pos - index) << self.low_bits | self.low(index)
+    }
+
+    fn low(&self, index: usize) -> usize {
+        if self.low_bits == 0 {
+            0
+        } else {
+            get_bits(&self.low, index * self.low_bits, self.low_bits) as usize
+        }
+    }
+
+    fn low_part(&self, value: usize) -> usize {
+        value & ((1 << self.low_bits) - 1)
+    }
+}
+
+impl Debug for CompactSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<'a> IntoIterator for &'a CompactSet {
+    type Item = usize;
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Iter<'a> { self.iter() }
+}
+
+impl<'a> From<&'a Set> for CompactSet {
+    fn from(set: &'a Set) -> CompactSet {
+        set.to_compact()
+    }
+}
+
+/// An iterator over the values of a `CompactSet`.
+#[derive(Clone)]
+pub struct Iter<'a> {
+    set: &'a CompactSet,
+    front: usize,
+    back: usize,
+    // The position in `high` to search for the next set bit from.
+    pos: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.front == self.back {
+            return None;
+        }
+        let high = &self.set.high;
+        let mut word = self.pos / 64;
+        let mut bits = high[word] & (!0 << (self.pos % 64));
+        while bits == 0 {
+            word += 1;
+            bits = high[word];
+        }
+        let pos = word * 64 + bits.trailing_zeros() as usize;
+        let value = self.set.value(self.front, pos);
+        self.front += 1;
+        self.pos = pos + 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.set.select(self.back)
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+fn words(bits: usize) -> usize {
+    bits.div_ceil(64)
+}
+
+fn bit(bits: &[u64], pos: usize) -> bool {
+    bits.get(pos / 64).is_some_and(|word| word >> (pos % 64) & 1 == 1)
+}
+
+// Stores the low `width` bits of `value` at bit `start`, which must be clear.
+fn set_bits(bits: &mut [u64], start: usize, width: usize, value: u64) {
+    let value = value & (!0 >> (64 - width));
+    let (word, shift) = (start / 64, start % 64);
+    bits[word] |= value << shift;
+    if shift + width > 64 {
+        bits[word + 1] |= value >> (64 - shift);
+    }
+}
+
+fn get_bits(bits: &[u64], start: usize, width: usize) -> u64 {
+    let (word, shift) = (start / 64, start % 64);
+    let mut value = bits[word] >> shift;
+    if shift + width > 64 {
+        value |= bits[word + 1] << (64 - shift);
+    }
+    value & (!0 >> (64 - width))
+}
+
+// Records the position of every `SAMPLE`th set bit of `bits`, or clear bit if `zeros` is true,
+// among the first `count` of them.
+fn samples(bits: &[u64], zeros: bool, count: usize) -> Vec<usize> {
+    let mut samples = Vec::with_capacity(words(count) / (SAMPLE / 64) + 1);
+    let mut seen = 0;
+    for (i, &word) in bits.iter().enumerate() {
+        let word = if zeros { !word } else { word };
+        let found = word.count_ones() as usize;
+        let mut next = samples.len() * SAMPLE;
+        while next < count && next < seen + found {
+            samples.push(i * 64 + select_in_word(word, next - seen));
+            next += SAMPLE;
+        }
+        seen += found;
+    }
+    samples
+}
+
+// Returns the position of the `k`th set bit of `bits`, or clear bit if `zeros` is true, which
+// must exist.
+fn select(bits: &[u64], samples: &[usize], zeros: bool, k: usize) -> usize {
+    let start = samples[k / SAMPLE];
+    let mut rest = k % SAMPLE;
+    let mut i = start / 64;
+    let load = |i: usize| if zeros { !bits[i] } else { bits[i] };
+    let mut word = load(i) & (!0 << (start % 64));
+    loop {
+        let found = word.count_ones() as usize;
+        if rest < found {
+            return i * 64 + select_in_word(word, rest);
+        }
+        rest -= found;
+        i += 1;
+        word = load(i);
+    }
+}
+
+// Returns the position of the `k`th set bit of `word`.
+fn select_in_word(mut word: u64, k: usize) -> usize {
+    for _ in 0..k {
+        word &= word - 1;
+    }
+    word.trailing_zeros() as usize
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rand::{Rng, weak_rng};
+
+    use super::super::Set;
+
+    fn check(set: &Set) {
+        let compact = set.to_compact();
+        let values: Vec<usize> = set.iter().collect();
+        assert_eq!(compact.len(), values.len());
+        assert!(compact.iter().eq(values.iter().cloned()));
+        assert!(compact.iter().rev().eq(values.iter().rev().cloned()));
+        assert_eq!(compact.to_set(), *set);
+        assert_eq!(compact.select(values.len()), None);
+
+        let mut probes = vec![0, 1, 63, 64, usize::MAX - 1, usize::MAX];
+        for &value in values.iter().step_by(7) {
+            probes.extend(&[value.wrapping_sub(1), value, value.wrapping_add(1)]);
+        }
+        for &probe in &probes {
+            let rank = values.binary_search(&probe).unwrap_or_else(|i| i);
+            assert_eq!(compact.rank(probe), rank);
+            assert_eq!(compact.contains(&probe), set.contains(&probe));
+            assert_eq!(compact.select(rank), values.get(rank).cloned());
+            assert!(compact.lower_bound(probe).take(5).eq(set.lower_bound(probe).take(5)));
+            assert!(compact.upper_bound(probe).take(5).eq(set.upper_bound(probe).take(5)));
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        check(&Set::new());
+        check(&[0].iter().cloned().collect());
+        check(&[usize::MAX].iter().cloned().collect());
+        check(&[0, usize::MAX].iter().cloned().collect());
+        check(&(0..5000).collect());
+        check(&(0..5000).map(|i| i * 1_000_003).collect());
+        check(&(usize::MAX - 1000..=usize::MAX).collect());
+
+        let mut rng = weak_rng();
+        let mut set = Set::new();
+        for _ in 0..20 {
+            let start = rng.gen::<usize>() >> rng.gen_range(0, 64);
+            set.insert_range(start..start.saturating_add(rng.gen_range(1, 300)));
+        }
+        for _ in 0..2000 {
+            set.insert(rng.gen_range(0, 1 << 20));
+        }
+        check(&set);
+    }
+
+    #[test]
+    fn test_size() {
+        // Sparse values take a whole word each in a trie.
+        let set: Set = (0..200_000).map(|i| i * 4096 + (i * 7) % 4096).collect();
+        let compact = set.to_compact();
+        assert!(compact.heap_size() < 200_000 * 15 / 8);
+        assert_eq!(compact.select(123_456), Some(123_456 * 4096 + 123_456 * 7 % 4096));
+    }
+}
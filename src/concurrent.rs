@@ -0,0 +1,464 @@
+//! A map that many threads can read and write at once, based on a trie.
+//!
+//! `ConcurrentMap` has the same layout as `Map`: each internal node has sixteen children indexed
+//! by successive 4-bit chunks of the key. Each child slot is an atomic pointer. Readers walk
+//! down the trie without taking any locks. Each writer publishes its change with a single
+//! compare-and-swap on the slot it affects, and starts again from that slot if another writer
+//! changed it first.
+//!
+//! A value that is replaced or removed may still be in use by a reader on another thread, so it
+//! is freed later. crossbeam's epoch-based reclamation frees it once every thread that was pinned
+//! when it was removed has unpinned. References returned by the map borrow the `Guard` that
+//! pins the current thread, which keeps them valid.
+//!
+//! Internal nodes are never removed. A map that shrinks keeps the nodes it needed at its largest
+//! until it is dropped.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Debug};
+use core::iter;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use crossbeam_epoch::{self as epoch, Atomic, Owned, Shared};
+
+pub use crossbeam_epoch::{pin, Guard};
+
+use super::map::{chunk, MAX_DEPTH, SIZE};
+
+/// A map from `usize` keys to values that can be shared between threads, where lookups never
+/// block and never wait for writers.
+///
+/// Every method takes `&self`, so the map can be shared with an `Arc` or a scoped thread
+/// without a lock. Methods that return references take a `Guard` from `pin`. Values that are
+/// replaced or removed are not dropped while any guard that could have seen them is alive.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use trie::concurrent::{pin, ConcurrentMap};
+///
+/// let map = ConcurrentMap::new();
+/// thread::scope(|s| {
+///     for t in 0..4 {
+///         let map = &map;
+///         s.spawn(move || {
+///             for key in (t * 100)..(t + 1) * 100 {
+///                 map.insert(key, key * 2, &pin());
+///             }
+///         });
+///     }
+/// });
+///
+/// let guard = pin();
+/// assert_eq!(map.len(), 400);
+/// assert_eq!(map.get(&150, &guard), Some(&300));
+/// assert_eq!(map.remove(&150, &guard), Some(&300));
+/// assert_eq!(map.get(&150, &guard), None);
+/// assert!(map.iter(&guard).map(|(key, _)| key).eq((0..400).filter(|&key| key != 150)));
+/// ```
+pub struct ConcurrentMap<T> {
+    root: Node<T>,
+    length: AtomicUsize,
+}
+
+struct Node<T> {
+    children: [Atomic<Child<T>>; SIZE],
+}
+
+enum Child<T> {
+    Internal(Node<T>),
+    External(usize, T),
+}
+
+impl<T> Node<T> {
+    fn new() -> Node<T> {
+        Node { children: Default::default() }
+    }
+}
+
+impl<T> Drop for Node<T> {
+    fn drop(&mut self) {
+        for child in self.children.iter() {
+            // Safety: a node is dropped either with the map, which no reader can be borrowing,
+            // or before it was ever published.
+            unsafe {
+                let child = child.load(Relaxed, epoch::unprotected());
+                if !child.is_null() {
+                    drop(child.into_owned());
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for ConcurrentMap<T> {
+    #[inline]
+    fn default() -> ConcurrentMap<T> { ConcurrentMap::new() }
+}
+
+impl<T: Debug> Debug for ConcurrentMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let guard = pin();
+        f.debug_map().entries(self.iter(&guard)).finish()
+    }
+}
+
+impl<T> ConcurrentMap<T> {
+    /// Creates an empty map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::ConcurrentMap<&str> = trie::ConcurrentMap::new();
+    /// assert!(map.is_empty());
+    /// ```
+    #[inline]
+    pub fn new() -> ConcurrentMap<T> {
+        ConcurrentMap { root: Node::new(), length: AtomicUsize::new(0) }
+    }
+
+    /// Returns the number of elements in the map.
+    ///
+    /// While other threads are inserting or removing keys, the result may already be out of
+    /// date when it is returned.
+    #[inline]
+    pub fn len(&self) -> usize { self.length.load(Relaxed) }
+
+    /// Returns true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::concurrent::{pin, ConcurrentMap};
+    ///
+    /// let map = ConcurrentMap::new();
+    /// let guard = pin();
+    /// map.insert(1, "a", &guard);
+    /// assert_eq!(map.get(&1, &guard), Some(&"a"));
+    /// assert_eq!(map.get(&2, &guard), None);
+    /// ```
+    pub fn get<'g>(&'g self, key: &usize, guard: &'g Guard) -> Option<&'g T> {
+        let key = *key;
+        let mut node = &self.root;
+        for idx in 0..MAX_DEPTH {
+            let child = node.children[chunk(key, idx)].load(Acquire, guard);
+            match unsafe { child.as_ref() } {
+                Some(Child::Internal(next)) => node = next,
+                Some(&Child::External(stored, ref value)) if stored == key => return Some(value),
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::concurrent::{pin, ConcurrentMap};
+    ///
+    /// let map = ConcurrentMap::new();
+    /// map.insert(1, "a", &pin());
+    /// assert!(map.contains_key(&1));
+    /// assert!(!map.contains_key(&2));
+    /// ```
+    #[inline]
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.get(key, &pin()).is_some()
+    }
+
+    /// Gets an iterator over the entries of the map, sorted by key.
+    ///
+    /// The iterator is weakly consistent: it never yields a key twice and always yields keys in
+    /// order, and it yields every key that is in the map for as long as the iteration takes.
+    /// Keys that are inserted or removed during the iteration may or may not be yielded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::concurrent::{pin, ConcurrentMap};
+    ///
+    /// let map = ConcurrentMap::new();
+    /// let guard = pin();
+    /// map.insert(3, "c", &guard);
+    /// map.insert(1, "a", &guard);
+    /// map.insert(2, "b", &guard);
+    ///
+    /// let entries: Vec<_> = map.iter(&guard).collect();
+    /// assert_eq!(entries, [(1, &"a"), (2, &"b"), (3, &"c")]);
+    /// ```
+    pub fn iter<'g>(&'g self, guard: &'g Guard) -> Iter<'g, T> {
+        let mut stack = Vec::with_capacity(MAX_DEPTH);
+        stack.push((&self.root, 0));
+        Iter { stack, guard }
+    }
+}
+
+impl<T: Send + 'static> ConcurrentMap<T> {
+    /// Inserts a key-value pair into the map. If the key already had a value present in the
+    /// map, that value is returned. Otherwise, `None` is returned.
+    ///
+    /// The old value stays readable through the returned reference until `guard` is dropped.
+    /// It is dropped some time after that.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::concurrent::{pin, ConcurrentMap};
+    ///
+    /// let map = ConcurrentMap::new();
+    /// let guard = pin();
+    /// assert_eq!(map.insert(37, "a", &guard), None);
+    /// assert_eq!(map.insert(37, "b", &guard), Some(&"a"));
+    /// assert_eq!(map.get(&37, &guard), Some(&"b"));
+    /// ```
+    pub fn insert<'g>(&'g self, key: usize, value: T, guard: &'g Guard) -> Option<&'g T> {
+        let mut new = Owned::new(Child::External(key, value));
+        let mut node = &self.root;
+        let mut idx = 0;
+        loop {
+            let slot = &node.children[chunk(key, idx)];
+            let current = slot.load(Acquire, guard);
+            match unsafe { current.as_ref() } {
+                Some(Child::Internal(next)) => {
+                    node = next;
+                    idx += 1;
+                }
+                None => match slot.compare_exchange(current, new, AcqRel, Acquire, guard) {
+                    Ok(_) => {
+                        self.length.fetch_add(1, Relaxed);
+                        return None;
+                    }
+                    Err(err) => new = err.new,
+                },
+                Some(&Child::External(stored, ref old)) if stored == key => {
+                    match slot.compare_exchange(current, new, AcqRel, Acquire, guard) {
+                        Ok(_) => {
+                            unsafe { guard.defer_destroy(current) };
+                            return Some(old);
+                        }
+                        Err(err) => new = err.new,
+                    }
+                }
+                Some(&Child::External(stored, _)) => {
+                    // Move the other key down into a new node, then try again from there. The
+                    // old and new nodes share the key's allocation until the swap succeeds.
+                    let split = Node::new();
+                    split.children[chunk(stored, idx + 1)].store(current, Relaxed);
+                    let split = Owned::new(Child::Internal(split));
+                    let swapped = slot.compare_exchange(current, split, AcqRel, Acquire, guard);
+                    if let Err(err) = swapped {
+                        if let Child::Internal(ref split) = *err.new {
+                            split.children[chunk(stored, idx + 1)].store(Shared::null(), Relaxed);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in
+    /// the map.
+    ///
+    /// The value stays readable through the returned reference until `guard` is dropped. It is
+    /// dropped some time after that.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::concurrent::{pin, ConcurrentMap};
+    ///
+    /// let map = ConcurrentMap::new();
+    /// let guard = pin();
+    /// map.insert(1, "a", &guard);
+    /// assert_eq!(map.remove(&1, &guard), Some(&"a"));
+    /// assert_eq!(map.remove(&1, &guard), None);
+    /// ```
+    pub fn remove<'g>(&'g self, key: &usize, guard: &'g Guard) -> Option<&'g T> {
+        let key = *key;
+        let mut node = &self.root;
+        let mut idx = 0;
+        loop {
+            let slot = &node.children[chunk(key, idx)];
+            let current = slot.load(Acquire, guard);
+            match unsafe { current.as_ref() } {
+                Some(Child::Internal(next)) => {
+                    node = next;
+                    idx += 1;
+                }
+                Some(&Child::External(stored, ref value)) if stored == key => {
+                    let removed = slot.compare_exchange(current, Shared::null(), AcqRel, Acquire,
+                                                        guard);
+                    if removed.is_ok() {
+                        self.length.fetch_sub(1, Relaxed);
+                        unsafe { guard.defer_destroy(current) };
+                        return Some(value);
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> iter::FromIterator<(usize, T)> for ConcurrentMap<T> {
+    fn from_iter<I: IntoIterator<Item=(usize, T)>>(iter: I) -> ConcurrentMap<T> {
+        let map = ConcurrentMap::new();
+        let guard = pin();
+        for (key, value) in iter {
+            map.insert(key, value, &guard);
+        }
+        map
+    }
+}
+
+/// A weakly consistent iterator over the entries of a concurrent map, sorted by key.
+pub struct Iter<'g, T: 'g> {
+    // The nodes being visited, with the next slot to look at in each.
+    stack: Vec<(&'g Node<T>, usize)>,
+    guard: &'g Guard,
+}
+
+impl<'g, T> Iterator for Iter<'g, T> {
+    type Item = (usize, &'g T);
+
+    fn next(&mut self) -> Option<(usize, &'g T)> {
+        while let Some(&mut (node, ref mut slot)) = self.stack.last_mut() {
+            if *slot == SIZE {
+                self.stack.pop();
+                continue;
+            }
+            let child = node.children[*slot].load(Acquire, self.guard);
+            *slot += 1;
+            match unsafe { child.as_ref() } {
+                Some(Child::Internal(next)) => self.stack.push((next, 0)),
+                Some(&Child::External(key, ref value)) => return Some((key, value)),
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::{pin, ConcurrentMap};
+    use crate::map::USIZE_BITS;
+
+    #[test]
+    fn test_basic() {
+        let map = ConcurrentMap::new();
+        let guard = pin();
+        // Keys that share all but their last chunk, and keys that differ in their first.
+        let keys = [0, 1, 0x10, 1 << (USIZE_BITS - 4), !0, !0 - 1];
+        for &key in keys.iter() {
+            assert_eq!(map.insert(key, key, &guard), None);
+        }
+        assert_eq!(map.len(), keys.len());
+        for &key in keys.iter() {
+            assert_eq!(map.get(&key, &guard), Some(&key));
+        }
+        assert_eq!(map.get(&2, &guard), None);
+
+        let mut sorted = keys.to_vec();
+        sorted.sort();
+        assert_eq!(map.iter(&guard).map(|(key, _)| key).collect::<Vec<_>>(), sorted);
+
+        assert_eq!(map.insert(1, 100, &guard), Some(&1));
+        assert_eq!(map.remove(&0x10, &guard), Some(&0x10));
+        assert_eq!(map.remove(&0x10, &guard), None);
+        assert_eq!(map.remove(&2, &guard), None);
+        assert_eq!(map.len(), keys.len() - 1);
+        assert_eq!(format!("{:?}", map.get(&1, &guard)), "Some(100)");
+    }
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let map = ConcurrentMap::new();
+        {
+            let guard = pin();
+            for key in 0..100 {
+                map.insert(key, Counted(drops.clone()), &guard);
+            }
+            for key in 0..10 {
+                map.insert(key, Counted(drops.clone()), &guard);
+                map.remove(&(key + 50), &guard);
+            }
+        }
+        drop(map);
+        // Replaced and removed values are dropped once no thread is pinned.
+        for _ in 0..1000 {
+            if drops.load(Ordering::Relaxed) == 110 { break }
+            pin().flush();
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 110);
+    }
+
+    #[test]
+    fn test_threads() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 2000;
+
+        let map = ConcurrentMap::new();
+        let done = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (map, done) = (&map, &done);
+                s.spawn(move || {
+                    // Interleave the threads' keys so that they split each other's nodes.
+                    for i in 0..KEYS {
+                        let key = (i * THREADS + t).wrapping_mul(0x9e37_79b9);
+                        map.insert(key, key, &pin());
+                        if i % 3 == 0 {
+                            map.remove(&key, &pin());
+                        }
+                    }
+                    done.fetch_add(1, Ordering::Release);
+                });
+            }
+            s.spawn(|| {
+                while done.load(Ordering::Acquire) < THREADS {
+                    let guard = pin();
+                    let mut last = None;
+                    for (key, &value) in map.iter(&guard) {
+                        assert_eq!(key, value);
+                        assert!(last < Some(key));
+                        last = Some(key);
+                    }
+                }
+            });
+        });
+
+        let guard = pin();
+        let mut expected = Vec::new();
+        for t in 0..THREADS {
+            for i in (0..KEYS).filter(|i| i % 3 != 0) {
+                expected.push((i * THREADS + t).wrapping_mul(0x9e37_79b9));
+            }
+        }
+        expected.sort();
+        assert_eq!(map.len(), expected.len());
+        assert_eq!(map.iter(&guard).map(|(key, _)| key).collect::<Vec<_>>(), expected);
+    }
+}
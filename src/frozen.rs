@@ -0,0 +1,701 @@
+//! An immutable map laid out in a single contiguous buffer.
+//!
+//! `Map::freeze` copies a map into a `FrozenMap`, whose nodes and values refer to each other by
+//! byte offsets within one buffer. The buffer can be written to a file as it is
+//! and opened again with `FrozenMap::new`, which reads it in place: opening checks the nodes and
+//! leaves but copies nothing and never looks at the values, and lookups touch just the parts of
+//! the buffer they need, so a memory-mapped file can be shared by many processes without being
+//! deserialized.
+//!
+//! Values are stored as byte strings. Maps whose values are not already bytes can be converted
+//! first, for example with `to_le_bytes`.
+//!
+//! # Layout
+//!
+//! All integers are little-endian and nothing in the buffer needs to be aligned.
+//!
+//! | Offset | Contents                                                                |
+//! |--------|-------------------------------------------------------------------------|
+//! | 0      | the four bytes `TRIF`                                                   |
+//! | 4      | the format version, one byte, currently `1`                             |
+//! | 5      | the key width in bits, one byte                                         |
+//! | 6      | two zero bytes                                                          |
+//! | 8      | the number of entries, `u64`                                            |
+//! | 16     | the offset of the leaves, `u64`                                         |
+//! | 24     | the offset of the values, `u64`                                         |
+//! | 32     | the length of the buffer, `u64`                                         |
+//! | 40     | the offset of the root node, `u64`                                      |
+//!
+//! The nodes follow the header. Each starts with a `u16` bitmap of its non-empty children, a
+//! `u16` bitmap of those that are nodes rather than leaves, four zero bytes, and two `u64`s
+//! giving the range of leaf indices found below it. One `u64` follows for each non-empty child in
+//! order: the offset of a child node, or the index of a leaf.
+//!
+//! The leaves are sixteen bytes each, in key order: the key as a `u64`, then the offset just past
+//! the end of its value within the values. Each value starts where the previous one ended, and
+//! the values run to the end of the buffer.
+//!
+//! # Examples
+//!
+//! ```
+//! use trie::frozen::FrozenMap;
+//!
+//! let map: trie::Map<&str> = [(1, "one"), (20, "twenty"), (300, "three hundred")]
+//!     .iter().cloned().collect();
+//! let frozen = map.freeze();
+//!
+//! // The buffer could equally have been read from a file or mapped into memory.
+//! let bytes: &[u8] = frozen.as_bytes();
+//! let opened = FrozenMap::new(bytes).unwrap();
+//! assert_eq!(opened.len(), 3);
+//! assert_eq!(opened.get(&20), Some(&b"twenty"[..]));
+//! assert_eq!(opened.lower_bound(21).next(), Some((300, &b"three hundred"[..])));
+//! ```
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Debug};
+use std::vec::Vec;
+use std::ops::{Bound, RangeBounds};
+
+use super::map::{Allocator, Arena, InternalNode, Map, MAX_DEPTH, SIZE, USIZE_BITS, chunk,
+                 slot_bounds};
+use super::map::TrieNode::*;
+use super::snapshot::SnapshotError;
+
+const MAGIC: [u8; 4] = *b"TRIF";
+const VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 48;
+const NODE_LEN: usize = 24;
+const LEAF_LEN: usize = 16;
+
+/// An immutable map stored in a single buffer of bytes.
+///
+/// See the [module documentation](index.html) for the layout of the buffer. `B` is the type of
+/// the buffer: `Map::freeze` returns a map that owns a `Vec<u8>`, while `FrozenMap::new` can
+/// borrow a slice or take any other type that derefs to bytes, such as a memory map.
+#[derive(Clone)]
+pub struct FrozenMap<B> {
+    bytes: B,
+    len: usize,
+    leaves: usize,
+    values: usize,
+    root: usize,
+}
+
+impl<T: AsRef<[u8]>, A: Allocator + Clone> Map<T, A> {
+    /// Copies the map into a `FrozenMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<[u8; 4]> =
+    ///     (0..100u32).map(|i| (i as usize, i.to_le_bytes())).collect();
+    /// let frozen = map.freeze();
+    ///
+    /// let value = frozen.get(&42).unwrap();
+    /// assert_eq!(u32::from_le_bytes([value[0], value[1], value[2], value[3]]), 42);
+    /// ```
+    pub fn freeze(&self) -> FrozenMap<Vec<u8>> {
+        let mut bytes = vec![0; HEADER_LEN];
+        let mut leaf = 0;
+        let root = freeze_node(&self.nodes, &self.root, &mut bytes, &mut leaf);
+
+        let leaves = bytes.len();
+        let mut end = 0;
+        for (key, value) in self.iter() {
+            end += value.as_ref().len();
+            bytes.extend_from_slice(&(key as u64).to_le_bytes());
+            bytes.extend_from_slice(&(end as u64).to_le_bytes());
+        }
+        let values = bytes.len();
+        for value in self.values() {
+            bytes.extend_from_slice(value.as_ref());
+        }
+        let len = bytes.len();
+
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = USIZE_BITS as u8;
+        for (i, &field) in [self.len(), leaves, values, len, root].iter().enumerate() {
+            put_u64(&mut bytes, 8 + 8 * i, field);
+        }
+        FrozenMap { bytes, len: self.len(), leaves, values, root }
+    }
+}
+
+// Appends `node` and the nodes below it to `bytes`, numbering its leaves from `leaf`, and returns
+// its offset.
+fn freeze_node<T, A: Allocator + Clone>(nodes: &Arena<T, A>, node: &InternalNode<T>,
+                                        bytes: &mut Vec<u8>, leaf: &mut usize) -> usize {
+    let offset = bytes.len();
+    let (mut occupied, mut internal) = (0u16, 0u16);
+    for (slot, child) in node.children.iter().enumerate() {
+        match *child {
+            Internal(_) => { occupied |= 1 << slot; internal |= 1 << slot; }
+            External(..) => occupied |= 1 << slot,
+            Nothing => {}
+        }
+    }
+    bytes.extend_from_slice(&occupied.to_le_bytes());
+    bytes.extend_from_slice(&internal.to_le_bytes());
+    bytes.resize(offset + NODE_LEN + 8 * occupied.count_ones() as usize, 0);
+    put_u64(bytes, offset + 8, *leaf);
+
+    let mut entry = offset + NODE_LEN;
+    for child in node.children.iter() {
+        match *child {
+            Internal(id) => {
+                let child = freeze_node(nodes, &nodes[id], bytes, leaf);
+                put_u64(bytes, entry, child);
+            }
+            External(..) => {
+                put_u64(bytes, entry, *leaf);
+                *leaf += 1;
+            }
+            Nothing => continue,
+        }
+        entry += 8;
+    }
+    put_u64(bytes, offset + 16, *leaf);
+    offset
+}
+
+fn put_u64(bytes: &mut [u8], at: usize, n: usize) {
+    bytes[at..at + 8].copy_from_slice(&(n as u64).to_le_bytes());
+}
+
+impl<B: AsRef<[u8]>> FrozenMap<B> {
+    /// Opens a frozen map stored in `bytes`, such as the contents of a file written from
+    /// `as_bytes`.
+    ///
+    /// Every node and leaf is checked, so that a truncated or damaged buffer is rejected here
+    /// rather than making later lookups fail. This takes time proportional to the number of
+    /// entries, but the values themselves are not read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::frozen::FrozenMap;
+    ///
+    /// let map: trie::Map<&str> = [(1, "a"), (2, "b")].iter().cloned().collect();
+    /// let bytes = map.freeze().into_inner();
+    ///
+    /// let frozen = FrozenMap::new(&bytes[..]).unwrap();
+    /// assert_eq!(frozen.get(&2), Some(&b"b"[..]));
+    ///
+    /// assert!(FrozenMap::new(&bytes[..20]).is_err());
+    /// ```
+    pub fn new(bytes: B) -> Result<FrozenMap<B>, SnapshotError> {
+        let (len, leaves, values, root) = {
+            let buf = bytes.as_ref();
+            if buf.len() < HEADER_LEN {
+                return Err(SnapshotError::Corrupt("buffer is too short"));
+            }
+            if buf[..4] != MAGIC {
+                return Err(SnapshotError::BadMagic);
+            }
+            if buf[4] != VERSION {
+                return Err(SnapshotError::UnsupportedVersion(buf[4]));
+            }
+            if buf[5] as usize != USIZE_BITS {
+                return Err(SnapshotError::Corrupt("keys are of a different width"));
+            }
+            let field = |i: usize| usize::try_from(read_u64(buf, 8 + 8 * i)).ok();
+            let fields = (field(0), field(1), field(2), field(3), field(4));
+            let (len, leaves, values, end, root) = match fields {
+                (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+                _ => return Err(SnapshotError::Corrupt("offset out of range")),
+            };
+            if end != buf.len() {
+                return Err(SnapshotError::Corrupt("buffer has the wrong length"));
+            }
+            let leaves_end = len.checked_mul(LEAF_LEN).and_then(|n| n.checked_add(leaves));
+            if root < HEADER_LEN || root > leaves.saturating_sub(NODE_LEN)
+                || leaves_end != Some(values) || values > end {
+                return Err(SnapshotError::Corrupt("offset out of range"));
+            }
+            let raw = Raw { bytes: buf, leaves, values };
+            if check_node(&raw, len, root, 0, 0, 0)? != len {
+                return Err(SnapshotError::Corrupt("leaves are missing from the trie"));
+            }
+            check_values(&raw, len)?;
+            (len, leaves, values, root)
+        };
+        Ok(FrozenMap { bytes, len, leaves, values, root })
+    }
+
+    /// Returns the buffer holding the map.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_ref()
+    }
+
+    /// Returns the buffer holding the map, consuming it.
+    pub fn into_inner(self) -> B {
+        self.bytes
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(1, "a")].iter().cloned().collect();
+    /// let frozen = map.freeze();
+    /// assert_eq!(frozen.get(&1), Some(&b"a"[..]));
+    /// assert_eq!(frozen.get(&2), None);
+    /// ```
+    pub fn get(&self, key: &usize) -> Option<&[u8]> {
+        let raw = self.raw();
+        let mut node = self.root;
+        for idx in 0..MAX_DEPTH {
+            let bit = 1 << chunk(*key, idx);
+            let (occupied, internal) = raw.bitmaps(node);
+            if occupied & bit == 0 {
+                return None;
+            }
+            let entry = raw.entry(node, occupied, bit);
+            if internal & bit == 0 {
+                return if raw.key(entry) == *key { Some(raw.value(entry)) } else { None };
+            }
+            node = entry;
+        }
+        None
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Gets an iterator over the entries of the map, in ascending order of key.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { raw: self.raw(), front: 0, back: self.len }
+    }
+
+    /// Gets an iterator pointing to the first entry whose key is not less than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(2, "a"), (4, "b"), (6, "c")].iter().cloned().collect();
+    /// let frozen = map.freeze();
+    ///
+    /// assert_eq!(frozen.lower_bound(4).next(), Some((4, &b"b"[..])));
+    /// assert_eq!(frozen.lower_bound(5).next(), Some((6, &b"c"[..])));
+    /// assert_eq!(frozen.lower_bound(10).next(), None);
+    /// ```
+    pub fn lower_bound(&self, key: usize) -> Iter<'_> {
+        Iter { raw: self.raw(), front: self.lower_index(key), back: self.len }
+    }
+
+    /// Gets an iterator pointing to the first entry whose key is greater than `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(2, "a"), (4, "b"), (6, "c")].iter().cloned().collect();
+    /// let frozen = map.freeze();
+    ///
+    /// assert_eq!(frozen.upper_bound(4).next(), Some((6, &b"c"[..])));
+    /// assert_eq!(frozen.upper_bound(10).next(), None);
+    /// ```
+    pub fn upper_bound(&self, key: usize) -> Iter<'_> {
+        Iter { raw: self.raw(), front: self.upper_index(key), back: self.len }
+    }
+
+    /// Gets an iterator over the entries whose keys lie in `range`, in ascending order of key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(2, "a"), (4, "b"), (6, "c")].iter().cloned().collect();
+    /// let frozen = map.freeze();
+    ///
+    /// let keys: Vec<usize> = frozen.range(3..=6).map(|(key, _)| key).collect();
+    /// assert_eq!(keys, [4, 6]);
+    /// assert_eq!(frozen.range(..4).next_back(), Some((2, &b"a"[..])));
+    /// ```
+    pub fn range<R: RangeBounds<usize>>(&self, range: R) -> Iter<'_> {
+        let front = match range.start_bound() {
+            Bound::Included(&start) => self.lower_index(start),
+            Bound::Excluded(&start) => self.upper_index(start),
+            Bound::Unbounded => 0,
+        };
+        let back = match range.end_bound() {
+            Bound::Included(&end) => self.upper_index(end),
+            Bound::Excluded(&end) => self.lower_index(end),
+            Bound::Unbounded => self.len,
+        };
+        Iter { raw: self.raw(), front, back: back.max(front) }
+    }
+
+    fn raw(&self) -> Raw<'_> {
+        Raw { bytes: self.bytes.as_ref(), leaves: self.leaves, values: self.values }
+    }
+
+    // Returns the index of the first leaf whose key is not less than `key`.
+    fn lower_index(&self, key: usize) -> usize {
+        let raw = self.raw();
+        let mut node = self.root;
+        for idx in 0..MAX_DEPTH {
+            let bit = 1 << chunk(key, idx);
+            let (occupied, internal) = raw.bitmaps(node);
+            if occupied & bit != 0 {
+                let entry = raw.entry(node, occupied, bit);
+                if internal & bit != 0 {
+                    node = entry;
+                    continue;
+                }
+                // Leaves are numbered in key order, so a smaller key is followed by the answer.
+                return if raw.key(entry) >= key { entry } else { entry + 1 };
+            }
+            let above = occupied & !(bit | (bit - 1));
+            if above == 0 {
+                return raw.leaf_end(node);
+            }
+            let next = above & above.wrapping_neg();
+            let entry = raw.entry(node, occupied, next);
+            return if internal & next != 0 { raw.leaf_start(entry) } else { entry };
+        }
+        self.len
+    }
+
+    // Returns the index of the first leaf whose key is greater than `key`.
+    fn upper_index(&self, key: usize) -> usize {
+        if key == usize::MAX { self.len } else { self.lower_index(key + 1) }
+    }
+}
+
+impl<B: AsRef<[u8]>> Debug for FrozenMap<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, B: AsRef<[u8]>> IntoIterator for &'a FrozenMap<B> {
+    type Item = (usize, &'a [u8]);
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Iter<'a> { self.iter() }
+}
+
+// The buffer of a frozen map, with the offsets of its regions.
+#[derive(Clone, Copy)]
+struct Raw<'a> {
+    bytes: &'a [u8],
+    leaves: usize,
+    values: usize,
+}
+
+impl<'a> Raw<'a> {
+    fn bitmaps(&self, node: usize) -> (u16, u16) {
+        let b = &self.bytes[node..node + 4];
+        (u16::from_le_bytes([b[0], b[1]]), u16::from_le_bytes([b[2], b[3]]))
+    }
+
+    // Returns the entry of the child in the slot `bit` of a node with the given children.
+    fn entry(&self, node: usize, occupied: u16, bit: u16) -> usize {
+        let rank = (occupied & (bit - 1)).count_ones() as usize;
+        self.usize_at(node + NODE_LEN + 8 * rank)
+    }
+
+    fn leaf_start(&self, node: usize) -> usize {
+        self.usize_at(node + 8)
+    }
+
+    fn leaf_end(&self, node: usize) -> usize {
+        self.usize_at(node + 16)
+    }
+
+    fn key(&self, leaf: usize) -> usize {
+        self.usize_at(self.leaves + LEAF_LEN * leaf)
+    }
+
+    fn value(&self, leaf: usize) -> &'a [u8] {
+        let start = if leaf == 0 { 0 } else { self.usize_at(self.leaves + LEAF_LEN * leaf - 8) };
+        let end = self.usize_at(self.leaves + LEAF_LEN * leaf + 8);
+        &self.bytes[self.values..][start..end]
+    }
+
+    fn usize_at(&self, at: usize) -> usize {
+        read_u64(self.bytes, at) as usize
+    }
+}
+
+// Checks the node at offset `node`, whose keys all start with `prefix` and whose children are at
+// depth `idx`, and everything below it. Its leaves must be numbered from `first`, and the number
+// after its last leaf is returned. The leaves of a map with `len` entries must be numbered in
+// order without gaps, so a node cannot be reached twice.
+fn check_node(raw: &Raw, len: usize, node: usize, idx: usize, prefix: usize, first: usize)
+    -> Result<usize, SnapshotError> {
+    let corrupt = |what| Err(SnapshotError::Corrupt(what));
+    if node < HEADER_LEN || node.checked_add(NODE_LEN).is_none_or(|end| end > raw.leaves) {
+        return corrupt("node out of range");
+    }
+    let (occupied, internal) = raw.bitmaps(node);
+    let entries = node + NODE_LEN + 8 * occupied.count_ones() as usize;
+    if entries > raw.leaves {
+        return corrupt("node out of range");
+    }
+    if internal & !occupied != 0 || (idx == MAX_DEPTH - 1 && internal != 0) {
+        return corrupt("invalid node");
+    }
+    if idx > 0 && occupied == 0 {
+        return corrupt("empty node");
+    }
+    if checked_usize_at(raw, node + 8)? != first {
+        return corrupt("node has the wrong leaves");
+    }
+
+    let mut next = first;
+    let mut entry = node + NODE_LEN;
+    for slot in 0..SIZE {
+        let bit = 1 << slot;
+        if occupied & bit == 0 {
+            continue;
+        }
+        let child = checked_usize_at(raw, entry)?;
+        let (start, end) = slot_bounds(prefix, slot, idx);
+        if internal & bit != 0 {
+            next = check_node(raw, len, child, idx + 1, start, next)?;
+        } else {
+            if child != next || child >= len {
+                return corrupt("leaf out of order");
+            }
+            let key = checked_usize_at(raw, raw.leaves + LEAF_LEN * child)?;
+            if key < start || key > end {
+                return corrupt("key in the wrong node");
+            }
+            next += 1;
+        }
+        entry += 8;
+    }
+    if checked_usize_at(raw, node + 16)? != next {
+        return corrupt("node has the wrong leaves");
+    }
+    Ok(next)
+}
+
+// Checks that the values of the `len` leaves follow each other and fill the rest of the buffer.
+fn check_values(raw: &Raw, len: usize) -> Result<(), SnapshotError> {
+    let mut start = 0;
+    for leaf in 0..len {
+        let end = checked_usize_at(raw, raw.leaves + LEAF_LEN * leaf + 8)?;
+        if end < start {
+            return Err(SnapshotError::Corrupt("values out of order"));
+        }
+        start = end;
+    }
+    if start != raw.bytes.len() - raw.values {
+        return Err(SnapshotError::Corrupt("values have the wrong length"));
+    }
+    Ok(())
+}
+
+// Reads a `u64` that must fit in a `usize`, from a position that is known to be in bounds.
+fn checked_usize_at(raw: &Raw, at: usize) -> Result<usize, SnapshotError> {
+    usize::try_from(read_u64(raw.bytes, at))
+        .map_err(|_| SnapshotError::Corrupt("offset out of range"))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// An iterator over the entries of a `FrozenMap`.
+#[derive(Clone)]
+pub struct Iter<'a> {
+    raw: Raw<'a>,
+    front: usize,
+    back: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (usize, &'a [u8]);
+
+    fn next(&mut self) -> Option<(usize, &'a [u8])> {
+        if self.front == self.back {
+            return None;
+        }
+        let leaf = self.front;
+        self.front += 1;
+        Some((self.raw.key(leaf), self.raw.value(leaf)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<(usize, &'a [u8])> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some((self.raw.key(self.back), self.raw.value(self.back)))
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use rand::{Rng, weak_rng};
+
+    use super::FrozenMap;
+    use super::super::Map;
+    use super::super::snapshot::SnapshotError;
+
+    fn check(map: &Map<Vec<u8>>) {
+        let frozen = map.freeze();
+        // Opening a copy at an odd address shows nothing relies on alignment.
+        let mut copy = vec![0];
+        copy.extend_from_slice(frozen.as_bytes());
+        let opened = FrozenMap::new(&copy[1..]).unwrap();
+
+        for frozen in &[FrozenMap::new(frozen.as_bytes()).unwrap(), opened] {
+            assert_eq!(frozen.len(), map.len());
+            assert!(frozen.iter().eq(map.iter().map(|(k, v)| (k, &v[..]))));
+            let keys: Vec<usize> = map.keys().collect();
+            assert!(frozen.iter().rev().map(|(k, _)| k).eq(keys.iter().rev().cloned()));
+
+            let mut probes: Vec<usize> = map.keys().collect();
+            probes.extend(map.keys().map(|k| k.wrapping_add(1)));
+            probes.extend(map.keys().map(|k| k.wrapping_sub(1)));
+            probes.extend(&[0, 1, 15, 16, 1000, usize::MAX - 1, usize::MAX]);
+            for &key in &probes {
+                assert_eq!(frozen.get(&key), map.get(&key).map(|v| &v[..]));
+                assert!(frozen.lower_bound(key).map(|(k, _)| k)
+                        .eq(map.lower_bound(key).map(|(k, _)| k)));
+                assert!(frozen.upper_bound(key).map(|(k, _)| k)
+                        .eq(map.upper_bound(key).map(|(k, _)| k)));
+                assert_eq!(frozen.range(..key).len(), map.keys().filter(|&k| k < key).count());
+                assert!(frozen.range(key..=key.saturating_add(100)).map(|(k, _)| k)
+                        .eq(map.keys().filter(|&k| k >= key && k - key <= 100)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_freeze() {
+        check(&Map::new());
+
+        let mut map = Map::new();
+        map.insert(usize::MAX, vec![]);
+        check(&map);
+        for i in 0..100 {
+            map.insert(i * 3, vec![i as u8; i % 5]);
+        }
+        check(&map);
+        map.insert(0x1234_5678, vec![1, 2, 3]);
+        map.insert(0x1234_5679, vec![4]);
+        check(&map);
+
+        let mut rng = weak_rng();
+        let mut map = Map::new();
+        for _ in 0..500 {
+            let key = rng.gen::<usize>() >> rng.gen_range(0, 64);
+            map.insert(key, key.to_le_bytes()[..rng.gen_range(0, 8)].to_vec());
+        }
+        check(&map);
+        // Removals leave keys deeper in the trie than they need to be.
+        let keys: Vec<usize> = map.keys().collect();
+        for key in keys.iter().step_by(3) {
+            map.remove(key);
+        }
+        check(&map);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_freeze_allocator() {
+        use super::super::allocator::Forwarding;
+
+        let mut map = Map::new_in(Forwarding);
+        for i in 0..100usize {
+            map.insert(i * 5, i.to_le_bytes());
+        }
+        let frozen = map.freeze();
+        assert_eq!(frozen.len(), 100);
+        assert!(frozen.iter().map(|(key, _)| key).eq(map.keys()));
+        assert_eq!(frozen.get(&35), Some(&7usize.to_le_bytes()[..]));
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let map: Map<&str> = [(1, "a"), (2, "b")].iter().cloned().collect();
+        let bytes = map.freeze().into_inner();
+
+        match FrozenMap::new(&b"TRIE"[..]) {
+            Err(SnapshotError::Corrupt(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        match FrozenMap::new(&wrong[..]) {
+            Err(SnapshotError::BadMagic) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut wrong = bytes.clone();
+        wrong[4] = 9;
+        match FrozenMap::new(&wrong[..]) {
+            Err(SnapshotError::UnsupportedVersion(9)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        for len in 0..bytes.len() {
+            assert!(FrozenMap::new(&bytes[..len]).is_err());
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(FrozenMap::new(&longer[..]).is_err());
+    }
+
+    #[test]
+    fn test_open_damaged() {
+        let mut map = Map::new();
+        for i in 0..40 {
+            map.insert(i * i * 5, vec![i as u8; i % 4]);
+        }
+        map.insert(usize::MAX, vec![1]);
+        let bytes = map.freeze().into_inner();
+
+        // A buffer that has been cut short and then padded out to its original length.
+        let mut truncated = bytes[..bytes.len() / 2].to_vec();
+        truncated.resize(bytes.len(), 0);
+        assert!(FrozenMap::new(&truncated[..]).is_err());
+
+        // Any single flipped bit is either rejected or leaves a map that can be used without
+        // panicking, though flips in keys and values may give wrong results.
+        let probes = [0, 5, 6, 20, 4500, 7605, 7606, 1 << 40, usize::MAX];
+        for at in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut damaged = bytes.clone();
+                damaged[at] ^= 1 << bit;
+                let frozen = match FrozenMap::new(&damaged[..]) {
+                    Ok(frozen) => frozen,
+                    Err(_) => continue,
+                };
+                assert_eq!(frozen.iter().count(), map.len());
+                for &key in &probes {
+                    frozen.get(&key);
+                    frozen.lower_bound(key).count();
+                    frozen.range(..key).rev().count();
+                }
+            }
+        }
+    }
+}
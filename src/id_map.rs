@@ -0,0 +1,480 @@
+//! A map that allocates its own keys, based on a trie.
+
+use core::fmt::{self, Debug};
+use core::ops;
+
+use super::map::{self, InternalNode, Map, SIZE, chunk, slot_bounds};
+use super::map::TrieNode::*;
+
+/// A map that picks the key for each inserted value, reusing keys that have been removed.
+///
+/// Values are stored in a `Map`, whose nodes record which of their children are full. Finding a
+/// free key only descends through nodes that still have room, so it takes time proportional to
+/// the depth of the trie.
+///
+/// By default the smallest unused key is handed out. A map created with `IdMap::cyclic` instead
+/// hands out the first unused key after the last one it allocated, wrapping around to zero when
+/// it reaches the end of the key space.
+///
+/// # Examples
+///
+/// ```
+/// let mut ids = trie::IdMap::new();
+/// let a = ids.insert("a");
+/// let b = ids.insert("b");
+/// let c = ids.insert("c");
+/// assert_eq!((a, b, c), (0, 1, 2));
+///
+/// assert_eq!(ids.remove(&b), Some("b"));
+/// assert_eq!(ids.insert("d"), 1);
+/// assert_eq!(ids[&1], "d");
+///
+/// // Keep an id out of circulation without storing a value for it.
+/// assert!(ids.reserve(3));
+/// assert_eq!(ids.insert("e"), 4);
+/// ```
+#[derive(Clone)]
+pub struct IdMap<T> {
+    // Reserved keys are stored without a value, so that they count towards the fullness of the
+    // map's nodes like the keys in use.
+    map: Map<Option<T>>,
+    // The number of values, which does not include reserved keys.
+    length: usize,
+    // The first key to try when allocating, or `None` to always pick the smallest free key.
+    next: Option<usize>,
+}
+
+// Returns the smallest key that is not less than `from` and is not in the map, where `node`'s
+// keys all start with `prefix`, `from` is one of them and `idx` is the depth of its children.
+fn first_free<T>(map: &Map<T>, node: &InternalNode<T>, idx: usize, prefix: usize, from: usize)
+    -> Option<usize> {
+    let first = chunk(from, idx);
+    for slot in first..SIZE {
+        if node.full & (1 << slot) != 0 {
+            continue;
+        }
+        let (start, end) = slot_bounds(prefix, slot, idx);
+        let from = if slot == first { from } else { start };
+        let found = match node.children[slot] {
+            Internal(id) => first_free(map, &map.nodes[id], idx + 1, start, from),
+            // The only key in use below this slot.
+            External(key, _) if key == from => if from < end { Some(from + 1) } else { None },
+            External(..) | Nothing => Some(from),
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+impl<T: Debug> Debug for IdMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Default for IdMap<T> {
+    #[inline]
+    fn default() -> IdMap<T> { IdMap::new() }
+}
+
+impl<T> IdMap<T> {
+    /// Creates an empty map that allocates the smallest unused key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids: trie::IdMap<&str> = trie::IdMap::new();
+    /// ```
+    #[inline]
+    pub fn new() -> IdMap<T> {
+        IdMap { map: Map::new(), length: 0, next: None }
+    }
+
+    /// Creates an empty map that allocates the first unused key after the most recently
+    /// allocated one, wrapping around to zero at the end of the key space.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::cyclic();
+    /// assert_eq!(ids.insert("a"), 0);
+    /// assert_eq!(ids.insert("b"), 1);
+    /// ids.remove(&0);
+    /// assert_eq!(ids.insert("c"), 2);
+    /// ```
+    #[inline]
+    pub fn cyclic() -> IdMap<T> {
+        IdMap::cyclic_from(0)
+    }
+
+    /// Creates an empty map that allocates keys like `cyclic`, starting from `first` rather
+    /// than zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::cyclic_from(300);
+    /// assert_eq!(ids.insert("a"), 300);
+    /// assert_eq!(ids.insert("b"), 301);
+    /// ```
+    #[inline]
+    pub fn cyclic_from(first: usize) -> IdMap<T> {
+        IdMap { map: Map::new(), length: 0, next: Some(first) }
+    }
+
+    /// Inserts a value under an unused key, and returns the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if every key is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// let id = ids.insert("a");
+    /// assert_eq!(ids.get(&id), Some(&"a"));
+    /// ```
+    pub fn insert(&mut self, value: T) -> usize {
+        let from = self.next.unwrap_or(0);
+        let root = &self.map.root;
+        let key = first_free(&self.map, root, 0, 0, from)
+            .or_else(|| first_free(&self.map, root, 0, 0, 0))
+            .expect("no unused keys left");
+        self.map.insert(key, Some(value));
+        self.length += 1;
+        if self.next.is_some() {
+            self.next = Some(key.wrapping_add(1));
+        }
+        key
+    }
+
+    /// Inserts a value under a specific key, which will no longer be allocated by `insert`.
+    /// If the key already had a value present in the map, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// assert_eq!(ids.insert_at(0, "a"), None);
+    /// assert_eq!(ids.insert_at(0, "b"), Some("a"));
+    /// assert_eq!(ids.insert("c"), 1);
+    /// ```
+    pub fn insert_at(&mut self, key: usize, value: T) -> Option<T> {
+        let old = self.map.insert(key, Some(value)).flatten();
+        if old.is_none() {
+            self.length += 1;
+        }
+        old
+    }
+
+    /// Reserves a key without storing a value for it, so that `insert` will not allocate it.
+    /// Returns `false` if the key is already in use or reserved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// assert!(ids.reserve(0));
+    /// assert!(!ids.reserve(0));
+    /// assert_eq!(ids.insert("a"), 1);
+    /// assert!(!ids.contains_key(&0));
+    /// ```
+    pub fn reserve(&mut self, key: usize) -> bool {
+        match self.map.entry(key) {
+            map::Occupied(_) => false,
+            map::Vacant(entry) => {
+                entry.insert(None);
+                true
+            }
+        }
+    }
+
+    /// Releases a key reserved with `reserve`, so that `insert` may allocate it again.
+    /// Returns `false` if the key was not reserved. Keys that hold a value are released by
+    /// `remove` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// ids.reserve(0);
+    /// assert!(ids.release(0));
+    /// assert!(!ids.release(0));
+    /// assert_eq!(ids.insert("a"), 0);
+    /// ```
+    pub fn release(&mut self, key: usize) -> bool {
+        match self.map.entry(key) {
+            map::Occupied(entry) if entry.get().is_none() => {
+                entry.remove();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in
+    /// the map. The key may then be allocated again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// let id = ids.insert("a");
+    /// assert_eq!(ids.remove(&id), Some("a"));
+    /// assert_eq!(ids.remove(&id), None);
+    /// ```
+    pub fn remove(&mut self, key: &usize) -> Option<T> {
+        match self.map.entry(*key) {
+            map::Occupied(entry) if entry.get().is_some() => {
+                self.length -= 1;
+                entry.remove()
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    #[inline]
+    pub fn get(&self, key: &usize) -> Option<&T> {
+        self.map.get(key).and_then(Option::as_ref)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    #[inline]
+    pub fn get_mut(&mut self, key: &usize) -> Option<&mut T> {
+        self.map.get_mut(key).and_then(Option::as_mut)
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    #[inline]
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Return the number of values in the map.
+    #[inline]
+    pub fn len(&self) -> usize { self.length }
+
+    /// Return true if the map contains no values.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Clears the map, removing all values and reservations. A cyclic map carries on from the
+    /// key after the last one it allocated.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.length = 0;
+    }
+
+    /// Gets an iterator over the key-value pairs in the map, ordered by keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut ids = trie::IdMap::new();
+    /// ids.insert("a");
+    /// ids.insert("b");
+    ///
+    /// let pairs: Vec<_> = ids.iter().collect();
+    /// assert_eq!(pairs, [(0, &"a"), (1, &"b")]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T> { Iter(self.map.iter()) }
+
+    /// Gets an iterator over the key-value pairs in the map, with the ability to mutate the
+    /// values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> { IterMut(self.map.iter_mut()) }
+
+    /// Gets an iterator visiting all keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, T> { Keys(self.iter()) }
+
+    /// Gets an iterator visiting all values in ascending order by the keys.
+    pub fn values(&self) -> Values<'_, T> { Values(self.iter()) }
+}
+
+impl<'a, T> ops::Index<&'a usize> for IdMap<T> {
+    type Output = T;
+    #[inline]
+    fn index(&self, i: &'a usize) -> &T {
+        self.get(i).expect("key not present")
+    }
+}
+
+impl<'a, T> ops::IndexMut<&'a usize> for IdMap<T> {
+    #[inline]
+    fn index_mut(&mut self, i: &'a usize) -> &mut T {
+        self.get_mut(i).expect("key not present")
+    }
+}
+
+/// A forward iterator over the key-value pairs of an `IdMap`.
+pub struct Iter<'a, T: 'a>(map::Iter<'a, Option<T>>);
+
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Iter<'a, T> { Iter(self.0.clone()) }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+    fn next(&mut self) -> Option<(usize, &'a T)> {
+        self.0.by_ref().find_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.0.size_hint().1) }
+}
+
+/// A forward iterator over the key-value pairs of an `IdMap`, with mutable references to the
+/// values.
+pub struct IterMut<'a, T: 'a>(map::IterMut<'a, Option<T>>);
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (usize, &'a mut T);
+    fn next(&mut self) -> Option<(usize, &'a mut T)> {
+        self.0.by_ref().find_map(|(key, value)| value.as_mut().map(|value| (key, value)))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.0.size_hint().1) }
+}
+
+/// A forward iterator over the keys of an `IdMap`.
+pub struct Keys<'a, T: 'a>(Iter<'a, T>);
+
+impl<'a, T> Clone for Keys<'a, T> {
+    fn clone(&self) -> Keys<'a, T> { Keys(self.0.clone()) }
+}
+
+impl<'a, T> Iterator for Keys<'a, T> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> { self.0.next().map(|e| e.0) }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+/// A forward iterator over the values of an `IdMap`.
+pub struct Values<'a, T: 'a>(Iter<'a, T>);
+
+impl<'a, T> Clone for Values<'a, T> {
+    fn clone(&self) -> Values<'a, T> { Values(self.0.clone()) }
+}
+
+impl<'a, T> Iterator for Values<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> { self.0.next().map(|e| e.1) }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.0.size_hint() }
+}
+
+impl<'a, T> IntoIterator for &'a IdMap<T> {
+    type Item = (usize, &'a T);
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> { self.iter() }
+}
+
+impl<'a, T> IntoIterator for &'a mut IdMap<T> {
+    type Item = (usize, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> IterMut<'a, T> { self.iter_mut() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use std::collections::BTreeSet;
+
+    use rand::{weak_rng, Rng};
+
+    use super::IdMap;
+
+    #[test]
+    fn test_lowest() {
+        let mut ids = IdMap::new();
+        for i in 0..1000 {
+            assert_eq!(ids.insert(i), i);
+        }
+
+        let mut freed = BTreeSet::new();
+        for i in (3..1000).step_by(37) {
+            assert_eq!(ids.remove(&i), Some(i));
+            freed.insert(i);
+        }
+        assert_eq!(ids.len(), 1000 - freed.len());
+
+        for &i in freed.iter() {
+            assert_eq!(ids.insert(0), i);
+        }
+        assert_eq!(ids.insert(0), 1000);
+        assert_eq!(ids.len(), 1001);
+    }
+
+    #[test]
+    fn test_random() {
+        // Allocation must agree with a linear scan for the smallest free key.
+        let mut rng = weak_rng();
+        let mut ids = IdMap::new();
+        let mut used = BTreeSet::new();
+        for _ in 0..5000 {
+            let key = rng.gen::<usize>() % 3000;
+            if rng.gen_weighted_bool(3) {
+                assert_eq!(ids.remove(&key).is_some(), used.remove(&key));
+            } else {
+                let expected = (0..).find(|key| !used.contains(key)).unwrap();
+                assert_eq!(ids.insert(()), expected);
+                used.insert(expected);
+            }
+        }
+        assert_eq!(ids.len(), used.len());
+        assert!(ids.keys().eq(used.iter().cloned()));
+    }
+
+    #[test]
+    fn test_cyclic() {
+        let mut ids = IdMap::cyclic();
+        assert_eq!(ids.insert('a'), 0);
+        assert_eq!(ids.insert('b'), 1);
+        ids.remove(&0);
+        assert_eq!(ids.insert('c'), 2);
+
+        let mut ids = IdMap::cyclic_from(usize::MAX - 1);
+        ids.insert_at(1, 'b');
+        ids.insert_at(2, 'c');
+        assert_eq!(ids.insert('d'), usize::MAX - 1);
+        assert_eq!(ids.insert('e'), usize::MAX);
+        assert_eq!(ids.insert('a'), 0);
+        assert_eq!(ids.insert('f'), 3);
+
+        let keys: Vec<_> = ids.keys().collect();
+        assert_eq!(keys, [0, 1, 2, 3, usize::MAX - 1, usize::MAX]);
+        let values: Vec<_> = ids.values().cloned().collect();
+        assert_eq!(values, ['a', 'b', 'c', 'f', 'd', 'e']);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut ids = IdMap::new();
+        for i in 0..20 {
+            assert!(ids.reserve(i * 2));
+        }
+        assert!(ids.is_empty());
+        for i in 0..20 {
+            assert_eq!(ids.insert(()), i * 2 + 1);
+        }
+        assert_eq!(ids.len(), 20);
+        assert!(!ids.release(1));
+        assert!(ids.release(2));
+        assert_eq!(ids.insert(()), 2);
+        assert_eq!(ids.insert(()), 40);
+
+        assert_eq!(ids.insert_at(4, ()), None);
+        assert_eq!(ids.len(), 23);
+        assert!(!ids.release(4));
+        assert_eq!(ids.remove(&4), Some(()));
+        assert_eq!(ids.remove(&6), None);
+        assert_eq!(ids.insert(()), 4);
+        assert_eq!(ids.iter().count(), ids.len());
+
+        ids.clear();
+        assert!(ids.is_empty());
+        assert_eq!(ids.insert(()), 0);
+    }
+}
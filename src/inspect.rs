@@ -0,0 +1,210 @@
+//! Views of the nodes of a map, for finding out why it has the shape it has.
+//!
+//! `Debug` prints a map as its keys and values in order. The views here print the trie itself:
+//! `Map::to_dot` as a graph for Graphviz, and `Map::debug_tree` as indented text. Both list the
+//! children of each internal node by slot, which is the hexadecimal digit of the key at that
+//! node's depth.
+
+use alloc::string::String;
+use core::fmt::{self, Debug, Display, Write};
+
+use super::map::{Allocator, Global, InternalNode, Map};
+use super::map::TrieNode::*;
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Returns the nodes of the map as a graph in the DOT language, which Graphviz can draw.
+    ///
+    /// Internal nodes are drawn as a row of their 16 slots below their depth and their number
+    /// of children, with an edge from each non-empty slot to the node it holds. External nodes
+    /// are labelled with their key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(1, "a"), (2, "b")].iter().cloned().collect();
+    /// let dot = map.to_dot();
+    /// assert!(dot.starts_with("digraph trie {"));
+    /// assert!(dot.contains("k1 [shape=box, label=\"1\"];"));
+    /// ```
+    pub fn to_dot(&self) -> String {
+        format!("{}", Dot { map: self, value: None })
+    }
+
+    /// Returns the nodes of the map as a DOT graph like `to_dot`, with values shown next to
+    /// their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(1, "a"), (2, "b")].iter().cloned().collect();
+    /// assert!(map.to_dot_with_values().contains("k1 [shape=box, label=\"1: \\\"a\\\"\"];"));
+    /// ```
+    pub fn to_dot_with_values(&self) -> String where T: Debug {
+        format!("{}", Dot { map: self, value: Some(|value, f| write!(f, ": {:?}", value)) })
+    }
+
+    /// Returns a view of the map that displays its nodes as an indented tree.
+    ///
+    /// Each internal node is shown with its number of children in brackets, and each external
+    /// node with its key and value. Both are preceded by their slot in their parent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = [(0x10, "a"), (0x20, "b")].iter().cloned().collect();
+    /// let tree = map.debug_tree().to_string();
+    /// let lines: Vec<&str> = tree.lines().map(str::trim_start).collect();
+    ///
+    /// // The keys only differ in their second to last chunk.
+    /// assert_eq!(lines[..2], ["root [1]", "0: [1]"]);
+    /// assert_eq!(lines[lines.len() - 3..], ["0: [2]", "1: 16 => \"a\"", "2: 32 => \"b\""]);
+    /// ```
+    pub fn debug_tree(&self) -> DebugTree<'_, T, A> {
+        DebugTree(self)
+    }
+}
+
+/// A view of a map that displays its nodes as an indented tree, created by `Map::debug_tree`.
+pub struct DebugTree<'a, T: 'a, A: Allocator + Clone + 'a = Global>(&'a Map<T, A>);
+
+impl<'a, T: Debug, A: Allocator + Clone> DebugTree<'a, T, A> {
+    fn node(&self, f: &mut fmt::Formatter, node: &InternalNode<T>, depth: usize) -> fmt::Result {
+        for (slot, child) in node.children.iter().enumerate() {
+            match *child {
+                Internal(id) => {
+                    let child = &self.0.nodes[id];
+                    let indent = 2 * depth;
+                    writeln!(f, "{:indent$}{:x}: [{}]", "", slot, child.count, indent = indent)?;
+                    self.node(f, child, depth + 1)?;
+                }
+                External(key, ref value) => {
+                    let indent = 2 * depth;
+                    writeln!(f, "{:indent$}{:x}: {} => {:?}", "", slot, key, value,
+                             indent = indent)?;
+                }
+                Nothing => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: Debug, A: Allocator + Clone> Display for DebugTree<'a, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "root [{}]", self.0.root.count)?;
+        self.node(f, &self.0.root, 1)
+    }
+}
+
+// Writes a map as a DOT graph, labelling external nodes with their key and, if `value` is set,
+// whatever it writes for their value.
+struct Dot<'a, T: 'a, A: Allocator + Clone + 'a> {
+    map: &'a Map<T, A>,
+    value: Option<fn(&T, &mut dyn Write) -> fmt::Result>,
+}
+
+impl<'a, T, A: Allocator + Clone> Dot<'a, T, A> {
+    // Writes `node`, whose children are at depth `idx`, and everything below it.
+    fn node(&self, f: &mut fmt::Formatter, name: &dyn Display, node: &InternalNode<T>,
+            idx: usize) -> fmt::Result {
+        write!(f, "    {} [label=\"{{depth {}, {} children|{{", name, idx, node.count)?;
+        for slot in 0..node.children.len() {
+            let sep = if slot == 0 { "" } else { "|" };
+            write!(f, "{}<s{:x}>{:x}", sep, slot, slot)?;
+        }
+        writeln!(f, "}}}}\"];")?;
+
+        for (slot, child) in node.children.iter().enumerate() {
+            match *child {
+                Internal(id) => {
+                    writeln!(f, "    {}:s{:x} -> n{};", name, slot, id)?;
+                    self.node(f, &format_args!("n{}", id), &self.map.nodes[id], idx + 1)?;
+                }
+                External(key, ref value) => {
+                    write!(f, "    k{} [shape=box, label=\"{}", key, key)?;
+                    if let Some(write_value) = self.value {
+                        write_value(value, &mut Escape(f))?;
+                    }
+                    writeln!(f, "\"];")?;
+                    writeln!(f, "    {}:s{:x} -> k{};", name, slot, key)?;
+                }
+                Nothing => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T, A: Allocator + Clone> Display for Dot<'a, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "digraph trie {{")?;
+        writeln!(f, "    node [shape=record];")?;
+        self.node(f, &"root", &self.map.root, 0)?;
+        writeln!(f, "}}")
+    }
+}
+
+// Escapes text for use in a quoted DOT string.
+struct Escape<'a, 'b: 'a>(&'a mut fmt::Formatter<'b>);
+
+impl<'a, 'b> Write for Escape<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' | '\\' => { self.0.write_char('\\')?; self.0.write_char(c)?; }
+                '\n' => self.0.write_str("\\n")?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    use super::super::Map;
+
+    #[test]
+    fn test_to_dot() {
+        let map: Map<&str> = [(1, "a\"b"), (2, "c"), (0x20, "d")].iter().cloned().collect();
+        let dot = map.to_dot_with_values();
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(lines[..2], ["digraph trie {", "    node [shape=record];"]);
+        assert_eq!(lines.last(), Some(&"}"));
+        assert!(lines[2].starts_with("    root [label=\"{depth 0, 1 children|{<s0>0|<s1>1|"));
+        assert!(lines.contains(&"    k1 [shape=box, label=\"1: \\\"a\\\\\\\"b\\\"\"];"));
+        assert!(lines.contains(&"    k32 [shape=box, label=\"32: \\\"d\\\"\"];"));
+
+        // There is an edge to every node except the root.
+        let edges = lines.iter().filter(|line| line.contains(" -> ")).count();
+        let stats = map.stats();
+        assert_eq!(edges, stats.internal_nodes - 1 + stats.external_nodes);
+        assert!(!map.to_dot().contains("\\\"c\\\""));
+    }
+
+    #[test]
+    fn test_debug_tree() {
+        let map: Map<u8> = [(1, 1), (2, 2), (0x20, 3)].iter().cloned().collect();
+        let tree = map.debug_tree().to_string();
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines[0], "root [1]");
+
+        // The keys share all but their last two chunks, so they are at the end of a chain.
+        let tail: Vec<(usize, &str)> = lines[lines.len() - 5..].iter().map(|line| {
+            let text = line.trim_start();
+            (line.len() - text.len(), text)
+        }).collect();
+        let indent = tail[0].0;
+        assert_eq!(indent, 2 * (lines.len() - 5));
+        assert_eq!(tail, [
+            (indent, "0: [2]"),
+            (indent + 2, "0: [2]"),
+            (indent + 4, "1: 1 => 1"),
+            (indent + 4, "2: 2 => 2"),
+            (indent + 2, "2: 32 => 3"),
+        ]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Checking the internal consistency of maps and sets.
+//!
+//! The methods of `Map` and `Set` keep their tries consistent, so `validate` should never find
+//! anything wrong. It is meant for debug builds and tests, and for data that came from elsewhere
+//! through unsafe code or a broken encoder.
+
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{self, Display};
+
+use super::Set;
+use super::set::{leaf_keys, leaf_len};
+use super::map::{Allocator, Arena, InternalNode, Map, MAX_DEPTH, is_full, slot_bounds};
+use super::map::TrieNode::*;
+
+/// An inconsistency found by `Map::validate` or `Set::validate`.
+///
+/// Nodes are identified by the bits that all keys below them start with, `prefix`, and by
+/// `depth`, the number of 4-bit chunks of those bits. The root is at depth 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// An internal node's count is not its number of non-empty children.
+    WrongCount { prefix: usize, depth: usize, count: usize, children: usize },
+    /// An internal node other than the root has no children. It should have been removed.
+    EmptyNode { prefix: usize, depth: usize },
+    /// A key is stored below a node whose prefix it does not have, or in the wrong slot.
+    MisplacedKey { key: usize, depth: usize, slot: usize },
+    /// A slot refers to an internal node that does not exist, has been freed, is already
+    /// referred to by another slot, or would be deeper than keys have chunks.
+    InvalidNode { prefix: usize, depth: usize },
+    /// Some internal nodes have been allocated and not freed, but are not in the trie.
+    UnreachableNodes(usize),
+    /// The recorded number of keys, or of values for a set, is not the number in the trie.
+    WrongLength { length: usize, actual: usize },
+    /// A set stores a marker for a block of full words that is larger than a slot below the
+    /// root, or that holds another key.
+    InvalidBlock { key: usize },
+    /// An internal node's mask of full children, `full`, is not the mask of the children that
+    /// are full, `actual`.
+    WrongFullness { prefix: usize, depth: usize, full: u16, actual: u16 },
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntegrityError::WrongCount { prefix, depth, count, children } => {
+                write!(f, "node {:#x} at depth {} has a count of {} but {} children",
+                       prefix, depth, count, children)
+            }
+            IntegrityError::EmptyNode { prefix, depth } => {
+                write!(f, "node {:#x} at depth {} is empty", prefix, depth)
+            }
+            IntegrityError::MisplacedKey { key, depth, slot } => {
+                write!(f, "key {:#x} is in slot {:x} at depth {}", key, slot, depth)
+            }
+            IntegrityError::InvalidNode { prefix, depth } => {
+                write!(f, "node {:#x} at depth {} is invalid", prefix, depth)
+            }
+            IntegrityError::UnreachableNodes(n) => write!(f, "{} nodes are unreachable", n),
+            IntegrityError::WrongLength { length, actual } => {
+                write!(f, "length is {} but there are {} entries", length, actual)
+            }
+            IntegrityError::InvalidBlock { key } => write!(f, "block {:#x} is invalid", key),
+            IntegrityError::WrongFullness { prefix, depth, full, actual } => {
+                write!(f, "node {:#x} at depth {} has full children {:#06x} but records {:#06x}",
+                       prefix, depth, actual, full)
+            }
+        }
+    }
+}
+
+impl Error for IntegrityError {}
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Checks that the map's trie is consistent, returning the first problem found.
+    ///
+    /// This visits every node, so it takes time proportional to the size of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<usize> = (0..1000).map(|i| (i * 7, i)).collect();
+    /// assert_eq!(map.validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), IntegrityError> {
+        self.validate_with(|_, _| Ok(()))
+    }
+
+    // Validates the map, passing each key and value to `check`.
+    fn validate_with<F>(&self, check: F) -> Result<(), IntegrityError>
+        where F: FnMut(usize, &T) -> Result<(), IntegrityError> {
+        // Freed nodes are marked as seen, so that a slot that refers to one is invalid.
+        let mut seen = Vec::new();
+        seen.resize(self.nodes.slots(), false);
+        for &id in self.nodes.released() {
+            seen[id as usize] = true;
+        }
+        let mut checker = Checker { nodes: &self.nodes, seen, keys: 0, check };
+        checker.node(&self.root, 0, 0)?;
+
+        let unreachable = checker.seen.iter().filter(|&&seen| !seen).count();
+        if unreachable != 0 {
+            return Err(IntegrityError::UnreachableNodes(unreachable));
+        }
+        if checker.keys != self.length {
+            return Err(IntegrityError::WrongLength { length: self.length, actual: checker.keys });
+        }
+        Ok(())
+    }
+}
+
+impl Set {
+    /// Checks that the set's trie is consistent, returning the first problem found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..1000).filter(|i| i % 3 == 0).collect();
+    /// assert_eq!(set.validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), IntegrityError> {
+        let mut values = 0usize;
+        // The last key that the previous word or block holds values for.
+        let mut last = None;
+        self.map.validate_with(|key, &word| {
+            let (first, end) = leaf_keys(key, word).ok_or(IntegrityError::InvalidBlock { key })?;
+            if last.is_some_and(|last| first <= last) {
+                return Err(IntegrityError::InvalidBlock { key });
+            }
+            last = Some(end);
+            values = values.wrapping_add(leaf_len(key, word));
+            Ok(())
+        })?;
+        if values != self.length {
+            return Err(IntegrityError::WrongLength { length: self.length, actual: values });
+        }
+        Ok(())
+    }
+}
+
+struct Checker<'a, T: 'a, A: Allocator + Clone + 'a, F> {
+    nodes: &'a Arena<T, A>,
+    // Whether each node in the arena has been freed or reached.
+    seen: Vec<bool>,
+    keys: usize,
+    check: F,
+}
+
+impl<'a, T, A, F> Checker<'a, T, A, F>
+    where A: Allocator + Clone, F: FnMut(usize, &T) -> Result<(), IntegrityError> {
+    // Checks `node`, whose keys all start with `prefix` and whose children are at depth `idx`,
+    // and everything below it.
+    fn node(&mut self, node: &InternalNode<T>, idx: usize, prefix: usize)
+        -> Result<(), IntegrityError> {
+        let mut children = 0;
+        let mut full = 0;
+        for (slot, child) in node.children.iter().enumerate() {
+            let (start, end) = slot_bounds(prefix, slot, idx);
+            match *child {
+                Nothing => continue,
+                Internal(id) => {
+                    let invalid = IntegrityError::InvalidNode { prefix: start, depth: idx + 1 };
+                    if idx + 1 >= MAX_DEPTH || self.seen.get(id as usize) != Some(&false) {
+                        return Err(invalid);
+                    }
+                    self.seen[id as usize] = true;
+                    let nodes = self.nodes;
+                    self.node(nodes.get(id).ok_or(invalid)?, idx + 1, start)?;
+                }
+                External(key, ref value) => {
+                    if key < start || key > end {
+                        return Err(IntegrityError::MisplacedKey { key, depth: idx, slot });
+                    }
+                    self.keys += 1;
+                    (self.check)(key, value)?;
+                }
+            }
+            if is_full(self.nodes, child, idx) {
+                full |= 1 << slot;
+            }
+            children += 1;
+        }
+        if idx > 0 && children == 0 {
+            return Err(IntegrityError::EmptyNode { prefix, depth: idx });
+        }
+        if children != usize::from(node.count) {
+            let count = usize::from(node.count);
+            return Err(IntegrityError::WrongCount { prefix, depth: idx, count, children });
+        }
+        if full != node.full {
+            let actual = full;
+            let full = node.full;
+            return Err(IntegrityError::WrongFullness { prefix, depth: idx, full, actual });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::ToString;
+
+    use super::IntegrityError::*;
+    use super::super::{Map, Set};
+    use super::super::map::{TrieNode, chunk};
+
+    #[test]
+    fn test_validate() {
+        let mut map: Map<usize> = (0..100).map(|i| (i * 3, i)).collect();
+        assert_eq!(map.validate(), Ok(()));
+
+        map.length += 1;
+        assert_eq!(map.validate(), Err(WrongLength { length: 101, actual: 100 }));
+        map.length -= 1;
+
+        map.root.count += 1;
+        assert_eq!(map.validate(), Err(WrongCount { prefix: 0, depth: 0, count: 2, children: 1 }));
+        map.root.count -= 1;
+
+        // Move a key to a different slot of the root.
+        let key = usize::MAX;
+        map.insert(key, 0);
+        map.root.children.swap(chunk(key, 0), 3);
+        let err = map.validate().unwrap_err();
+        assert_eq!(err, MisplacedKey { key, depth: 0, slot: 3 });
+        assert_eq!(err.to_string(), format!("key {:#x} is in slot 3 at depth 0", key));
+        map.root.children.swap(chunk(key, 0), 3);
+        assert_eq!(map.validate(), Ok(()));
+
+        // Detach a subtree, so that its nodes are leaked.
+        let slot = map.root.children.iter()
+            .position(|c| matches!(*c, TrieNode::Internal(_))).unwrap();
+        let child = std::mem::replace(&mut map.root.children[slot], TrieNode::Nothing);
+        map.root.count -= 1;
+        assert!(matches!(map.validate(), Err(UnreachableNodes(n)) if n > 0));
+
+        // Refer to the same subtree twice.
+        map.root.children[slot] = child.clone();
+        map.root.children[slot + 1] = child;
+        map.root.count += 2;
+        assert!(matches!(map.validate(), Err(InvalidNode { depth: 1, .. })));
+    }
+
+    #[test]
+    fn test_validate_fullness() {
+        // Keys 0 to 15 fill the node at the bottom of the chain.
+        let mut map: Map<usize> = (0..20).map(|i| (i, i)).collect();
+        assert_eq!(map.validate(), Ok(()));
+
+        map.root.full = 1;
+        let err = map.validate().unwrap_err();
+        assert_eq!(err, WrongFullness { prefix: 0, depth: 0, full: 1, actual: 0 });
+        assert_eq!(err.to_string(),
+                   "node 0x0 at depth 0 has full children 0x0000 but records 0x0001");
+    }
+
+    #[test]
+    fn test_validate_set() {
+        let mut set: Set = (0..1000).collect();
+        assert_eq!(set.validate(), Ok(()));
+        set.length -= 1;
+        assert_eq!(set.validate(), Err(WrongLength { length: 999, actual: 1000 }));
+        set.length += 1;
+
+        // A marker for the block of words 0 to 255 overlaps word 0.
+        set.map.insert(1, 0);
+        assert_eq!(set.validate(), Err(InvalidBlock { key: 1 }));
+        set.map.remove(&1);
+
+        set.map.insert(1 << 20 | 15, 0);
+        assert_eq!(set.validate(), Err(InvalidBlock { key: 1 << 20 | 15 }));
+    }
+}
@@ -10,18 +10,48 @@
 
 //! An ordered map and set based on a trie.
 
+#![no_std]
 #![cfg_attr(test, feature(fn_traits, test, unboxed_closures))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+#[macro_use] extern crate alloc;
+#[cfg(any(feature = "std", test))] extern crate std;
 
 #[cfg(test)] extern crate rand;
 #[cfg(test)] extern crate test;
 
+#[cfg(feature = "concurrent")]
+pub use concurrent::ConcurrentMap;
+pub use id_map::IdMap;
 pub use map::Map;
 pub use set::Set;
+#[cfg(feature = "std")]
+pub use sharded::ShardedMap;
 
 #[cfg(test)] #[macro_use] mod bench;
 
+mod allocator;
+pub mod batch;
+pub mod compact;
+#[cfg(feature = "concurrent")]
+pub mod concurrent;
+#[cfg(feature = "std")]
+pub mod frozen;
+pub mod id_map;
+pub mod inspect;
+pub mod integrity;
 pub mod map;
+#[cfg(feature = "rayon")]
+pub mod par;
 pub mod set;
+#[cfg(feature = "std")]
+pub mod sharded;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod stats;
 
 #[cfg(feature="ordered_iter")]
 mod ordered_iter;
+
+#[cfg(feature="serde")]
+mod serde;
@@ -13,14 +13,19 @@
 pub use self::Entry::*;
 use self::TrieNode::*;
 
-use std::cmp::Ordering;
-use std::fmt::{self, Debug};
-use std::hash::{Hash, Hasher};
-use std::iter;
-use std::mem;
-use std::ops;
-use std::ptr;
-use std::slice;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt::{self, Debug, Display};
+use core::hash::{Hash, Hasher};
+use core::iter;
+use core::mem;
+use core::ops;
+use core::slice;
+
+use super::allocator::vec as alloc_vec;
+
+pub use super::allocator::{Allocator, Global};
 
 #[cfg(target_pointer_width = "32")]
 pub const USIZE_BITS: usize = 32;
@@ -33,11 +38,11 @@ pub const USIZE_BITS: usize = 64;
 // FIXME(conventions): replace each_reverse by making iter DoubleEnded
 
 // FIXME: #5244: need to manually update the InternalNode constructor
-const SHIFT: usize = 4;
-const SIZE: usize = 1 << SHIFT;
-const MASK: usize = SIZE - 1;
+pub(crate) const SHIFT: usize = 4;
+pub(crate) const SIZE: usize = 1 << SHIFT;
+pub(crate) const MASK: usize = SIZE - 1;
 // The number of chunks that the key is divided into. Also the maximum depth of the map.
-const MAX_DEPTH: usize = USIZE_BITS / SHIFT;
+pub(crate) const MAX_DEPTH: usize = USIZE_BITS / SHIFT;
 
 /// A map implemented as a radix trie.
 ///
@@ -87,55 +92,284 @@ const MAX_DEPTH: usize = USIZE_BITS / SHIFT;
 /// map.clear();
 /// assert!(map.is_empty());
 /// ```
+///
+/// # Allocators
+///
+/// The internal nodes of a map are allocated from `A`, which is the global allocator unless the
+/// map is created with `new_in` or `with_capacity_in`. Other allocators need the
+/// `allocator-api2` feature, which makes `Allocator` a copy of the standard library's unstable
+/// trait of the same name that works on stable Rust; with the `allocator_api` feature, it is the
+/// standard library's trait itself. Without either, `Global` is the only allocator.
 #[derive(Clone)]
-pub struct Map<T> {
-    root: InternalNode<T>,
-    length: usize
+pub struct Map<T, A: Allocator + Clone = Global> {
+    pub(crate) root: InternalNode<T>,
+    // Every internal node except the root.
+    pub(crate) nodes: Arena<T, A>,
+    pub(crate) length: usize
 }
 
 // An internal node holds SIZE child nodes, which may themselves contain more internal nodes.
 //
 // Throughout this implementation, "idx" is used to refer to a section of key that is used
 // to access a node. The layer of the tree directly below the root corresponds to idx 0.
-struct InternalNode<T> {
+pub(crate) struct InternalNode<T> {
     // The number of direct children which are external (i.e. that store a value).
-    count: usize,
-    children: [TrieNode<T>; SIZE]
+    pub(crate) count: u16,
+    // Bit `i` is set if every key that can be stored below child `i` is in the map.
+    pub(crate) full: u16,
+    pub(crate) children: [TrieNode<T>; SIZE]
 }
 
+// The `full` mask of a node whose children are all full.
+pub(crate) const FULL: u16 = !0;
+
 // Each child of an InternalNode may be internal, in which case nesting continues,
-// external (containing a value), or empty
+// external (containing a value), or empty. Internal children are indices into the map's arena.
 #[derive(Clone)]
-enum TrieNode<T> {
-    Internal(Box<InternalNode<T>>),
+pub(crate) enum TrieNode<T> {
+    Internal(u32),
     External(usize, T),
     Nothing
 }
 
-impl<T: PartialEq> PartialEq for Map<T> {
-    fn eq(&self, other: &Map<T>) -> bool {
+// The internal nodes of a map other than its root, stored contiguously and referred to by index.
+// Nodes that are released are kept in place, empty, and reused by later allocations.
+#[derive(Clone)]
+pub(crate) struct Arena<T, A: Allocator + Clone = Global> {
+    nodes: alloc_vec::Vec<InternalNode<T>, A>,
+    free: alloc_vec::Vec<u32, A>,
+}
+
+impl<T> Arena<T> {
+    pub(crate) fn new() -> Arena<T> {
+        Arena::with_capacity_in(0, Global)
+    }
+}
+
+impl<T, A: Allocator + Clone> Arena<T, A> {
+    fn with_capacity_in(capacity: usize, alloc: A) -> Arena<T, A> {
+        Arena {
+            nodes: alloc_vec::Vec::with_capacity_in(capacity, alloc.clone()),
+            free: alloc_vec::Vec::new_in(alloc),
+        }
+    }
+
+    fn allocator(&self) -> &A {
+        self.nodes.allocator()
+    }
+
+    // Stores `node` and returns its index.
+    pub(crate) fn push(&mut self, node: InternalNode<T>) -> u32 {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                let id = self.nodes.len();
+                assert!(id < u32::MAX as usize, "too many nodes");
+                self.nodes.push(node);
+                id as u32
+            }
+        }
+    }
+
+    // Frees a node that has no children.
+    pub(crate) fn release(&mut self, id: u32) {
+        debug_assert!(self.nodes[id as usize].count == 0);
+        self.free.push(id);
+    }
+
+    // Frees a node and everything below it.
+    pub(crate) fn release_subtree(&mut self, id: u32) {
+        let node = self.take(id);
+        for child in node.children {
+            if let Internal(child) = child {
+                self.release_subtree(child);
+            }
+        }
+        self.free.push(id);
+    }
+
+    // Returns the node with index `id`, if there is one. It may have been released.
+    pub(crate) fn get(&self, id: u32) -> Option<&InternalNode<T>> {
+        self.nodes.get(id as usize)
+    }
+
+    // The number of nodes that have been allocated, including released ones.
+    pub(crate) fn slots(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // The indices of the released nodes.
+    pub(crate) fn released(&self) -> &[u32] {
+        &self.free
+    }
+
+    // Moves a node out of the arena, leaving an empty one in its place.
+    pub(crate) fn take(&mut self, id: u32) -> InternalNode<T> {
+        mem::replace(&mut self.nodes[id as usize], InternalNode::new())
+    }
+
+    // Copies `node` from the arena `from`, along with everything below it, and returns the index
+    // of the copy.
+    pub(crate) fn copy_subtree<B>(&mut self, from: &Arena<T, B>, node: &InternalNode<T>) -> u32
+        where T: Clone, B: Allocator + Clone {
+        let mut copy = InternalNode::new();
+        copy.count = node.count;
+        copy.full = node.full;
+        for (slot, child) in node.children.iter().enumerate() {
+            copy.children[slot] = match *child {
+                Internal(id) => Internal(self.copy_subtree(from, &from[id])),
+                ref other => other.clone(),
+            };
+        }
+        self.push(copy)
+    }
+
+    // Moves the node with index `id` out of the arena `from`, along with everything below it, and
+    // returns its index here and the number of values that were moved.
+    #[cfg(feature = "std")]
+    pub(crate) fn move_subtree<B>(&mut self, from: &mut Arena<T, B>, id: u32) -> (u32, usize)
+        where B: Allocator + Clone {
+        let mut node = from.take(id);
+        from.release(id);
+        let mut values = 0;
+        for child in node.children.iter_mut() {
+            match *child {
+                Internal(ref mut id) => {
+                    let (moved, count) = self.move_subtree(from, *id);
+                    *id = moved;
+                    values += count;
+                }
+                External(..) => values += 1,
+                Nothing => {}
+            }
+        }
+        (self.push(node), values)
+    }
+
+    // Moves every node of `other` to the end of this arena and returns the amount their indices
+    // were shifted by.
+    #[cfg(feature = "std")]
+    pub(crate) fn append<B: Allocator + Clone>(&mut self, other: Arena<T, B>) -> u32 {
+        let offset = self.nodes.len();
+        assert!(offset + other.nodes.len() < u32::MAX as usize, "too many nodes");
+        let offset = offset as u32;
+        self.nodes.reserve(other.nodes.len());
+        for mut node in other.nodes {
+            for child in node.children.iter_mut() {
+                if let Internal(ref mut id) = *child {
+                    *id += offset;
+                }
+            }
+            self.nodes.push(node);
+        }
+        self.free.extend(other.free.iter().map(|&id| id + offset));
+        offset
+    }
+
+    // The nodes, indexed by their ids.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn as_slice(&self) -> &[InternalNode<T>] {
+        &self.nodes
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut InternalNode<T> {
+        self.nodes.as_mut_ptr()
+    }
+
+    // The number of nodes in use.
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    // The number of bytes allocated for nodes, including spare capacity and released nodes.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<InternalNode<T>>() +
+            self.free.capacity() * mem::size_of::<u32>()
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional.saturating_sub(self.free.len()));
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let additional = additional.saturating_sub(self.free.len());
+        if self.nodes.len().saturating_add(additional) > u32::MAX as usize {
+            return Err(TryReserveError(()));
+        }
+        self.nodes.try_reserve(additional).map_err(|_| TryReserveError(()))
+    }
+
+    // Moves the nodes below `root` into a new arena in depth-first order, dropping released
+    // nodes, and frees any spare capacity.
+    fn compact(&mut self, root: &mut InternalNode<T>) {
+        let mut nodes = alloc_vec::Vec::with_capacity_in(self.len(), self.allocator().clone());
+        self.relocate(root, &mut nodes);
+        self.nodes = nodes;
+        self.free = alloc_vec::Vec::new_in(self.allocator().clone());
+    }
+
+    fn relocate(&mut self, node: &mut InternalNode<T>,
+                nodes: &mut alloc_vec::Vec<InternalNode<T>, A>) {
+        for child in node.children.iter_mut() {
+            if let Internal(ref mut id) = *child {
+                let mut moved = self.take(*id);
+                let at = nodes.len();
+                nodes.push(InternalNode::new());
+                self.relocate(&mut moved, nodes);
+                nodes[at] = moved;
+                *id = at as u32;
+            }
+        }
+    }
+}
+
+impl<T, A: Allocator + Clone> ops::Index<u32> for Arena<T, A> {
+    type Output = InternalNode<T>;
+    #[inline]
+    fn index(&self, id: u32) -> &InternalNode<T> {
+        &self.nodes[id as usize]
+    }
+}
+
+impl<T, A: Allocator + Clone> ops::IndexMut<u32> for Arena<T, A> {
+    #[inline]
+    fn index_mut(&mut self, id: u32) -> &mut InternalNode<T> {
+        &mut self.nodes[id as usize]
+    }
+}
+
+impl<T: PartialEq, A: Allocator + Clone, B: Allocator + Clone> PartialEq<Map<T, B>> for Map<T, A> {
+    fn eq(&self, other: &Map<T, B>) -> bool {
         self.len() == other.len() &&
             self.iter().zip(other.iter()).all(|(a, b)| a == b)
     }
 }
 
-impl<T: Eq> Eq for Map<T> {}
+impl<T: Eq, A: Allocator + Clone> Eq for Map<T, A> {}
 
-impl<T: PartialOrd> PartialOrd for Map<T> {
+impl<T: PartialOrd, A: Allocator + Clone> PartialOrd for Map<T, A> {
     #[inline]
-    fn partial_cmp(&self, other: &Map<T>) -> Option<Ordering> {
+    fn partial_cmp(&self, other: &Map<T, A>) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl<T: Ord> Ord for Map<T> {
+impl<T: Ord, A: Allocator + Clone> Ord for Map<T, A> {
     #[inline]
-    fn cmp(&self, other: &Map<T>) -> Ordering {
+    fn cmp(&self, other: &Map<T, A>) -> Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl<T: Debug> Debug for Map<T> {
+impl<T: Debug, A: Allocator + Clone> Debug for Map<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
@@ -156,7 +390,153 @@ impl<T> Map<T> {
     /// ```
     #[inline]
     pub fn new() -> Map<T> {
-        Map{root: InternalNode::new(), length: 0}
+        Map{root: InternalNode::new(), nodes: Arena::new(), length: 0}
+    }
+
+    /// Creates an empty map with room for at least `nodes` internal nodes before it needs to
+    /// reallocate.
+    ///
+    /// Internal nodes are only needed where keys share a prefix, so keys that are spread out
+    /// need few of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<&str> = trie::Map::with_capacity(100);
+    /// assert!(map.capacity() >= 100);
+    /// ```
+    #[inline]
+    pub fn with_capacity(nodes: usize) -> Map<T> {
+        Map::with_capacity_in(nodes, Global)
+    }
+
+    /// Builds a map from key-value pairs in strictly ascending order of key.
+    ///
+    /// The map is built from the bottom up in a single pass: each node is created once, with
+    /// all of its children, and no key is looked up from the root. This is much faster than
+    /// inserting the keys one at a time.
+    ///
+    /// Returns an error holding the first key that is not greater than the key before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::Map::from_sorted_iter(vec![(1, "a"), (3, "c"), (7, "g")]).unwrap();
+    /// assert_eq!(map.len(), 3);
+    /// assert_eq!(map[&3], "c");
+    ///
+    /// let err = trie::Map::from_sorted_iter(vec![(1, "a"), (7, "g"), (3, "c")]).unwrap_err();
+    /// assert_eq!(err.key(), 3);
+    /// assert!(trie::Map::from_sorted_iter(vec![(1, "a"), (1, "b")]).is_err());
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> Result<Map<T>, UnsortedError>
+        where I: IntoIterator<Item=(usize, T)> {
+        let mut builder = SortedBuilder::new();
+        for (key, value) in iter {
+            builder.push(key, value).map_err(|(key, _)| UnsortedError { key })?;
+        }
+        Ok(builder.finish())
+    }
+}
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Creates an empty map whose nodes are allocated from `alloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+    /// use trie::map::Global;
+    ///
+    /// let mut map = trie::Map::new_in(Global);
+    /// map.insert(1, "a");
+    /// assert_eq!(map[&1], "a");
+    /// ```
+    #[inline]
+    pub fn new_in(alloc: A) -> Map<T, A> {
+        Map::with_capacity_in(0, alloc)
+    }
+
+    /// Creates an empty map whose nodes are allocated from `alloc`, with room for at least
+    /// `nodes` internal nodes before it needs to reallocate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+    /// use trie::map::Global;
+    ///
+    /// let map: trie::Map<&str> = trie::Map::with_capacity_in(100, Global);
+    /// assert!(map.capacity() >= 100);
+    /// ```
+    #[inline]
+    pub fn with_capacity_in(nodes: usize, alloc: A) -> Map<T, A> {
+        Map{root: InternalNode::new(), nodes: Arena::with_capacity_in(nodes, alloc), length: 0}
+    }
+
+    /// Returns the allocator that the map's nodes are allocated from.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        self.nodes.allocator()
+    }
+
+    /// Returns the number of internal nodes the map can hold without reallocating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<&str> = trie::Map::with_capacity(10);
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.nodes.nodes.capacity()
+    }
+
+    /// Reserves room for at least `additional` more internal nodes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<&str> = trie::Map::new();
+    /// map.reserve(10);
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+    }
+
+    /// Reserves room for at least `additional` more internal nodes, returning an error if the
+    /// memory cannot be allocated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<&str> = trie::Map::new();
+    /// assert!(map.try_reserve(10).is_ok());
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.nodes.try_reserve(additional)
+    }
+
+    /// Shrinks the storage of the map to fit the nodes it uses.
+    ///
+    /// Nodes freed by removals are normally kept for reuse. This releases them, and lays out the
+    /// remaining nodes in key order so that later lookups and iteration touch less memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<usize> = (0..1000).map(|i| (i, i)).collect();
+    /// for i in 10..1000 {
+    ///     map.remove(&i);
+    /// }
+    /// map.shrink_to_fit();
+    /// assert_eq!(map.len(), 10);
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.compact(&mut self.root);
     }
 
     /// Visits all key-value pairs in reverse order. Aborts traversal when `f` returns `false`.
@@ -179,7 +559,7 @@ impl<T> Map<T> {
     #[inline]
     pub fn each_reverse<'a, F>(&'a self, mut f: F) -> bool
         where F: FnMut(&usize, &'a T) -> bool {
-        self.root.each_reverse(&mut f)
+        each_reverse(&self.nodes, &self.root, &mut f)
     }
 
     /// Gets an iterator visiting all keys in ascending order by the keys.
@@ -202,7 +582,7 @@ impl<T> Map<T> {
     /// }
     /// ```
     pub fn iter(&self) -> Iter<T> {
-        let mut iter = unsafe {Iter::new()};
+        let mut iter = unsafe {Iter::new(self.nodes.nodes.as_ptr() as *mut InternalNode<T>)};
         iter.stack[0] = self.root.children.iter();
         iter.length = 1;
         iter.remaining = self.length;
@@ -227,7 +607,7 @@ impl<T> Map<T> {
     /// assert_eq!(map.get(&3), Some(&-3));
     /// ```
     pub fn iter_mut(&mut self) -> IterMut<T> {
-        let mut iter = unsafe {IterMut::new()};
+        let mut iter = unsafe {IterMut::new(self.nodes.nodes.as_mut_ptr())};
         iter.stack[0] = self.root.children.iter_mut();
         iter.length = 1;
         iter.remaining = self.length;
@@ -263,6 +643,9 @@ impl<T> Map<T> {
 
     /// Clears the map, removing all values.
     ///
+    /// The map keeps its node storage for reuse; see `shrink_to_fit`. Unless the values need to
+    /// be dropped, this takes constant time however large the map is.
+    ///
     /// # Examples
     ///
     /// ```
@@ -274,6 +657,7 @@ impl<T> Map<T> {
     #[inline]
     pub fn clear(&mut self) {
         self.root = InternalNode::new();
+        self.nodes.clear();
         self.length = 0;
     }
 
@@ -293,7 +677,7 @@ impl<T> Map<T> {
         let mut idx = 0;
         loop {
             match node.children[chunk(*key, idx)] {
-              Internal(ref x) => node = &**x,
+              Internal(id) => node = &self.nodes[id],
               External(stored, ref value) => {
                 if stored == *key {
                     return Some(value)
@@ -337,7 +721,11 @@ impl<T> Map<T> {
     /// ```
     #[inline]
     pub fn get_mut(&mut self, key: &usize) -> Option<&mut T> {
-        find_mut(&mut self.root.children[chunk(*key, 0)], *key, 1)
+        let (node, idx) = self.search(*key);
+        match self.node_mut(node).children[chunk(*key, idx)] {
+            External(stored, ref mut value) if stored == *key => Some(value),
+            _ => None
+        }
     }
 
     /// Inserts a key-value pair from the map. If the key already had a value
@@ -355,11 +743,26 @@ impl<T> Map<T> {
     /// assert_eq!(map[&37], "c");
     /// ```
     pub fn insert(&mut self, key: usize, value: T) -> Option<T> {
-        let (_, old_val) = insert(&mut self.root.count,
-                                    &mut self.root.children[chunk(key, 0)],
-                                    key, value, 1);
-        if old_val.is_none() { self.length += 1 }
-        old_val
+        let (node, idx) = self.search(key);
+        self.insert_at(node, idx, key, value)
+    }
+
+    /// Inserts a key-value pair like `insert`, but returns an error instead of aborting if the
+    /// nodes needed to store the key cannot be allocated. The map is left unchanged on error.
+    ///
+    /// Replacing the value of a key that is already present never allocates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// assert_eq!(map.try_insert(37, "a"), Ok(None));
+    /// assert_eq!(map.try_insert(37, "b"), Ok(Some("a")));
+    /// ```
+    pub fn try_insert(&mut self, key: usize, value: T) -> Result<Option<T>, TryReserveError> {
+        let (node, idx) = self.search(key);
+        self.nodes.try_reserve(self.nodes_needed(node, idx, key))?;
+        Ok(self.insert_at(node, idx, key, value))
     }
 
     /// Removes a key from the map, returning the value at the key if the key
@@ -374,11 +777,309 @@ impl<T> Map<T> {
     /// assert_eq!(map.remove(&1), None);
     /// ```
     pub fn remove(&mut self, key: &usize) -> Option<T> {
-        let ret = remove(&mut self.root.count,
-                         &mut self.root.children[chunk(*key, 0)],
-                         *key, 1);
-        if ret.is_some() { self.length -= 1 }
-        ret
+        let mut path = [ROOT; MAX_DEPTH];
+        let mut idx = 0;
+        loop {
+            match self.node(path[idx]).children[chunk(*key, idx)] {
+                Internal(id) => {
+                    idx += 1;
+                    path[idx] = id;
+                }
+                External(stored, _) if stored == *key => break,
+                _ => return None
+            }
+        }
+        Some(self.remove_at(&path[..=idx], *key))
+    }
+}
+
+// Stands for the root of a map where nodes are identified by their index in the arena.
+pub(crate) const ROOT: u32 = u32::MAX;
+
+/// The error returned when a fallible insertion cannot allocate the nodes it needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TryReserveError(());
+
+impl Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("failed to allocate trie nodes")
+    }
+}
+
+impl Error for TryReserveError {}
+
+/// The error returned when keys that should be in strictly ascending order are not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsortedError {
+    pub(crate) key: usize,
+}
+
+impl UnsortedError {
+    /// Returns the first key that was not greater than the key before it.
+    pub fn key(&self) -> usize {
+        self.key
+    }
+}
+
+impl Display for UnsortedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key {} is not greater than the key before it", self.key)
+    }
+}
+
+impl Error for UnsortedError {}
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    #[inline]
+    pub(crate) fn node(&self, id: u32) -> &InternalNode<T> {
+        if id == ROOT { &self.root } else { &self.nodes[id] }
+    }
+
+    #[inline]
+    pub(crate) fn node_mut(&mut self, id: u32) -> &mut InternalNode<T> {
+        if id == ROOT { &mut self.root } else { &mut self.nodes[id] }
+    }
+
+    // The nodes on `key`'s path from the root down to depth `idx`, which must all be internal.
+    fn path_to(&self, key: usize, idx: usize) -> [u32; MAX_DEPTH] {
+        let mut path = [ROOT; MAX_DEPTH];
+        for i in 1..=idx {
+            path[i] = match self.node(path[i - 1]).children[chunk(key, i - 1)] {
+                Internal(id) => id,
+                _ => unreachable!()
+            };
+        }
+        path
+    }
+
+    // Makes the nodes on `key`'s path down to depth `idx` internal, adding empty nodes where the
+    // path ends and pushing down the keys in the way, so that slots at that depth can be
+    // detached.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn open_path(&mut self, key: usize, idx: usize) {
+        let mut node = ROOT;
+        for i in 0..idx {
+            let slot = chunk(key, i);
+            if let Internal(id) = self.node(node).children[slot] {
+                node = id;
+                continue;
+            }
+            let mut below = InternalNode::new();
+            match mem::replace(&mut self.node_mut(node).children[slot], Nothing) {
+                External(stored, value) => {
+                    below.children[chunk(stored, i + 1)] = External(stored, value);
+                    below.count = 1;
+                    below.update_full(&self.nodes, i + 1);
+                }
+                _ => self.node_mut(node).count += 1,
+            }
+            let id = self.nodes.push(below);
+            self.node_mut(node).children[slot] = Internal(id);
+            node = id;
+        }
+    }
+
+    // Moves the subtree in the slot on `key`'s path at depth `idx` into a new map in the same
+    // allocator, which has nothing else but its own copy of the nodes above the slot. The nodes
+    // above the slot must be internal, and the one holding it may be left empty until the slot
+    // is attached again.
+    #[cfg(feature = "std")]
+    pub(crate) fn detach_slot(&mut self, key: usize, idx: usize) -> Map<T, A> {
+        let path = self.path_to(key, idx);
+        let slot = chunk(key, idx);
+        let mut part = Map::new_in(self.allocator().clone());
+        let child = match mem::replace(&mut self.node_mut(path[idx]).children[slot], Nothing) {
+            Nothing => return part,
+            Internal(id) => {
+                let (id, values) = part.nodes.move_subtree(&mut self.nodes, id);
+                part.length = values;
+                Internal(id)
+            }
+            external => {
+                part.length = 1;
+                external
+            }
+        };
+        self.node_mut(path[idx]).count -= 1;
+        for (i, &id) in path[..=idx].iter().enumerate() {
+            self.node_mut(id).full &= !(1 << chunk(key, i));
+        }
+        self.length -= part.length;
+
+        let mut node = InternalNode::new();
+        node.children[slot] = child;
+        node.count = 1;
+        node.update_full(&part.nodes, idx);
+        for i in (0..idx).rev() {
+            let id = part.nodes.push(node);
+            node = InternalNode::new();
+            node.children[chunk(key, i)] = Internal(id);
+            node.count = 1;
+            node.update_full(&part.nodes, i);
+        }
+        part.root = node;
+        part
+    }
+
+    // Moves the keys of `part`, which all lie below the slot on `key`'s path at depth `idx`, into
+    // that slot, which must be empty. The nodes above the slot must be internal.
+    #[cfg(feature = "std")]
+    pub(crate) fn attach_slot(&mut self, key: usize, idx: usize, part: Map<T, A>) {
+        let Map { mut root, nodes, length } = part;
+        let offset = self.nodes.append(nodes);
+        // The part's nodes above the slot are released. A part with a single key may hold it
+        // above the slot, which is where it goes.
+        let mut child = match mem::replace(&mut root.children[chunk(key, 0)], Nothing) {
+            Internal(id) => Internal(id + offset),
+            other => other,
+        };
+        for i in 1..=idx {
+            let id = match child {
+                Internal(id) => id,
+                _ => break,
+            };
+            let mut node = self.nodes.take(id);
+            self.nodes.release(id);
+            child = mem::replace(&mut node.children[chunk(key, i)], Nothing);
+        }
+        if matches!(child, Nothing) {
+            return;
+        }
+
+        let path = self.path_to(key, idx);
+        let slot = chunk(key, idx);
+        debug_assert!(matches!(self.node(path[idx]).children[slot], Nothing));
+        let full = is_full(&self.nodes, &child, idx);
+        let node = self.node_mut(path[idx]);
+        node.children[slot] = child;
+        node.count += 1;
+        self.length += length;
+        if full {
+            self.propagate_full(key, idx + 1);
+        }
+    }
+
+    // Descends towards `key` and returns the node where the search stopped, with the depth of
+    // its children. The child on the key's path there is not internal: it is empty, or holds
+    // `key`, or holds another key that would have to be pushed down to make room.
+    #[inline]
+    fn search(&self, key: usize) -> (u32, usize) {
+        let mut node = ROOT;
+        let mut idx = 0;
+        while let Internal(id) = self.node(node).children[chunk(key, idx)] {
+            node = id;
+            idx += 1;
+        }
+        (node, idx)
+    }
+
+    // Stores `key` below the node where `search` stopped, replacing its value if it is present.
+    fn insert_at(&mut self, node: u32, idx: usize, key: usize, value: T) -> Option<T> {
+        if let External(stored, ref mut stored_value) =
+                self.node_mut(node).children[chunk(key, idx)] {
+            if stored == key {
+                return Some(mem::replace(stored_value, value));
+            }
+        }
+        self.place(node, idx, key, value);
+        self.length += 1;
+        None
+    }
+
+    // Returns the number of nodes that `place` needs to create to store `key` below the node
+    // where `search` stopped.
+    fn nodes_needed(&self, node: u32, idx: usize, key: usize) -> usize {
+        match self.node(node).children[chunk(key, idx)] {
+            External(stored, _) if stored != key => shared_chunks(stored, key) - idx,
+            _ => 0,
+        }
+    }
+
+    // Stores a key that is not in the map below the node where `search` stopped, and returns
+    // the node and depth where it ended up. If another key is in the way, both are moved into
+    // new nodes down to the first chunk where they differ.
+    fn place(&mut self, mut node: u32, mut idx: usize, key: usize, value: T) -> (u32, usize) {
+        let slot = chunk(key, idx);
+        match mem::replace(&mut self.node_mut(node).children[slot], Nothing) {
+            Nothing => {
+                let parent = self.node_mut(node);
+                parent.children[slot] = External(key, value);
+                parent.count += 1;
+                if idx == MAX_DEPTH - 1 {
+                    parent.full |= 1 << slot;
+                    if parent.full == FULL {
+                        self.propagate_full(key, idx);
+                    }
+                }
+            }
+            External(stored, stored_value) => {
+                loop {
+                    let child = self.nodes.push(InternalNode::new());
+                    self.node_mut(node).children[chunk(key, idx)] = Internal(child);
+                    node = child;
+                    idx += 1;
+                    let (a, b) = (chunk(stored, idx), chunk(key, idx));
+                    let new = &mut self.nodes[child];
+                    if a != b {
+                        new.children[a] = External(stored, stored_value);
+                        new.children[b] = External(key, value);
+                        new.count = 2;
+                        if idx == MAX_DEPTH - 1 {
+                            new.full = 1 << a | 1 << b;
+                        }
+                        break;
+                    }
+                    new.count = 1;
+                }
+            }
+            Internal(_) => unreachable!()
+        }
+        (node, idx)
+    }
+
+    // Marks the node on the path to `key` whose children are at depth `idx`, which has just
+    // become full, as full in its parent, and so on up for as long as the parents become full.
+    fn propagate_full(&mut self, key: usize, idx: usize) {
+        let path = self.path_to(key, idx - 1);
+        for i in (0..idx).rev() {
+            let node = self.node_mut(path[i]);
+            node.full |= 1 << chunk(key, i);
+            if node.full != FULL {
+                break;
+            }
+        }
+    }
+
+    // Removes the key found at the end of `path`, which lists the nodes from the root down, and
+    // frees the nodes that are left empty.
+    fn remove_at(&mut self, path: &[u32], key: usize) -> T {
+        let mut idx = path.len() - 1;
+        let value = match mem::replace(&mut self.node_mut(path[idx]).children[chunk(key, idx)],
+                                       Nothing) {
+            External(_, value) => value,
+            _ => unreachable!()
+        };
+        // The nodes on the path are no longer full, and nor are the ones above them that were.
+        for (i, &id) in path.iter().enumerate().rev() {
+            let node = self.node_mut(id);
+            let was_full = node.full == FULL;
+            node.full &= !(1 << chunk(key, i));
+            if !was_full {
+                break;
+            }
+        }
+        loop {
+            let node = self.node_mut(path[idx]);
+            node.count -= 1;
+            if node.count != 0 || idx == 0 {
+                break;
+            }
+            self.nodes.release(path[idx]);
+            idx -= 1;
+            self.node_mut(path[idx]).children[chunk(key, idx)] = Nothing;
+        }
+        self.length -= 1;
+        value
     }
 }
 
@@ -402,6 +1103,9 @@ macro_rules! bound {
      // method name for iterating.
      iter = $iter:ident,
 
+     // a pointer to the first node in the arena.
+     nodes = $nodes:expr,
+
      // see the comment on `addr!`, this is just an optional mut, but
      // there's no 0-or-1 repeats yet.
      mutability = $($mut_:tt)*) => {
@@ -421,14 +1125,14 @@ macro_rules! bound {
             // # For non-`mut`
             // We like sharing code so much that even a little unsafe won't
             // stop us.
+            let nodes: *mut InternalNode<T> = $nodes;
             let this = $this;
-            let mut node = unsafe {
-                mem::transmute::<_, usize>(&this.root) as *mut InternalNode<T>
-            };
+            let mut node = addr!(& $($mut_)* this.root) as *const InternalNode<T>
+                as *mut InternalNode<T>;
 
             let key = $key;
 
-            let mut it = unsafe {$iterator_name::new()};
+            let mut it = unsafe {$iterator_name::new(nodes)};
             // everything else is zero'd, as we want.
             it.remaining = this.length;
 
@@ -439,11 +1143,8 @@ macro_rules! bound {
                     // current depth through the `usize` key we've traversed.
                     let child_id = chunk(key, it.length);
                     let (slice_idx, ret) = match children[child_id] {
-                        Internal(ref $($mut_)* n) => {
-                            node = unsafe {
-                                mem::transmute::<_, usize>(&**n)
-                                    as *mut InternalNode<T>
-                            };
+                        Internal(id) => {
+                            node = unsafe { nodes.add(id as usize) };
                             (child_id + 1, false)
                         }
                         External(stored, _) => {
@@ -468,13 +1169,14 @@ macro_rules! bound {
     }
 }
 
-impl<T> Map<T> {
+impl<T, A: Allocator + Clone> Map<T, A> {
     // If `upper` is true then returns upper_bound else returns lower_bound.
     #[inline]
     fn bound(&self, key: usize, upper: bool) -> Range<T> {
         Range(bound!(Iter, self = self,
                key = key, is_upper = upper,
                iter = iter,
+               nodes = self.nodes.nodes.as_ptr() as *mut InternalNode<T>,
                mutability = ))
     }
 
@@ -515,6 +1217,7 @@ impl<T> Map<T> {
         RangeMut(bound!(IterMut, self = self,
                key = key, is_upper = upper,
                iter = iter_mut,
+               nodes = self.nodes.nodes.as_mut_ptr(),
                mutability = mut))
     }
 
@@ -567,6 +1270,113 @@ impl<T> Map<T> {
     }
 }
 
+/// Builds a map from keys in strictly ascending order in a single pass.
+///
+/// The nodes on the path to the most recent key are kept open on a stack. A key is placed as
+/// soon as the next key is known, because the depth of a key is decided by the longest prefix
+/// it shares with its neighbours, and nodes are attached to their parents once no later key
+/// can fall below them. Nothing is ever re-descended or split.
+pub(crate) struct SortedBuilder<T, A: Allocator + Clone = Global> {
+    // stack[i] is the open node at depth i, on the path to `pending`.
+    stack: Vec<InternalNode<T>>,
+    // Nodes that have been closed.
+    nodes: Arena<T, A>,
+    // The last key pushed, its value, and the number of chunks it shares with the key before.
+    pending: Option<(usize, T, usize)>,
+    length: usize,
+}
+
+impl<T> SortedBuilder<T> {
+    pub(crate) fn new() -> SortedBuilder<T> {
+        SortedBuilder::with_arena(Arena::new())
+    }
+}
+
+impl<T, A: Allocator + Clone> SortedBuilder<T, A> {
+    #[cfg(feature = "serde")]
+    pub(crate) fn new_in(alloc: A) -> SortedBuilder<T, A> {
+        SortedBuilder::with_arena(Arena::with_capacity_in(0, alloc))
+    }
+
+    // Builds into `nodes`, which must be empty, keeping its allocation.
+    fn with_arena(nodes: Arena<T, A>) -> SortedBuilder<T, A> {
+        debug_assert!(nodes.slots() == 0);
+        SortedBuilder {
+            stack: vec![InternalNode::new()],
+            nodes,
+            pending: None,
+            length: 0,
+        }
+    }
+
+    /// Returns the last key that was pushed.
+    #[cfg(feature = "serde")]
+    pub(crate) fn last_key(&self) -> Option<usize> {
+        self.pending.as_ref().map(|&(key, _, _)| key)
+    }
+
+    /// Adds a key-value pair. If `key` is not greater than the last key pushed, the pair is
+    /// handed back and the builder is left unchanged.
+    pub(crate) fn push(&mut self, key: usize, value: T) -> Result<(), (usize, T)> {
+        let shared = match self.pending {
+            Some((last, _, _)) if key <= last => return Err((key, value)),
+            Some((last, _, _)) => shared_chunks(last, key),
+            None => 0,
+        };
+        if let Some((last, last_value, last_shared)) = self.pending.take() {
+            self.place(last, last_value, last_shared.max(shared));
+            self.close(last, shared + 1);
+        }
+        self.pending = Some((key, value, shared));
+        Ok(())
+    }
+
+    /// Finishes building the map.
+    pub(crate) fn finish(mut self) -> Map<T, A> {
+        if let Some((last, last_value, last_shared)) = self.pending.take() {
+            self.place(last, last_value, last_shared);
+            self.close(last, 1);
+        }
+        Map { root: self.stack.pop().unwrap(), nodes: self.nodes, length: self.length }
+    }
+
+    // Stores `key` in an external node at depth `idx`, opening nodes on its path as needed.
+    fn place(&mut self, key: usize, value: T, idx: usize) {
+        while self.stack.len() <= idx {
+            self.stack.push(InternalNode::new());
+        }
+        let node = &mut self.stack[idx];
+        node.children[chunk(key, idx)] = External(key, value);
+        node.count += 1;
+        if idx == MAX_DEPTH - 1 {
+            node.full |= 1 << chunk(key, idx);
+        }
+        self.length += 1;
+    }
+
+    // Attaches the open nodes below `depth` to their parents, where `key` lies below all of them.
+    fn close(&mut self, key: usize, depth: usize) {
+        while self.stack.len() > depth {
+            let node = self.stack.pop().unwrap();
+            let full = node.full == FULL;
+            let node = self.nodes.push(node);
+            let idx = self.stack.len() - 1;
+            let parent = &mut self.stack[idx];
+            parent.children[chunk(key, idx)] = Internal(node);
+            parent.count += 1;
+            if full {
+                parent.full |= 1 << chunk(key, idx);
+            }
+        }
+    }
+}
+
+// Returns the number of leading chunks that two different keys have in common.
+#[inline]
+pub(crate) fn shared_chunks(a: usize, b: usize) -> usize {
+    (a ^ b).leading_zeros() as usize / SHIFT
+}
+
 impl<T> iter::FromIterator<(usize, T)> for Map<T> {
     fn from_iter<I: IntoIterator<Item=(usize, T)>>(iter: I) -> Map<T> {
         let mut map = Map::new();
@@ -575,15 +1385,35 @@ impl<T> iter::FromIterator<(usize, T)> for Map<T> {
     }
 }
 
-impl<T> Extend<(usize, T)> for Map<T> {
+impl<T, A: Allocator + Clone> Extend<(usize, T)> for Map<T, A> {
     fn extend<I: IntoIterator<Item=(usize, T)>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        if self.is_empty() {
+            // Build from the bottom up for as long as the keys arrive in ascending order, then
+            // insert the rest one at a time.
+            let alloc = self.allocator().clone();
+            let mut nodes = mem::replace(&mut self.nodes, Arena::with_capacity_in(0, alloc));
+            nodes.clear();
+            let mut builder = SortedBuilder::with_arena(nodes);
+            let mut unsorted = None;
+            for (k, v) in iter.by_ref() {
+                if let Err(pair) = builder.push(k, v) {
+                    unsorted = Some(pair);
+                    break;
+                }
+            }
+            *self = builder.finish();
+            if let Some((k, v)) = unsorted {
+                self.insert(k, v);
+            }
+        }
         for (k, v) in iter {
             self.insert(k, v);
         }
     }
 }
 
-impl<T: Hash> Hash for Map<T> {
+impl<T: Hash, A: Allocator + Clone> Hash for Map<T, A> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         for elt in self.iter() {
             elt.hash(state);
@@ -591,7 +1421,7 @@ impl<T: Hash> Hash for Map<T> {
     }
 }
 
-impl<'a, T> ops::Index<&'a usize> for Map<T> {
+impl<'a, T, A: Allocator + Clone> ops::Index<&'a usize> for Map<T, A> {
     type Output = T;
     #[inline]
     fn index(&self, i: &'a usize) -> &T {
@@ -599,7 +1429,7 @@ impl<'a, T> ops::Index<&'a usize> for Map<T> {
     }
 }
 
-impl<'a, T> ops::IndexMut<&'a usize> for Map<T> {
+impl<'a, T, A: Allocator + Clone> ops::IndexMut<&'a usize> for Map<T, A> {
     #[inline]
     fn index_mut(&mut self, i: &'a usize) -> &mut T {
         self.get_mut(i).expect("key not present")
@@ -612,6 +1442,7 @@ impl<T:Clone> Clone for InternalNode<T> {
         let ch = &self.children;
         InternalNode {
             count: self.count,
+            full: self.full,
              children: [ch[0].clone(), ch[1].clone(), ch[2].clone(), ch[3].clone(),
                         ch[4].clone(), ch[5].clone(), ch[6].clone(), ch[7].clone(),
                         ch[8].clone(), ch[9].clone(), ch[10].clone(), ch[11].clone(),
@@ -621,153 +1452,77 @@ impl<T:Clone> Clone for InternalNode<T> {
 
 impl<T> InternalNode<T> {
     #[inline]
-    fn new() -> InternalNode<T> {
+    pub(crate) fn new() -> InternalNode<T> {
         // FIXME: #5244: [Nothing, ..SIZE] should be possible without implicit
         // copyability
-        InternalNode{count: 0,
+        InternalNode{count: 0, full: 0,
                  children: [Nothing, Nothing, Nothing, Nothing,
                             Nothing, Nothing, Nothing, Nothing,
                             Nothing, Nothing, Nothing, Nothing,
                             Nothing, Nothing, Nothing, Nothing]}
     }
-}
 
-impl<T> InternalNode<T> {
-    fn each_reverse<'a, F>(&'a self, f: &mut F) -> bool
-        where F: FnMut(&usize, &'a T) -> bool {
-        for elt in self.children.iter().rev() {
-            match *elt {
-                Internal(ref x) => if !x.each_reverse(f) { return false },
-                External(k, ref v) => if !f(&k, v) { return false },
-                Nothing => ()
+    // Recomputes `full` from the children, which are at depth `idx`.
+    pub(crate) fn update_full<A: Allocator + Clone>(&mut self, nodes: &Arena<T, A>, idx: usize) {
+        let mut full = 0;
+        for (slot, child) in self.children.iter().enumerate() {
+            if is_full(nodes, child, idx) {
+                full |= 1 << slot;
             }
         }
-        true
+        self.full = full;
     }
 }
 
-// if this was done via a trait, the key could be generic
+// Returns whether every key that can be stored below `child`, which is at depth `idx`, is in the
+// map. An external node holds a single key, so it only fills its slot at the bottom level.
 #[inline]
-fn chunk(n: usize, idx: usize) -> usize {
-    let sh = USIZE_BITS - (SHIFT * (idx + 1));
-    (n >> sh) & MASK
-}
-
-fn find_mut<T>(child: &mut TrieNode<T>, key: usize, idx: usize) -> Option<&mut T> {
+pub(crate) fn is_full<T, A>(nodes: &Arena<T, A>, child: &TrieNode<T>, idx: usize) -> bool
+    where A: Allocator + Clone {
     match *child {
-        External(stored, ref mut value) if stored == key => Some(value),
-        External(..) => None,
-        Internal(ref mut x) => find_mut(&mut x.children[chunk(key, idx)], key, idx + 1),
-        Nothing => None
+        Internal(id) => nodes[id].full == FULL,
+        External(..) => idx == MAX_DEPTH - 1,
+        Nothing => false,
     }
 }
 
-/// Inserts a new node for the given key and value, at or below `start_node`.
-///
-/// The index (`idx`) is the index of the next node, such that the start node
-/// was accessed via parent.children[chunk(key, idx - 1)].
-///
-/// The count is the external node counter for the start node's parent,
-/// which will be incremented only if `start_node` is transformed into a *new* external node.
-///
-/// Returns a mutable reference to the inserted value and an optional previous value.
-fn insert<'a, T>(count: &mut usize, start_node: &'a mut TrieNode<T>, key: usize, value: T, idx: usize)
-    -> (&'a mut T, Option<T>) {
-    // We branch twice to avoid having to do the `replace` when we
-    // don't need to; this is much faster, especially for keys that
-    // have long shared prefixes.
-
-    let mut hack = false;
-    match *start_node {
-        Nothing => {
-            *count += 1;
-            *start_node = External(key, value);
-            match *start_node {
-                External(_, ref mut value_ref) => return (value_ref, None),
-                _ => unreachable!()
-            }
-        }
-        Internal(ref mut x) => {
-            let x = &mut **x;
-            return insert(&mut x.count, &mut x.children[chunk(key, idx)], key, value, idx + 1);
-        }
-        External(stored_key, _) if stored_key == key => {
-            hack = true;
-        }
-        _ => {}
-    }
-
-    if !hack {
-        // Conflict, an external node with differing keys.
-        // We replace the old node by an internal one, then re-insert the two values beneath it.
-        match mem::replace(start_node, Internal(Box::new(InternalNode::new()))) {
-            External(stored_key, stored_value) => {
-                match *start_node {
-                    Internal(ref mut new_node) => {
-                        let new_node = &mut **new_node;
-                        // Re-insert the old value.
-                        insert(&mut new_node.count,
-                               &mut new_node.children[chunk(stored_key, idx)],
-                               stored_key, stored_value, idx + 1);
-
-                        // Insert the new value, and return a reference to it directly.
-                        return insert(&mut new_node.count,
-                               &mut new_node.children[chunk(key, idx)],
-                               key, value, idx + 1);
-                    }
-                    // Value that was just copied disappeared.
-                    _ => unreachable!()
-                }
-            }
-            // Logic error in previous match.
-            _ => unreachable!(),
+fn each_reverse<'a, T, A, F>(nodes: &'a Arena<T, A>, node: &'a InternalNode<T>, f: &mut F) -> bool
+    where A: Allocator + Clone, F: FnMut(&usize, &'a T) -> bool {
+    for elt in node.children.iter().rev() {
+        match *elt {
+            Internal(id) => if !each_reverse(nodes, &nodes[id], f) { return false },
+            External(k, ref v) => if !f(&k, v) { return false },
+            Nothing => ()
         }
     }
+    true
+}
 
-    if let External(_, ref mut stored_value) = *start_node {
-            // Swap in the new value and return the old.
-            let old_value = mem::replace(stored_value, value);
-            return (stored_value, Some(old_value));
-    }
-
-    unreachable!();
+// if this was done via a trait, the key could be generic
+#[inline]
+pub(crate) fn chunk(n: usize, idx: usize) -> usize {
+    let sh = USIZE_BITS - (SHIFT * (idx + 1));
+    (n >> sh) & MASK
 }
 
-fn remove<T>(count: &mut usize, child: &mut TrieNode<T>, key: usize,
-             idx: usize) -> Option<T> {
-    let (ret, this) = match *child {
-      External(stored, _) if stored == key => {
-        match mem::replace(child, Nothing) {
-            External(_, value) => (Some(value), true),
-            _ => unreachable!()
-        }
-      }
-      External(..) => (None, false),
-      Internal(ref mut x) => {
-          let x = &mut **x;
-          let ret = remove(&mut x.count, &mut x.children[chunk(key, idx)],
-                           key, idx + 1);
-          (ret, x.count == 0)
-      }
-      Nothing => (None, false)
-    };
-
-    if this {
-        *child = Nothing;
-        *count -= 1;
-    }
-    return ret;
+// Returns the first and last key that can be stored below child `slot` of a node whose keys
+// all start with `prefix`, where `idx` is the depth of the children.
+#[inline]
+pub(crate) fn slot_bounds(prefix: usize, slot: usize, idx: usize) -> (usize, usize) {
+    let sh = USIZE_BITS - (SHIFT * (idx + 1));
+    let start = prefix | (slot << sh);
+    (start, start | ((1 << sh) - 1))
 }
 
 /// A view into a single entry in a map, which may be vacant or occupied.
-pub enum Entry<'a, T: 'a> {
+pub enum Entry<'a, T: 'a, A: Allocator + Clone + 'a = Global> {
     /// An occupied entry.
-    Occupied(OccupiedEntry<'a, T>),
+    Occupied(OccupiedEntry<'a, T, A>),
     /// A vacant entry.
-    Vacant(VacantEntry<'a, T>)
+    Vacant(VacantEntry<'a, T, A>)
 }
 
-impl<'a, T> Entry<'a, T> {
+impl<'a, T, A: Allocator + Clone> Entry<'a, T, A> {
     /// Ensures a value is in the entry by inserting the default if empty, and returns
     /// a mutable reference to the value in the entry.
     pub fn or_insert(self, default: T) -> &'a mut T {
@@ -785,139 +1540,259 @@ impl<'a, T> Entry<'a, T> {
             Vacant(entry) => entry.insert(default()),
         }
     }
+
+    /// Ensures a value is in the entry by inserting the result of the default function, which
+    /// is given the entry's key, if empty, and returns a mutable reference to the value in the
+    /// entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// assert_eq!(*map.entry(7).or_insert_with_key(|key| key * 2), 14);
+    /// ```
+    pub fn or_insert_with_key<F: FnOnce(usize) -> T>(self, default: F) -> &'a mut T {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default value if empty, and returns a
+    /// mutable reference to the value in the entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<Vec<&str>> = trie::Map::new();
+    /// map.entry(1).or_default().push("a");
+    /// map.entry(1).or_default().push("b");
+    /// assert_eq!(map[&1], ["a", "b"]);
+    /// ```
+    pub fn or_default(self) -> &'a mut T where T: Default {
+        self.or_insert_with(T::default)
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into
+    /// the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// map.entry(1).and_modify(|count| *count += 1).or_insert(0);
+    /// assert_eq!(map[&1], 0);
+    /// map.entry(1).and_modify(|count| *count += 1).or_insert(0);
+    /// assert_eq!(map[&1], 1);
+    /// ```
+    pub fn and_modify<F: FnOnce(&mut T)>(self, f: F) -> Entry<'a, T, A> {
+        match self {
+            Occupied(mut entry) => {
+                f(entry.get_mut());
+                Occupied(entry)
+            }
+            Vacant(entry) => Vacant(entry),
+        }
+    }
+
+    /// Sets the value of the entry, and returns an `OccupiedEntry`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// let entry = map.entry(1).insert_entry("a");
+    /// assert_eq!((entry.key(), *entry.get()), (1, "a"));
+    /// ```
+    pub fn insert_entry(self, value: T) -> OccupiedEntry<'a, T, A> {
+        match self {
+            Occupied(mut entry) => {
+                entry.insert(value);
+                entry
+            }
+            Vacant(entry) => entry.insert_entry(value),
+        }
+    }
+
+    /// Returns the key of the entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map: trie::Map<&str> = trie::Map::new();
+    /// assert_eq!(map.entry(5).key(), 5);
+    /// ```
+    pub fn key(&self) -> usize {
+        match *self {
+            Occupied(ref entry) => entry.key(),
+            Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    // Returns the closest entry after the entry's key if `after` is true, or before it
+    // otherwise.
+    pub(crate) fn neighbor(&self, after: bool) -> Option<(usize, &T)> {
+        match *self {
+            Occupied(ref entry) => entry.neighbor(after),
+            Vacant(ref entry) => entry.search_stack.neighbor(after),
+        }
+    }
 }
 
 /// A view into an occupied entry in a map.
-pub struct OccupiedEntry<'a, T: 'a> {
-    search_stack: SearchStack<'a, T>
+pub struct OccupiedEntry<'a, T: 'a, A: Allocator + Clone + 'a = Global> {
+    search_stack: SearchStack<'a, T, A>
 }
 
 /// A view into a vacant entry in a map.
-pub struct VacantEntry<'a, T: 'a> {
-    search_stack: SearchStack<'a, T>
+pub struct VacantEntry<'a, T: 'a, A: Allocator + Clone + 'a = Global> {
+    search_stack: SearchStack<'a, T, A>
 }
 
-/// A list of nodes encoding a path from the root of a map to a node.
+/// A list of nodes encoding a path from the root of a map to a slot.
 ///
 /// Invariants:
-/// * The last node is either `External` or `Nothing`.
-/// * Pointers at indexes less than `length` can be safely dereferenced.
-struct SearchStack<'a, T: 'a> {
-    map: &'a mut Map<T>,
+/// * `items[0]` is the root, and each later item is an internal child of the one before it.
+/// * The slot for `key` in the last node is either `External` or `Nothing`.
+struct SearchStack<'a, T: 'a, A: Allocator + Clone + 'a> {
+    map: &'a mut Map<T, A>,
     length: usize,
     key: usize,
-    items: [*mut TrieNode<T>; MAX_DEPTH]
+    items: [u32; MAX_DEPTH]
 }
 
-impl<'a, T> SearchStack<'a, T> {
-    /// Creates a new search-stack with empty entries.
-    fn new(map: &'a mut Map<T>, key: usize) -> SearchStack<'a, T> {
+impl<'a, T, A: Allocator + Clone> SearchStack<'a, T, A> {
+    /// Creates a new search-stack holding only the root.
+    fn new(map: &'a mut Map<T, A>, key: usize) -> SearchStack<'a, T, A> {
         SearchStack {
             map: map,
-            length: 0,
+            length: 1,
             key: key,
-            items: [ptr::null_mut(); MAX_DEPTH]
+            items: [ROOT; MAX_DEPTH]
         }
     }
 
-    fn push(&mut self, node: *mut TrieNode<T>) {
+    fn push(&mut self, node: u32) {
+        self.items[self.length] = node;
         self.length += 1;
-        self.items[self.length - 1] = node;
     }
 
-    fn peek(&self) -> *mut TrieNode<T> {
+    fn peek(&self) -> u32 {
         self.items[self.length - 1]
     }
 
-    fn peek_ref(&self) -> &'a mut TrieNode<T> {
-        let item = self.items[self.length - 1];
-        unsafe { &mut *item }
+    /// Returns the slot for the key in the last node.
+    fn slot(&self) -> &TrieNode<T> {
+        &self.map.node(self.peek()).children[chunk(self.key, self.length - 1)]
     }
 
-    fn pop_ref(&mut self) -> &'a mut TrieNode<T> {
-        self.length -= 1;
-        unsafe {
-            &mut *self.items[self.length]
-        }
+    fn slot_mut(&mut self) -> &mut TrieNode<T> {
+        let (node, idx) = (self.peek(), chunk(self.key, self.length - 1));
+        &mut self.map.node_mut(node).children[idx]
+    }
+
+    fn into_slot(self) -> &'a mut TrieNode<T> {
+        let (node, idx) = (self.peek(), chunk(self.key, self.length - 1));
+        &mut self.map.node_mut(node).children[idx]
     }
 
-    fn is_empty(&self) -> bool {
-        self.length == 0
+    /// Returns the closest entry after the key if `after` is true, or before it otherwise,
+    /// searching outwards from the end of the path rather than from the root.
+    fn neighbor(&self, after: bool) -> Option<(usize, &T)> {
+        // A vacant slot may hold another key, which is the closest on one side.
+        if let External(stored, ref value) = *self.slot() {
+            if stored != self.key && (stored > self.key) == after {
+                return Some((stored, value));
+            }
+        }
+        let nodes = &self.map.nodes;
+        for depth in (0..self.length).rev() {
+            let children = &self.map.node(self.items[depth]).children;
+            let slot = chunk(self.key, depth);
+            let found = if after {
+                children[slot + 1..].iter().find_map(|child| outermost(nodes, child, false))
+            } else {
+                children[..slot].iter().rev().find_map(|child| outermost(nodes, child, true))
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
     }
+}
 
-    fn get_ref(&self, idx: usize) -> &'a mut TrieNode<T> {
-        assert!(idx < self.length);
-        unsafe {
-            &mut *self.items[idx]
+// Returns the entry with the largest key at or below `child` if `last` is true, or the one
+// with the smallest key otherwise.
+fn outermost<'a, T, A>(nodes: &'a Arena<T, A>, mut child: &'a TrieNode<T>, last: bool)
+                       -> Option<(usize, &'a T)> where A: Allocator + Clone {
+    loop {
+        match *child {
+            Internal(id) => {
+                let mut children = nodes[id].children.iter();
+                let used = |child: &&TrieNode<T>| !matches!(**child, Nothing);
+                child = if last { children.rfind(used) } else { children.find(used) }?;
+            }
+            External(key, ref value) => return Some((key, value)),
+            Nothing => return None,
         }
     }
 }
 
 // Implementation of SearchStack creation logic.
 // Once a SearchStack has been created the Entry methods are relatively straight-forward.
-impl<T> Map<T> {
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Gets the given key's corresponding entry in the map like `entry`, first making sure
+    /// that the nodes needed to insert into it can be allocated. If they cannot, an error is
+    /// returned and the map is left unchanged. Otherwise, inserting into the entry never
+    /// allocates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// *map.try_entry(1).unwrap().or_insert(0) += 10;
+    /// assert_eq!(map[&1], 10);
+    /// ```
+    pub fn try_entry(&mut self, key: usize) -> Result<Entry<'_, T, A>, TryReserveError> {
+        let (node, idx) = self.search(key);
+        self.nodes.try_reserve(self.nodes_needed(node, idx, key))?;
+        Ok(self.entry(key))
+    }
+
     /// Gets the given key's corresponding entry in the map for in-place manipulation.
     #[inline]
-    pub fn entry(&mut self, key: usize) -> Entry<T> {
-        // Create an empty search stack.
+    pub fn entry(&mut self, key: usize) -> Entry<T, A> {
+        // Create a search stack holding the root.
         let mut search_stack = SearchStack::new(self, key);
 
-        // Unconditionally add the corresponding node from the first layer.
-        let first_node = &mut search_stack.map.root.children[chunk(key, 0)] as *mut _;
-        search_stack.push(first_node);
-
         // While no appropriate slot is found, keep descending down the Trie,
         // adding nodes to the search stack.
-        let search_successful: bool;
         loop {
-            match unsafe { next_child(search_stack.peek(), key, search_stack.length) } {
-                (Some(child), _) => search_stack.push(child),
-                (None, success) => {
-                    search_successful = success;
-                    break;
+            match *search_stack.slot() {
+                Internal(id) => search_stack.push(id),
+                // If the key doesn't match, node expansion will be done upon
+                // insertion. If it does match, we've found our node.
+                External(stored_key, _) if stored_key == key => {
+                    return Occupied(OccupiedEntry { search_stack: search_stack })
+                }
+                External(..) | Nothing => {
+                    return Vacant(VacantEntry { search_stack: search_stack })
                 }
             }
         }
-
-        if search_successful {
-            Occupied(OccupiedEntry { search_stack: search_stack })
-        } else {
-            Vacant(VacantEntry { search_stack: search_stack })
-        }
-    }
-}
-
-/// Get a mutable pointer to the next child of a node, given a key and an idx.
-///
-/// The idx is the index of the next child, such that `node` was accessed via
-/// parent.children[chunk(key, idx - 1)].
-///
-/// Returns a tuple with an optional mutable pointer to the next child, and
-/// a boolean flag to indicate whether the external key node was found.
-///
-/// This function is safe only if `node` points to a valid `TrieNode`.
-#[inline]
-unsafe fn next_child<T>(node: *mut TrieNode<T>, key: usize, idx: usize)
-    -> (Option<*mut TrieNode<T>>, bool) {
-    match *node {
-        // If the node is internal, tell the caller to descend further.
-        Internal(ref mut node_internal) => {
-            (Some(&mut node_internal.children[chunk(key, idx)] as *mut _), false)
-        },
-        // If the node is external or empty, the search is complete.
-        // If the key doesn't match, node expansion will be done upon
-        // insertion. If it does match, we've found our node.
-        External(stored_key, _) if stored_key == key => (None, true),
-        External(..) | Nothing => (None, false)
     }
 }
 
 // NB: All these methods assume a correctly constructed occupied entry (matching the given key).
-impl<'a, T> OccupiedEntry<'a, T> {
+impl<'a, T, A: Allocator + Clone> OccupiedEntry<'a, T, A> {
     /// Gets a reference to the value in the entry.
     #[inline]
     pub fn get(&self) -> &T {
-        match *self.search_stack.peek_ref() {
+        match *self.search_stack.slot() {
             External(_, ref value) => value,
             // Invalid SearchStack, non-external last node.
             _ => unreachable!()
@@ -927,7 +1802,7 @@ impl<'a, T> OccupiedEntry<'a, T> {
     /// Gets a mutable reference to the value in the entry.
     #[inline]
     pub fn get_mut(&mut self) -> &mut T {
-        match *self.search_stack.peek_ref() {
+        match *self.search_stack.slot_mut() {
             External(_, ref mut value) => value,
             // Invalid SearchStack, non-external last node.
             _ => unreachable!()
@@ -938,7 +1813,7 @@ impl<'a, T> OccupiedEntry<'a, T> {
     /// with a lifetime bound to the map itself.
     #[inline]
     pub fn into_mut(self) -> &'a mut T {
-        match *self.search_stack.peek_ref() {
+        match *self.search_stack.into_slot() {
             External(_, ref mut value) => value,
             // Invalid SearchStack, non-external last node.
             _ => unreachable!()
@@ -948,7 +1823,7 @@ impl<'a, T> OccupiedEntry<'a, T> {
     /// Sets the value of the entry, and returns the entry's old value.
     #[inline]
     pub fn insert(&mut self, value: T) -> T {
-        match *self.search_stack.peek_ref() {
+        match *self.search_stack.slot_mut() {
             External(_, ref mut stored_value) => {
                 mem::replace(stored_value, value)
             }
@@ -960,87 +1835,129 @@ impl<'a, T> OccupiedEntry<'a, T> {
     /// Takes the value out of the entry, and returns it.
     #[inline]
     pub fn remove(self) -> T {
-        // This function removes the external leaf-node, then unwinds the search-stack
-        // deleting now-childless ancestors.
-        let mut search_stack = self.search_stack;
-
-        // Extract the value from the leaf-node of interest.
-        let leaf_node = mem::replace(search_stack.pop_ref(), Nothing);
+        let search_stack = self.search_stack;
+        let path = &search_stack.items[..search_stack.length];
+        search_stack.map.remove_at(path, search_stack.key)
+    }
 
-        let value = match leaf_node {
-            External(_, value) => value,
-            // Invalid SearchStack, non-external last node.
-            _ => unreachable!()
-        };
+    /// Returns the key of the entry.
+    #[inline]
+    pub fn key(&self) -> usize {
+        self.search_stack.key
+    }
 
-        // Iterate backwards through the search stack, deleting nodes if they are childless.
-        // We compare each ancestor's parent count to 1 because each ancestor reached has just
-        // had one of its children deleted.
-        while !search_stack.is_empty() {
-            let ancestor = search_stack.pop_ref();
-            match *ancestor {
-                Internal(ref mut internal) => {
-                    // If stopping deletion, update the child count and break.
-                    if internal.count != 1 {
-                        internal.count -= 1;
-                        break;
-                    }
-                }
-                // Invalid SearchStack, non-internal ancestor node.
-                _ => unreachable!()
-            }
-            *ancestor = Nothing;
-        }
+    /// Takes the key and value out of the entry, and returns them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// map.insert(1, "a");
+    /// if let trie::map::Entry::Occupied(entry) = map.entry(1) {
+    ///     assert_eq!(entry.remove_entry(), (1, "a"));
+    /// }
+    /// assert!(map.is_empty());
+    /// ```
+    #[inline]
+    pub fn remove_entry(self) -> (usize, T) {
+        let key = self.key();
+        (key, self.remove())
+    }
 
-        // Decrement the length of the entire map, for the removed node.
-        search_stack.map.length -= 1;
+    /// Sets the value of the entry, and returns the entry's key and old value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// map.insert(1, "a");
+    /// if let trie::map::Entry::Occupied(entry) = map.entry(1) {
+    ///     assert_eq!(entry.replace_entry("b"), (1, "a"));
+    /// }
+    /// assert_eq!(map[&1], "b");
+    /// ```
+    #[inline]
+    pub fn replace_entry(mut self, value: T) -> (usize, T) {
+        (self.key(), self.insert(value))
+    }
 
-        value
+    // Returns the closest entry after the entry's key if `after` is true, or before it
+    // otherwise.
+    pub(crate) fn neighbor(&self, after: bool) -> Option<(usize, &T)> {
+        self.search_stack.neighbor(after)
     }
 }
 
-impl<'a, T> VacantEntry<'a, T> {
+impl<'a, T, A: Allocator + Clone> VacantEntry<'a, T, A> {
+    /// Set the vacant entry to the given value, returning an error instead of aborting if the
+    /// nodes needed to store it cannot be allocated. The map is left unchanged on error.
+    pub fn try_insert(self, value: T) -> Result<&'a mut T, TryReserveError> {
+        let map = &mut *self.search_stack.map;
+        let node = self.search_stack.items[self.search_stack.length - 1];
+        let needed = map.nodes_needed(node, self.search_stack.length - 1, self.search_stack.key);
+        map.nodes.try_reserve(needed)?;
+        Ok(self.insert(value))
+    }
+
     /// Set the vacant entry to the given value.
     pub fn insert(self, value: T) -> &'a mut T {
-        let search_stack = self.search_stack;
-        let old_length = search_stack.length;
+        self.insert_entry(value).into_mut()
+    }
+
+    /// Sets the vacant entry to the given value, and returns an `OccupiedEntry`.
+    pub fn insert_entry(self, value: T) -> OccupiedEntry<'a, T, A> {
+        let mut search_stack = self.search_stack;
         let key = search_stack.key;
 
         // Update the map's length for the new element.
         search_stack.map.length += 1;
 
-        // If there's only 1 node in the search stack, insert a new node below it at idx 1.
-        if old_length == 1 {
-            // Note: Small hack to appease the borrow checker. Can't mutably borrow root.count
-            let mut temp = search_stack.map.root.count;
-            let (value_ref, _) = insert(&mut temp, search_stack.get_ref(0), key, value, 1);
-            search_stack.map.root.count = temp;
-            value_ref
-        }
-        // Otherwise, find the predecessor of the last stack node, and insert as normal.
-        else {
-            match *search_stack.get_ref(old_length - 2) {
-                Internal(ref mut parent) => {
-                    let parent = &mut **parent;
-                    let (value_ref, _) = insert(&mut parent.count,
-                                                &mut parent.children[chunk(key, old_length - 1)],
-                                                key, value, old_length);
-                    value_ref
-                }
-                // Invalid SearchStack, non-internal ancestor node.
+        let (node, idx) = search_stack.map.place(search_stack.peek(), search_stack.length - 1,
+                                                 key, value);
+        // Extend the path through any nodes that were added to make room for the key.
+        while search_stack.peek() != node {
+            match *search_stack.slot() {
+                Internal(id) => search_stack.push(id),
                 _ => unreachable!()
             }
         }
+        debug_assert_eq!(search_stack.length - 1, idx);
+        OccupiedEntry { search_stack }
+    }
+
+    /// Returns the key of the entry.
+    #[inline]
+    pub fn key(&self) -> usize {
+        self.search_stack.key
+    }
+
+    /// Returns the key of the entry, giving up the entry.
+    #[inline]
+    pub fn into_key(self) -> usize {
+        self.search_stack.key
+    }
+
+    // Returns the entry for another key that occupies the slot the key would go in, if any.
+    pub(crate) fn occupant(&self) -> Option<(usize, &T)> {
+        match *self.search_stack.slot() {
+            External(stored, ref value) => Some((stored, value)),
+            _ => None,
+        }
     }
 }
 
 /// A forward iterator over a map.
 pub struct Iter<'a, T:'a> {
     stack: [slice::Iter<'a, TrieNode<T>>; MAX_DEPTH],
+    // The map's arena, which is only read through.
+    nodes: *mut InternalNode<T>,
     length: usize,
     remaining: usize,
 }
 
+unsafe impl<'a, T: Sync> Send for Iter<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for Iter<'a, T> {}
+
 impl<'a, T> Clone for Iter<'a, T> {
     #[cfg(target_pointer_width="32")]
     fn clone(&self) -> Iter<'a, T> {
@@ -1069,10 +1986,14 @@ impl<'a, T> Clone for Iter<'a, T> {
 /// values being mutable.
 pub struct IterMut<'a, T:'a> {
     stack: [slice::IterMut<'a, TrieNode<T>>; MAX_DEPTH],
+    nodes: *mut InternalNode<T>,
     length: usize,
     remaining: usize,
 }
 
+unsafe impl<'a, T: Send> Send for IterMut<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for IterMut<'a, T> {}
+
 /// A forward iterator over the keys of a map.
 pub struct Keys<'a, T: 'a>(Iter<'a, T>);
 
@@ -1116,8 +2037,9 @@ macro_rules! iterator_impl {
             // from failing to initialise correctly after calling these is a
             // segfault.
             #[cfg(target_pointer_width="32")]
-            unsafe fn new() -> $name<'a, T> {
+            unsafe fn new(nodes: *mut InternalNode<T>) -> $name<'a, T> {
                 $name {
+                    nodes,
                     remaining: 0,
                     length: 0,
                     // ick :( ... at least the compiler will tell us if we screwed up.
@@ -1135,8 +2057,9 @@ macro_rules! iterator_impl {
             }
 
             #[cfg(target_pointer_width="64")]
-            unsafe fn new() -> $name<'a, T> {
+            unsafe fn new(nodes: *mut InternalNode<T>) -> $name<'a, T> {
                 $name {
+                    nodes,
                     remaining: 0,
                     length: 0,
                     stack: [IntoIterator::into_iter((& $($mut_)*[])),
@@ -1182,6 +2105,7 @@ macro_rules! iterator_impl {
                 // to store the maximum depth of Internal nodes in the
                 // trie (8 on 32-bit platforms, 16 on 64-bit).
                 fn next(&mut self) -> Option<(usize, &'a $($mut_)* T)> {
+                    let nodes = self.nodes;
                     let start_ptr = self.stack.as_mut_ptr();
 
                     unsafe {
@@ -1202,11 +2126,12 @@ macro_rules! iterator_impl {
                                 None => write_ptr = write_ptr.offset(-1),
                                 Some(child) => {
                                     addr!(match *child {
-                                            Internal(ref $($mut_)* node) => {
+                                            Internal(id) => {
                                                 // going down a level, so push
                                                 // to the stack (this is the
                                                 // write referenced above)
-                                                *write_ptr = node.children.$iter();
+                                                let node = nodes.add(id as usize);
+                                                *write_ptr = (*node).children.$iter();
                                                 write_ptr = write_ptr.offset(1);
                                             }
                                             External(key, ref $($mut_)* value) => {
@@ -1245,7 +2170,7 @@ iterator_impl! { Iter, iter = iter, mutability = }
 iterator_impl! { IterMut, iter = iter_mut, mutability = mut }
 
 /// A bounded forward iterator over a map.
-pub struct Range<'a, T: 'a>(Iter<'a, T>);
+pub struct Range<'a, T: 'a>(pub(crate) Iter<'a, T>);
 
 impl<'a, T> Clone for Range<'a, T> {
     fn clone(&self) -> Range<'a, T> { Range(self.0.clone()) }
@@ -1267,13 +2192,13 @@ impl<'a, T> Iterator for RangeMut<'a, T> {
     fn size_hint(&self) -> (usize, Option<usize>) { (0, Some(self.0.remaining)) }
 }
 
-impl<'a, T> IntoIterator for &'a Map<T> {
+impl<'a, T, A: Allocator + Clone> IntoIterator for &'a Map<T, A> {
     type Item = (usize, &'a T);
     type IntoIter = Iter<'a, T>;
     fn into_iter(self) -> Iter<'a, T> { self.iter() }
 }
 
-impl<'a, T> IntoIterator for &'a mut Map<T> {
+impl<'a, T, A: Allocator + Clone> IntoIterator for &'a mut Map<T, A> {
     type Item = (usize, &'a mut T);
     type IntoIter = IterMut<'a, T>;
     fn into_iter(self) -> IterMut<'a, T> { self.iter_mut() }
@@ -1281,24 +2206,46 @@ impl<'a, T> IntoIterator for &'a mut Map<T> {
 
 #[cfg(test)]
 mod test {
+    use std::string::ToString;
+    use std::vec::Vec;
+
     use std::usize;
+    #[cfg(feature = "allocator-api2")]
+    use std::alloc::Layout;
+    #[cfg(feature = "allocator-api2")]
+    use std::cell::Cell;
     use std::hash::{Hash, Hasher};
     use std::collections::hash_map::DefaultHasher;
+    #[cfg(feature = "allocator-api2")]
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+
+    #[cfg(feature = "allocator-api2")]
+    use allocator_api2::alloc::AllocError;
 
-    use super::{Map, InternalNode};
+    #[cfg(feature = "allocator-api2")]
+    use super::Global;
+    use super::{Allocator, Arena, Map, InternalNode, FULL, MAX_DEPTH, USIZE_BITS};
     use super::Entry::*;
     use super::TrieNode::*;
 
-    fn check_integrity<T>(trie: &InternalNode<T>) {
+    fn check_integrity<T, A: Allocator + Clone>(trie: &Map<T, A>) {
+        // Every node in use in the arena must be reachable from the root.
+        assert_eq!(check_node(&trie.nodes, &trie.root), trie.nodes.len());
+        assert_eq!(trie.validate(), Ok(()));
+    }
+
+    fn check_node<T, A: Allocator + Clone>(nodes: &Arena<T, A>, trie: &InternalNode<T>) -> usize {
         assert!(trie.count != 0);
 
         let mut sum = 0;
+        let mut reachable = 0;
 
         for x in trie.children.iter() {
             match *x {
               Nothing => (),
-              Internal(ref y) => {
-                  check_integrity(&**y);
+              Internal(id) => {
+                  reachable += 1 + check_node(nodes, &nodes[id]);
                   sum += 1
               }
               External(_, _) => { sum += 1 }
@@ -1306,6 +2253,7 @@ mod test {
         }
 
         assert_eq!(sum, trie.count);
+        reachable
     }
 
     #[test]
@@ -1339,31 +2287,31 @@ mod test {
         for x in (1..n).step_by(2) {
             assert!(trie.insert(x, x + 1).is_none());
             assert!(trie.contains_key(&x));
-            check_integrity(&trie.root);
+            check_integrity(&trie);
         }
 
         for x in (0..n).step_by(2) {
             assert!(!trie.contains_key(&x));
             assert!(trie.insert(x, x + 1).is_none());
-            check_integrity(&trie.root);
+            check_integrity(&trie);
         }
 
         for x in 0..n {
             assert!(trie.contains_key(&x));
             assert!(!trie.insert(x, x + 1).is_none());
-            check_integrity(&trie.root);
+            check_integrity(&trie);
         }
 
         for x in (1..n).step_by(2) {
             assert!(trie.remove(&x).is_some());
             assert!(!trie.contains_key(&x));
-            check_integrity(&trie.root);
+            check_integrity(&trie);
         }
 
         for x in (0..n).step_by(2) {
             assert!(trie.contains_key(&x));
             assert!(!trie.insert(x, x + 1).is_none());
-            check_integrity(&trie.root);
+            check_integrity(&trie);
         }
     }
 
@@ -1425,6 +2373,178 @@ mod test {
         assert_eq!(m.remove(&1), None);
     }
 
+    #[test]
+    fn test_fullness() {
+        // The node whose children are three levels above the bottom, on the path to 0.
+        fn block(map: &Map<usize>) -> (&InternalNode<usize>, &InternalNode<usize>) {
+            let (mut parent, mut node) = (&map.root, &map.root);
+            for _ in 0..MAX_DEPTH - 3 {
+                parent = node;
+                node = match node.children[0] {
+                    Internal(id) => &map.nodes[id],
+                    _ => panic!("expected an internal node"),
+                };
+            }
+            (parent, node)
+        }
+
+        // Keys 0 to 4095 differ only in the last three chunks, so they fill that node.
+        let mut map = Map::new();
+        for key in 0..4096 {
+            map.insert(key, key);
+        }
+        check_integrity(&map);
+        let (parent, node) = block(&map);
+        assert_eq!((parent.full, node.full), (1, FULL));
+
+        assert_eq!(map.remove(&0x7d0), Some(0x7d0));
+        check_integrity(&map);
+        let (parent, node) = block(&map);
+        assert_eq!((parent.full, node.full), (0, FULL & !(1 << 7)));
+
+        *map.entry(0x7d0).or_insert(0) += 1;
+        check_integrity(&map);
+        assert_eq!(block(&map).1.full, FULL);
+
+        let sorted: Map<usize> = (0..4096).map(|key| (key, key)).collect();
+        check_integrity(&sorted);
+        assert_eq!(block(&sorted).1.full, FULL);
+    }
+
+    #[test]
+    fn test_arena() {
+        let mut m = Map::with_capacity(64);
+        for i in 0..1000 {
+            m.insert(i * 7, i);
+        }
+        check_integrity(&m);
+        let used = m.nodes.len();
+
+        // Removed nodes are reused rather than appended.
+        for i in 0..500 {
+            assert_eq!(m.remove(&(i * 7)), Some(i));
+        }
+        check_integrity(&m);
+        for i in 0..500 {
+            m.insert(i * 7, i);
+        }
+        check_integrity(&m);
+        assert_eq!(m.nodes.len(), used);
+
+        for i in 100..1000 {
+            m.remove(&(i * 7));
+        }
+        let capacity = m.capacity();
+        m.shrink_to_fit();
+        check_integrity(&m);
+        assert!(m.capacity() < capacity);
+        assert!((0..100).all(|i| m[&(i * 7)] == i));
+
+        m.clear();
+        assert!(m.is_empty() && m.nodes.len() == 0);
+        m.insert(1, 1);
+        assert_eq!(m.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_clear() {
+        // Values without drop glue are forgotten along with the nodes, which are kept.
+        let mut m: Map<usize> = (0..10_000).map(|i| (i * 31, i)).collect();
+        let capacity = m.capacity();
+        m.clear();
+        assert!(m.is_empty());
+        assert_eq!((m.nodes.slots(), m.nodes.released().len()), (0, 0));
+        assert_eq!(m.capacity(), capacity);
+        m.insert(5, 5);
+        check_integrity(&m);
+
+        // Other values are each dropped once, including with released nodes in the arena.
+        let value = Rc::new(());
+        let mut m: Map<Rc<()>> = (0..1000).map(|i| (i * 31, value.clone())).collect();
+        for i in 0..500 {
+            m.remove(&(i * 62));
+        }
+        assert_eq!(Rc::strong_count(&value), 501);
+        m.clear();
+        assert_eq!(Rc::strong_count(&value), 1);
+        assert_eq!(m.nodes.slots(), 0);
+    }
+
+    // Counts the bytes it has handed out that are still live, and fails rather than go over a
+    // limit.
+    #[cfg(feature = "allocator-api2")]
+    #[derive(Clone)]
+    struct Counting(Rc<Cell<usize>>, usize);
+
+    #[cfg(feature = "allocator-api2")]
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if self.0.get() + layout.size() > self.1 {
+                return Err(AllocError);
+            }
+            self.0.set(self.0.get() + layout.size());
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.set(self.0.get() - layout.size());
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_allocator() {
+        let live = Rc::new(Cell::new(0));
+        let mut m = Map::new_in(Counting(live.clone(), usize::MAX));
+        for i in 0..1000 {
+            m.insert(i, i);
+        }
+        check_integrity(&m);
+        assert!(live.get() >= m.nodes.len() * std::mem::size_of::<InternalNode<usize>>());
+
+        let copy = m.clone();
+        let before = live.get();
+        m.shrink_to_fit();
+        assert!(live.get() <= before);
+        let global: Map<usize> = (0..1000).map(|i| (i, i)).collect();
+        assert!(m == copy && m == global);
+
+        drop(m);
+        drop(copy);
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_try_insert() {
+        let mut m = Map::new_in(Counting(Rc::new(Cell::new(0)), 4096));
+        let mut n = 0;
+        let err = loop {
+            match m.try_insert(n * 3, n) {
+                Ok(old) => assert_eq!(old, None),
+                Err(err) => break err,
+            }
+            n += 1;
+        };
+        assert_eq!(err.to_string(), "failed to allocate trie nodes");
+        check_integrity(&m);
+        assert_eq!(m.len(), n);
+        assert!(m.keys().eq((0..n).map(|i| i * 3)));
+
+        // Nothing needs to be allocated to replace a value or to fill an empty slot.
+        assert_eq!(m.try_insert(0, 7), Ok(Some(0)));
+        assert_eq!(m.try_insert(usize::MAX, 7), Ok(None));
+
+        assert!(m.try_entry(n * 3).is_err());
+        match m.entry(n * 3) {
+            Vacant(entry) => assert!(entry.try_insert(n).is_err()),
+            Occupied(_) => panic!(),
+        }
+        check_integrity(&m);
+        assert_eq!(m.len(), n + 1);
+    }
+
     #[test]
     fn test_from_iter() {
         let xs = [(1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6)];
@@ -1436,6 +2556,53 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_from_sorted_iter() {
+        let keys: Vec<usize> = (0..2000).map(|i| i * i * 500).collect();
+        let map = Map::from_sorted_iter(keys.iter().map(|&k| (k, k + 1))).unwrap();
+        check_integrity(&map);
+        let mut expected = Map::new();
+        for &k in keys.iter() {
+            expected.insert(k, k + 1);
+        }
+        assert_eq!(map, expected);
+
+        assert_eq!(Map::<()>::from_sorted_iter(None).unwrap().len(), 0);
+        let err = Map::from_sorted_iter(vec![(1, ()), (5, ()), (5, ())]).unwrap_err();
+        assert_eq!(err.key(), 5);
+        assert_eq!(err.to_string(), "key 5 is not greater than the key before it");
+    }
+
+    #[test]
+    fn test_extend_sorted() {
+        // Ascending, then a duplicate that replaces a value, then out of order.
+        let pairs = [(1, 'a'), (5, 'b'), (90, 'c'), (90, 'd'), (2, 'e'), (!0, 'f'), (3, 'g')];
+        let mut map = Map::new();
+        map.extend(pairs.iter().cloned());
+        check_integrity(&map);
+        let mut expected = Map::new();
+        for &(k, v) in pairs.iter() {
+            expected.insert(k, v);
+        }
+        assert_eq!(map, expected);
+
+        // A map that is not empty takes the keys one at a time.
+        map.extend(vec![(0, 'h'), (4, 'i')]);
+        check_integrity(&map);
+        assert_eq!(map.keys().collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5, 90, !0]);
+
+        #[cfg(feature = "allocator-api2")]
+        {
+            let live = Rc::new(Cell::new(0));
+            let mut m = Map::new_in(Counting(live.clone(), usize::MAX));
+            m.extend((0..1000).map(|i| (i, i)));
+            check_integrity(&m);
+            assert!(live.get() >= m.nodes.len() * std::mem::size_of::<InternalNode<usize>>());
+            drop(m);
+            assert_eq!(live.get(), 0);
+        }
+    }
+
     #[test]
     fn test_keys() {
         let vec = [(1, 'a'), (2, 'b'), (3, 'c')];
@@ -1724,7 +2891,7 @@ mod test {
                 Vacant(_) => panic!("Key not found.")
             }
         }
-        check_integrity(&map.root);
+        check_integrity(&map);
     }
 
     #[test]
@@ -1742,7 +2909,7 @@ mod test {
             assert_eq!(map.get(&i).unwrap(), &(i * i * i));
         }
 
-        check_integrity(&map.root);
+        check_integrity(&map);
     }
 
     #[test]
@@ -1771,7 +2938,7 @@ mod test {
             }
         }
 
-        check_integrity(&map.root);
+        check_integrity(&map);
 
         // Check that the values for even keys remain unmodified.
         for i in (0..SQUARES_UPPER_LIM).step_by(2) {
@@ -1781,6 +2948,50 @@ mod test {
         assert_eq!(map.len(), SQUARES_UPPER_LIM / 2);
     }
 
+    #[test]
+    fn test_entry_keys() {
+        let mut map = squares_map();
+        for i in 0..SQUARES_UPPER_LIM * 2 {
+            let entry = map.entry(i);
+            assert_eq!(entry.key(), i);
+            match entry {
+                Occupied(e) => assert_eq!(e.key(), i),
+                Vacant(e) => assert_eq!(e.into_key(), i),
+            }
+        }
+    }
+
+    #[test]
+    fn test_entry_insert_entry() {
+        let mut map = Map::new();
+        // Each key splits the external node of the one before it at a deeper level.
+        for shift in (0..USIZE_BITS).step_by(4).rev() {
+            let key = 1 << shift;
+            let mut e = map.entry(key).insert_entry(key);
+            assert_eq!((e.key(), *e.get()), (key, key));
+            *e.get_mut() += 1;
+            assert_eq!(e.replace_entry(0), (key, key + 1));
+            check_integrity(&map);
+        }
+        let mut e = map.entry(1).insert_entry(7);
+        assert_eq!(e.insert(8), 7);
+        assert_eq!(e.remove_entry(), (1, 8));
+        assert_eq!(map.len(), USIZE_BITS / 4 - 1);
+        check_integrity(&map);
+    }
+
+    #[test]
+    fn test_entry_or() {
+        let mut map: Map<usize> = Map::new();
+        *map.entry(1).or_default() += 1;
+        *map.entry(1).or_default() += 1;
+        assert_eq!(*map.entry(2).or_insert_with_key(|key| key * 10), 20);
+        assert_eq!(*map.entry(2).or_insert_with_key(|_| unreachable!()), 20);
+        map.entry(3).and_modify(|_| unreachable!()).or_insert(3);
+        map.entry(3).and_modify(|value| *value *= 2).or_insert(0);
+        assert_eq!(map.iter().collect::<Vec<_>>(), [(1, &2), (2, &20), (3, &6)]);
+    }
+
     #[test]
     fn test_occupied_entry_set() {
         let mut map = squares_map();
@@ -1793,7 +3004,7 @@ mod test {
             }
             assert_eq!(map.get(&i).unwrap(), &(i * i * i));
         }
-        check_integrity(&map.root);
+        check_integrity(&map);
     }
 
     #[test]
@@ -1815,7 +3026,7 @@ mod test {
             assert_eq!(map.get(&i).unwrap(), &(i * i * i));
         }
 
-        check_integrity(&map.root);
+        check_integrity(&map);
         assert_eq!(map.len(), SQUARES_UPPER_LIM);
     }
 
@@ -1833,6 +3044,8 @@ mod test {
 
 #[cfg(test)]
 mod bench {
+    use std::vec::Vec;
+
     use rand::{weak_rng, Rng};
     use test::{Bencher, black_box};
 
@@ -1871,6 +3084,24 @@ mod bench {
         });
     }
 
+    // The difference between these two is the cost of `clear`, which does not grow with the
+    // size of the map for values that need no dropping.
+    #[bench]
+    fn bench_clone(b: &mut Bencher) {
+        let map = random_map(100_000);
+        b.iter(|| black_box(map.clone()));
+    }
+
+    #[bench]
+    fn bench_clone_and_clear(b: &mut Bencher) {
+        let map = random_map(100_000);
+        b.iter(|| {
+            let mut copy = map.clone();
+            copy.clear();
+            black_box(copy)
+        });
+    }
+
     #[bench]
     pub fn iter_20(b: &mut Bencher) {
         bench_iter(b, 20);
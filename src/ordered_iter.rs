@@ -36,3 +36,9 @@ impl<'a> OrderedSetIterator for set::Intersection<'a> {}
 impl<'a> OrderedSetIterator for set::SymmetricDifference<'a> {}
 
 impl<'a> OrderedSetIterator for set::Union<'a> {}
+
+impl<'a> OrderedSetIterator for set::UnionAll<'a> {}
+
+impl<'a> OrderedSetIterator for set::IntersectionAll<'a> {}
+
+impl<'a> OrderedSetIterator for set::Complement<'a> {}
@@ -0,0 +1,493 @@
+//! Parallel iteration and extension of maps and sets with rayon.
+//!
+//! A parallel iterator starts out owning the sixteen children of the root and is split in half
+//! between threads as rayon asks for more work. When only a single internal node is left, it is
+//! split at that node's children instead, so keys that share their leading chunks, such as
+//! small integers, are still divided evenly.
+//!
+//! `par_extend` and `collect` sort the items and divide them at the first depth where their keys
+//! differ, so small keys are divided as evenly as any others. The subtree for each slot there is
+//! built on its own thread, from the bottom up where it was empty, and then moved back into
+//! place.
+
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::Range;
+
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+
+use super::Set;
+use super::map::{chunk, shared_chunks, Allocator, InternalNode, Map, TrieNode};
+use super::map::TrieNode::*;
+use super::set::Bits;
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Gets a parallel iterator over the entries of the map.
+    ///
+    /// Unlike `iter`, the entries are not visited in order, though `collect` into a `Vec` still
+    /// keeps them sorted by key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let map: trie::Map<usize> = (0..1000).map(|key| (key, key * 2)).collect();
+    /// let sum: usize = map.par_iter().map(|(_, &value)| value).sum();
+    /// assert_eq!(sum, 999 * 1000);
+    /// ```
+    pub fn par_iter(&self) -> Iter<'_, T> {
+        Iter(Producer { nodes: self.nodes.as_slice(), children: &self.root.children })
+    }
+
+    /// Gets a parallel iterator over the entries of the map, with mutable references to the
+    /// values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let mut map: trie::Map<usize> = (0..1000).map(|key| (key, key)).collect();
+    /// map.par_iter_mut().for_each(|(key, value)| *value += key);
+    /// assert_eq!(map[&500], 1000);
+    /// ```
+    pub fn par_iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut(ProducerMut { nodes: self.nodes.as_mut_ptr(), children: &mut self.root.children })
+    }
+
+    /// Gets a parallel iterator over the keys of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let map: trie::Map<()> = (0..1000).map(|key| (key, ())).collect();
+    /// let keys: Vec<usize> = map.par_keys().collect();
+    /// assert_eq!(keys, (0..1000).collect::<Vec<_>>());
+    /// ```
+    pub fn par_keys(&self) -> Keys<'_, T> {
+        Keys(self.par_iter())
+    }
+
+    /// Gets a parallel iterator over the values of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let map: trie::Map<usize> = (0..1000).map(|key| (key, key % 10)).collect();
+    /// assert_eq!(map.par_values().filter(|&&value| value == 0).count(), 100);
+    /// ```
+    pub fn par_values(&self) -> Values<'_, T> {
+        Values(self.par_iter())
+    }
+}
+
+impl Set {
+    /// Gets a parallel iterator over the values in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let set: trie::Set = (0..1000).collect();
+    /// assert_eq!(set.par_iter().filter(|value| value % 2 == 0).count(), 500);
+    /// ```
+    pub fn par_iter(&self) -> SetIter<'_> {
+        SetIter(self.map.par_iter())
+    }
+}
+
+/// A parallel iterator over the entries of a map.
+pub struct Iter<'a, T: 'a>(Producer<'a, T>);
+
+/// A parallel iterator over the entries of a map, with mutable references to the values.
+pub struct IterMut<'a, T: 'a>(ProducerMut<'a, T>);
+
+/// A parallel iterator over the keys of a map.
+pub struct Keys<'a, T: 'a>(Iter<'a, T>);
+
+/// A parallel iterator over the values of a map.
+pub struct Values<'a, T: 'a>(Iter<'a, T>);
+
+/// A parallel iterator over the values in a set.
+pub struct SetIter<'a>(Iter<'a, u64>);
+
+impl<'a, T: Sync> ParallelIterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item> {
+        bridge_unindexed(self.0, consumer)
+    }
+}
+
+impl<'a, T: Send> ParallelIterator for IterMut<'a, T> {
+    type Item = (usize, &'a mut T);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item> {
+        bridge_unindexed(self.0, consumer)
+    }
+}
+
+impl<'a, T: Sync> ParallelIterator for Keys<'a, T> {
+    type Item = usize;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item> {
+        self.0.map(|(key, _)| key).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, T: Sync> ParallelIterator for Values<'a, T> {
+    type Item = &'a T;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item> {
+        self.0.map(|(_, value)| value).drive_unindexed(consumer)
+    }
+}
+
+impl<'a> ParallelIterator for SetIter<'a> {
+    type Item = usize;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item> {
+        self.0.flat_map_iter(|(key, &word)| Bits::new(key, word)).drive_unindexed(consumer)
+    }
+}
+
+impl<'a, T: Sync, A: Allocator + Clone> IntoParallelIterator for &'a Map<T, A> {
+    type Item = (usize, &'a T);
+    type Iter = Iter<'a, T>;
+    fn into_par_iter(self) -> Iter<'a, T> { self.par_iter() }
+}
+
+impl<'a, T: Send, A: Allocator + Clone> IntoParallelIterator for &'a mut Map<T, A> {
+    type Item = (usize, &'a mut T);
+    type Iter = IterMut<'a, T>;
+    fn into_par_iter(self) -> IterMut<'a, T> { self.par_iter_mut() }
+}
+
+impl<'a> IntoParallelIterator for &'a Set {
+    type Item = usize;
+    type Iter = SetIter<'a>;
+    fn into_par_iter(self) -> SetIter<'a> { self.par_iter() }
+}
+
+// A run of sibling slots, along with the arena their internal nodes point into.
+struct Producer<'a, T: 'a> {
+    nodes: &'a [InternalNode<T>],
+    children: &'a [TrieNode<T>],
+}
+
+impl<'a, T: Sync> UnindexedProducer for Producer<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let nodes = self.nodes;
+        let mut children = &self.children[occupied(self.children)];
+        while let [Internal(id)] = *children {
+            let below = &nodes[id as usize].children;
+            children = &below[occupied(below)];
+        }
+        if children.len() < 2 {
+            return (Producer { nodes, children }, None);
+        }
+        let (left, right) = children.split_at(children.len() / 2);
+        (Producer { nodes, children: left }, Some(Producer { nodes, children: right }))
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, folder: F) -> F {
+        fold(self.nodes, self.children, folder)
+    }
+}
+
+fn fold<'a, T, F>(nodes: &'a [InternalNode<T>], children: &'a [TrieNode<T>], mut folder: F) -> F
+    where F: Folder<(usize, &'a T)> {
+    for child in children {
+        folder = match *child {
+            Internal(id) => fold(nodes, &nodes[id as usize].children, folder),
+            External(key, ref value) => folder.consume((key, value)),
+            Nothing => folder,
+        };
+        if folder.full() { break }
+    }
+    folder
+}
+
+// Like `Producer`, but the arena is reached through a pointer so that producers on different
+// threads can borrow different nodes mutably. Every node has exactly one parent slot, so no
+// node is reachable from two producers.
+struct ProducerMut<'a, T: 'a> {
+    nodes: *mut InternalNode<T>,
+    children: &'a mut [TrieNode<T>],
+}
+
+unsafe impl<'a, T: Send> Send for ProducerMut<'a, T> {}
+
+impl<'a, T: Send> UnindexedProducer for ProducerMut<'a, T> {
+    type Item = (usize, &'a mut T);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let nodes = self.nodes;
+        let range = occupied(self.children);
+        let mut children = &mut self.children[range];
+        while let [Internal(id)] = *children {
+            let below = unsafe { &mut (*nodes.add(id as usize)).children };
+            let range = occupied(below);
+            children = &mut below[range];
+        }
+        if children.len() < 2 {
+            return (ProducerMut { nodes, children }, None);
+        }
+        let (left, right) = children.split_at_mut(children.len() / 2);
+        (ProducerMut { nodes, children: left }, Some(ProducerMut { nodes, children: right }))
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, folder: F) -> F {
+        unsafe { fold_mut(self.nodes, self.children, folder) }
+    }
+}
+
+// Safety: `children` and the nodes below them must not be borrowed anywhere else.
+unsafe fn fold_mut<'a, T, F>(nodes: *mut InternalNode<T>, children: &'a mut [TrieNode<T>],
+                             mut folder: F) -> F
+    where F: Folder<(usize, &'a mut T)> {
+    for child in children {
+        folder = match *child {
+            Internal(id) => fold_mut(nodes, &mut (*nodes.add(id as usize)).children, folder),
+            External(key, ref mut value) => folder.consume((key, value)),
+            Nothing => folder,
+        };
+        if folder.full() { break }
+    }
+    folder
+}
+
+// The range of `children` between the first and last slots that are not empty.
+fn occupied<T>(children: &[TrieNode<T>]) -> Range<usize> {
+    let is_used = |child: &TrieNode<T>| !matches!(*child, Nothing);
+    let start = children.iter().position(is_used).unwrap_or(children.len());
+    let end = children.iter().rposition(is_used).map_or(start, |last| last + 1);
+    start..end
+}
+
+impl<T: Send> FromParallelIterator<(usize, T)> for Map<T> {
+    fn from_par_iter<I>(iter: I) -> Map<T> where I: IntoParallelIterator<Item=(usize, T)> {
+        let mut map = Map::new();
+        map.par_extend(iter);
+        map
+    }
+}
+
+impl<T: Send, A: Allocator + Clone + Send> ParallelExtend<(usize, T)> for Map<T, A> {
+    fn par_extend<I>(&mut self, iter: I) where I: IntoParallelIterator<Item=(usize, T)> {
+        // The sort is stable, so when a key is repeated the value that came last is kept, as
+        // with `extend`.
+        let mut items: Vec<(usize, T)> = iter.into_par_iter().collect();
+        items.par_sort_by_key(|&(key, _)| key);
+        items.dedup_by(|later, earlier| {
+            if later.0 != earlier.0 {
+                return false;
+            }
+            mem::swap(later, earlier);
+            true
+        });
+        if items.len() < 2 {
+            self.extend(items);
+            return;
+        }
+        let (idx, batches) = split(items);
+
+        // The subtrees that will receive new keys are moved into maps of their own, which
+        // allocate from the same allocator, so that each can be built on a separate thread and
+        // then moved back.
+        self.open_path(batches[0].0, idx);
+        let work: Vec<_> = batches.into_iter()
+            .map(|(key, batch)| (key, self.detach_slot(key, idx), batch))
+            .collect();
+        let parts: Vec<(usize, Map<T, A>)> = work.into_par_iter()
+            .map(|(key, mut part, batch)| {
+                part.extend(batch);
+                (key, part)
+            })
+            .collect();
+        for (key, part) in parts {
+            self.attach_slot(key, idx, part);
+        }
+    }
+}
+
+// Items sorted by key, with the first of their keys.
+type Batch<T> = (usize, Vec<(usize, T)>);
+
+// Divides at least two items with distinct keys, sorted by key, at the first depth where the
+// keys differ. Returns that depth and the items below each slot there, with the first key of
+// each batch.
+fn split<T>(mut items: Vec<(usize, T)>) -> (usize, Vec<Batch<T>>) {
+    let idx = shared_chunks(items[0].0, items[items.len() - 1].0);
+    let mut batches = Vec::new();
+    while let Some(&(last, _)) = items.last() {
+        let start = items.partition_point(|&(key, _)| chunk(key, idx) < chunk(last, idx));
+        let batch = items.split_off(start);
+        batches.push((batch[0].0, batch));
+    }
+    batches.reverse();
+    (idx, batches)
+}
+
+#[cfg(test)]
+mod test {
+    use rayon::prelude::*;
+    use std::vec::Vec;
+
+    use crate::{Map, Set};
+    use crate::map::USIZE_BITS;
+    use super::split;
+
+    fn keys() -> Vec<usize> {
+        // Small keys share a long chain below slot 0, the others are spread across the root.
+        let mut keys: Vec<usize> = (0..5000).collect();
+        keys.extend((0..5000).map(|i: usize| i.wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize)));
+        keys.extend((0..16).map(|i| i << (USIZE_BITS - 4)));
+        keys
+    }
+
+    #[test]
+    fn test_par_iter() {
+        let map: Map<usize> = keys().into_iter().map(|key| (key, !key)).collect();
+        let entries: Vec<(usize, &usize)> = map.par_iter().collect();
+        assert_eq!(entries, map.iter().collect::<Vec<_>>());
+        assert_eq!(map.par_keys().collect::<Vec<_>>(), map.keys().collect::<Vec<_>>());
+        assert_eq!(map.par_values().collect::<Vec<_>>(), map.values().collect::<Vec<_>>());
+        assert_eq!((&map).into_par_iter().count(), map.len());
+        assert_eq!(map.par_iter().find_any(|&(key, _)| key == 4999), Some((4999, &!4999)));
+
+        let empty: Map<usize> = Map::new();
+        assert_eq!(empty.par_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_par_iter_mut() {
+        let mut map: Map<usize> = keys().into_iter().map(|key| (key, 0)).collect();
+        map.par_iter_mut().for_each(|(key, value)| *value = key / 2);
+        assert!(map.iter().all(|(key, &value)| value == key / 2));
+        (&mut map).into_par_iter().for_each(|(_, value)| *value += 1);
+        assert!(map.iter().all(|(key, &value)| value == key / 2 + 1));
+    }
+
+    #[test]
+    fn test_set_par_iter() {
+        let set: Set = keys().into_iter().collect();
+        assert_eq!(set.par_iter().collect::<Vec<_>>(), set.iter().collect::<Vec<_>>());
+        assert_eq!((&set).into_par_iter().count(), set.len());
+    }
+
+    #[test]
+    fn test_from_par_iter() {
+        let map: Map<usize> = keys().into_par_iter().map(|key| (key, key + 1)).collect();
+        let expected: Map<usize> = keys().into_iter().map(|key| (key, key + 1)).collect();
+        assert_eq!(map, expected);
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_par_extend() {
+        let keys = keys();
+        let mut map: Map<usize> = keys.iter().step_by(3).map(|&key| (key, 0)).collect();
+        let mut expected = map.clone();
+        map.par_extend(keys.par_iter().step_by(2).map(|&key| (key, 1)));
+        expected.extend(keys.iter().step_by(2).map(|&key| (key, 1)));
+        assert_eq!(map, expected);
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_split() {
+        // Keys that share all but their last four chunks are divided between sixteen slots.
+        let items: Vec<(usize, ())> = (0..1 << 16).map(|key| (key, ())).collect();
+        let (idx, batches) = split(items);
+        assert_eq!(idx, USIZE_BITS / 4 - 4);
+        assert_eq!(batches.len(), 16);
+        for (slot, (first, batch)) in batches.into_iter().enumerate() {
+            assert_eq!(first, slot << 12);
+            assert!(batch.iter().map(|&(key, _)| key).eq(first..first + (1 << 12)));
+        }
+
+        let (idx, batches) = split(vec![(0x120, 'a'), (0x125, 'b'), (0x180, 'c')]);
+        assert_eq!(idx, USIZE_BITS / 4 - 2);
+        let sizes: Vec<(usize, usize)> =
+            batches.iter().map(|&(first, ref batch)| (first, batch.len())).collect();
+        assert_eq!(sizes, [(0x120, 2), (0x180, 1)]);
+    }
+
+    #[test]
+    fn test_par_extend_small_keys() {
+        let mut map: Map<usize> = (0..1000).map(|key| (key * 7, 0)).chain(vec![(!0, 0)]).collect();
+        let mut expected = map.clone();
+        map.par_extend((0..1 << 14).into_par_iter().map(|key| (key, key)));
+        expected.extend((0..1 << 14).map(|key| (key, key)));
+        assert_eq!(map, expected);
+        assert_eq!(map.validate(), Ok(()));
+
+        // The path to the keys is made of new nodes, one of which takes the place of a key.
+        let mut map: Map<usize> = vec![(0x1234_5678, 0)].into_iter().collect();
+        map.par_extend((0x1234_5600..0x1234_5700).into_par_iter().map(|key| (key, 1)));
+        assert_eq!(map.len(), 256);
+        assert_eq!(map[&0x1234_5678], 1);
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_par_extend_duplicates() {
+        // The last value for a key wins, as with `extend`.
+        let items: Vec<(usize, usize)> = (0..20000).map(|i| (i % 1000, i)).collect();
+        let map: Map<usize> = items.par_iter().cloned().collect();
+        assert_eq!(map.len(), 1000);
+        assert!(map.iter().all(|(key, &value)| value == 19000 + key));
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_par_extend_allocator() {
+        use std::alloc::Layout;
+        use std::collections::HashSet;
+        use std::ptr::NonNull;
+        use std::sync::{Arc, Mutex};
+        use std::thread::{self, ThreadId};
+
+        use allocator_api2::alloc::{AllocError, Allocator, Global};
+
+        // Records the threads that it allocates on.
+        #[derive(Clone)]
+        struct Recording(Arc<Mutex<HashSet<ThreadId>>>);
+
+        unsafe impl Allocator for Recording {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.lock().unwrap().insert(thread::current().id());
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let mut map = Map::new_in(Recording(threads.clone()));
+        map.par_extend(keys().into_par_iter().map(|key| (key, !key)));
+        let expected: Map<usize> = keys().into_iter().map(|key| (key, !key)).collect();
+        assert!(map.iter().eq(expected.iter()));
+        assert_eq!(map.validate(), Ok(()));
+        // The subtrees were built on rayon's threads, from the map's own allocator.
+        assert!(threads.lock().unwrap().iter().any(|&id| id != thread::current().id()));
+    }
+}
@@ -0,0 +1,218 @@
+extern crate serde;
+#[cfg(test)]
+extern crate serde_test;
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use self::serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use self::serde::ser::{Serialize, Serializer, SerializeMap, SerializeSeq};
+use super::map::Allocator;
+use super::{map, set, Map, Set};
+
+impl<T: Serialize, A: Allocator + Clone> Serialize for Map<T, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self.iter() {
+            state.serialize_entry(&key, value)?;
+        }
+        state.end()
+    }
+}
+
+impl Serialize for Set {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_seq(Some(self.len()))?;
+        for value in self.iter() {
+            state.serialize_element(&value)?;
+        }
+        state.end()
+    }
+}
+
+struct MapVisitor<T, A>(PhantomData<(T, A)>);
+
+impl<'de, T: Deserialize<'de>, A: Allocator + Clone + Default> Visitor<'de> for MapVisitor<T, A> {
+    type Value = Map<T, A>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map with integer keys")
+    }
+
+    // Entries are fed to the sorted builder for as long as their keys ascend. Once a key is out
+    // of order, the map built so far is finished and the remaining entries are inserted.
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Map<T, A>, M::Error> {
+        let mut builder = map::SortedBuilder::new_in(A::default());
+        while let Some((key, value)) = access.next_entry()? {
+            let (key, value) = match builder.push(key, value) {
+                Ok(()) => continue,
+                Err(entry) => entry,
+            };
+            if builder.last_key() == Some(key) {
+                return Err(duplicate_key(key));
+            }
+
+            let mut map = builder.finish();
+            map.insert(key, value);
+            while let Some((key, value)) = access.next_entry()? {
+                if map.insert(key, value).is_some() {
+                    return Err(duplicate_key(key));
+                }
+            }
+            return Ok(map);
+        }
+        Ok(builder.finish())
+    }
+}
+
+// Maps are deserialized into a new instance of their allocator.
+impl<'de, T: Deserialize<'de>, A: Allocator + Clone + Default> Deserialize<'de> for Map<T, A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Map<T, A>, D::Error> {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct SetVisitor;
+
+impl<'de> Visitor<'de> for SetVisitor {
+    type Value = Set;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of integers")
+    }
+
+    // See `MapVisitor::visit_map`.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Set, A::Error> {
+        let mut builder = set::SortedBuilder::new();
+        while let Some(value) = access.next_element()? {
+            let value = match builder.push(value) {
+                Ok(()) => continue,
+                Err(value) => value,
+            };
+            if builder.last_value() == Some(value) {
+                return Err(duplicate_value(value));
+            }
+
+            let mut set = builder.finish();
+            set.insert(value);
+            while let Some(value) = access.next_element()? {
+                if !set.insert(value) {
+                    return Err(duplicate_value(value));
+                }
+            }
+            return Ok(set);
+        }
+        Ok(builder.finish())
+    }
+}
+
+impl<'de> Deserialize<'de> for Set {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Set, D::Error> {
+        deserializer.deserialize_seq(SetVisitor)
+    }
+}
+
+fn duplicate_key<E: de::Error>(key: usize) -> E {
+    E::custom(format_args!("duplicate key {}", key))
+}
+
+fn duplicate_value<E: de::Error>(value: usize) -> E {
+    E::custom(format_args!("duplicate value {}", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::serde_test::{assert_de_tokens_error, assert_tokens, assert_de_tokens, Token};
+    use super::{Map, Set};
+
+    #[test]
+    fn test_map() {
+        let map: Map<char> = [(1, 'a'), (17, 'b'), (300, 'c')].iter().cloned().collect();
+        assert_tokens(&map, &[
+            Token::Map { len: Some(3) },
+            Token::U64(1), Token::Char('a'),
+            Token::U64(17), Token::Char('b'),
+            Token::U64(300), Token::Char('c'),
+            Token::MapEnd,
+        ]);
+        assert_tokens(&Map::<char>::new(), &[Token::Map { len: Some(0) }, Token::MapEnd]);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_map_allocator() {
+        use super::super::allocator::Forwarding;
+
+        let mut map: Map<char, Forwarding> = Map::new_in(Forwarding);
+        map.insert(2, 'a');
+        map.insert(40, 'b');
+        assert_tokens(&map, &[
+            Token::Map { len: Some(2) },
+            Token::U64(2), Token::Char('a'),
+            Token::U64(40), Token::Char('b'),
+            Token::MapEnd,
+        ]);
+    }
+
+    #[test]
+    fn test_map_unsorted() {
+        let map: Map<char> = [(1, 'a'), (17, 'b'), (300, 'c')].iter().cloned().collect();
+        assert_de_tokens(&map, &[
+            Token::Map { len: None },
+            Token::U64(17), Token::Char('b'),
+            Token::U64(300), Token::Char('c'),
+            Token::U64(1), Token::Char('a'),
+            Token::MapEnd,
+        ]);
+        assert_de_tokens_error::<Map<char>>(&[
+            Token::Map { len: None },
+            Token::U64(1), Token::Char('a'),
+            Token::U64(1), Token::Char('b'),
+        ], "duplicate key 1");
+        assert_de_tokens_error::<Map<char>>(&[
+            Token::Map { len: None },
+            Token::U64(5), Token::Char('a'),
+            Token::U64(2), Token::Char('b'),
+            Token::U64(5), Token::Char('c'),
+        ], "duplicate key 5");
+    }
+
+    #[test]
+    fn test_set() {
+        let set: Set = [3, 64, 65, 1000].iter().cloned().collect();
+        assert_tokens(&set, &[
+            Token::Seq { len: Some(4) },
+            Token::U64(3), Token::U64(64), Token::U64(65), Token::U64(1000),
+            Token::SeqEnd,
+        ]);
+        assert_de_tokens(&set, &[
+            Token::Seq { len: None },
+            Token::U64(64), Token::U64(1000), Token::U64(3), Token::U64(65),
+            Token::SeqEnd,
+        ]);
+        assert_de_tokens_error::<Set>(&[
+            Token::Seq { len: None },
+            Token::U64(3), Token::U64(3),
+        ], "duplicate value 3");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map: Map<usize> = (0..5000).map(|i| (i * 37 % 10007, i)).collect();
+        let mut tokens = vec![Token::Map { len: Some(map.len()) }];
+        for (k, &v) in map.iter() {
+            tokens.push(Token::U64(k as u64));
+            tokens.push(Token::U64(v as u64));
+        }
+        tokens.push(Token::MapEnd);
+        assert_tokens(&map, &tokens);
+
+        let set: Set = (0..5000).map(|i| i * i % 65537)
+            .chain(usize::MAX - 3..=usize::MAX)
+            .collect();
+        let mut tokens = vec![Token::Seq { len: Some(set.len()) }];
+        tokens.extend(set.iter().map(|v| Token::U64(v as u64)));
+        tokens.push(Token::SeqEnd);
+        assert_tokens(&set, &tokens);
+    }
+}
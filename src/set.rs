@@ -14,15 +14,36 @@
 
 //! An ordered set based on a trie.
 
-use std::cmp::Ordering::{self, Less, Equal, Greater};
-use std::fmt::{self, Debug};
-use std::iter::{self, Peekable};
-use std::ops;
-
-use super::map::{Map, self};
+pub use self::Entry::*;
+
+use alloc::vec::Vec;
+use core::cmp::Ordering::{self, Less, Equal, Greater};
+use core::error::Error;
+use core::fmt::{self, Debug, Display};
+use core::hash::{Hash, Hasher};
+use core::iter::{self, Peekable};
+use core::mem;
+use core::num::ParseIntError;
+use core::ops::{self, Bound, RangeBounds, RangeInclusive};
+use core::str::FromStr;
+
+use super::map::{Arena, Map, InternalNode, MASK, MAX_DEPTH, SHIFT, SIZE, TryReserveError,
+                 UnsortedError, chunk, slot_bounds, self};
+use super::map::TrieNode::{self, *};
+
+// Values are stored in 64-bit words: the high bits of a value select a word in the trie, and the
+// low `WORD_SHIFT` bits select a bit within it.
+pub(crate) const WORD_SHIFT: usize = 6;
+const WORD_MASK: usize = (1 << WORD_SHIFT) - 1;
 
 /// A set implemented as a radix trie.
 ///
+/// The bottom level of the trie is made of bitmap leaves: each external node stores a 64-bit word
+/// covering 64 consecutive values, so a dense range of values takes a single slot per 64
+/// elements rather than one slot per element. A sparse value costs one word. A range that
+/// covers whole slots of the trie stores a single marker for each slot instead, so even a set of
+/// every `usize` takes only a few nodes.
+///
 /// # Examples
 ///
 /// ```
@@ -48,9 +69,61 @@ use super::map::{Map, self};
 /// set.clear();
 /// assert!(set.is_empty());
 /// ```
-#[derive(Clone, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Default)]
 pub struct Set {
-    map: Map<()>
+    // Maps `value >> WORD_SHIFT` to the word of values sharing those high bits. A word of zero
+    // is a marker for a block of full words instead; see `block`.
+    pub(crate) map: Map<u64>,
+    // The number of values, which wraps to zero when the set holds every value.
+    pub(crate) length: usize
+}
+
+// A range of values may be stored as words in one set and as a marked block in another, so sets
+// are compared by their runs rather than by their tries.
+impl PartialEq for Set {
+    fn eq(&self, other: &Set) -> bool {
+        self.length == other.length && self.iter_runs().eq(other.iter_runs())
+    }
+}
+
+impl Eq for Set {}
+
+impl Hash for Set {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for run in self.iter_runs() {
+            run.hash(state);
+        }
+    }
+}
+
+impl PartialOrd for Set {
+    #[inline]
+    fn partial_cmp(&self, other: &Set) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Set {
+    // Compares the values in order, like `iter().cmp`, but a run at a time.
+    fn cmp(&self, other: &Set) -> Ordering {
+        let (mut a, mut b) = (self.iter_runs(), other.iter_runs());
+        let (mut x, mut y) = (a.next(), b.next());
+        loop {
+            let (run, other_run) = match (x, y) {
+                (Some(run), Some(other_run)) => (run, other_run),
+                (x, y) => return x.is_some().cmp(&y.is_some()),
+            };
+            if run.start() != other_run.start() {
+                return run.start().cmp(other_run.start());
+            }
+            // The longer run goes on to values that the other set only has later, if at all.
+            match run.end().cmp(other_run.end()) {
+                Equal => (x, y) = (a.next(), b.next()),
+                Less => (x, y) = (a.next(), Some(run.end() + 1..=*other_run.end())),
+                Greater => (x, y) = (Some(other_run.end() + 1..=*run.end()), b.next()),
+            }
+        }
+    }
 }
 
 impl Debug for Set {
@@ -59,6 +132,99 @@ impl Debug for Set {
     }
 }
 
+/// Formats the set as a comma-separated list of its runs, such as `1-5,7,9-12`.
+///
+/// # Examples
+///
+/// ```
+/// let set: trie::Set = [1, 2, 3, 4, 5, 7, 9, 10, 11, 12].iter().cloned().collect();
+/// assert_eq!(set.to_string(), "1-5,7,9-12");
+/// assert_eq!(trie::Set::new().to_string(), "");
+/// ```
+impl Display for Set {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, run) in self.iter_runs().enumerate() {
+            if i > 0 { f.write_str(",")?; }
+            if run.start() == run.end() {
+                write!(f, "{}", run.start())?;
+            } else {
+                write!(f, "{}-{}", run.start(), run.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the format produced by `Display`: a comma-separated list of values and inclusive
+/// ranges `a-b`. Whitespace around items is ignored, and runs may overlap or be out of order.
+///
+/// # Examples
+///
+/// ```
+/// let set: trie::Set = "9-12, 1-5,7".parse().unwrap();
+/// assert_eq!(set, [1, 2, 3, 4, 5, 7, 9, 10, 11, 12].iter().cloned().collect());
+///
+/// assert!("".parse::<trie::Set>().unwrap().is_empty());
+/// assert!("5-1".parse::<trie::Set>().is_err());
+/// assert!("1,,2".parse::<trie::Set>().is_err());
+/// ```
+impl FromStr for Set {
+    type Err = ParseSetError;
+
+    fn from_str(s: &str) -> Result<Set, ParseSetError> {
+        let mut set = Set::new();
+        if s.trim().is_empty() {
+            return Ok(set);
+        }
+        for item in s.split(',') {
+            let mut bounds = item.splitn(2, '-');
+            let start = parse_value(bounds.next().unwrap())?;
+            let end = match bounds.next() {
+                Some(end) => parse_value(end)?,
+                None => start,
+            };
+            if start > end {
+                return Err(ParseSetError::InvalidRange(start, end));
+            }
+            set.insert_range(start..=end);
+        }
+        Ok(set)
+    }
+}
+
+fn parse_value(s: &str) -> Result<usize, ParseSetError> {
+    s.trim().parse().map_err(ParseSetError::InvalidValue)
+}
+
+/// An error returned when parsing a `Set` from a string fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseSetError {
+    /// A value was missing or was not a valid `usize`.
+    InvalidValue(ParseIntError),
+    /// A range ended before it started.
+    InvalidRange(usize, usize),
+}
+
+impl Display for ParseSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseSetError::InvalidValue(ref err) => write!(f, "invalid value: {}", err),
+            ParseSetError::InvalidRange(start, end) => {
+                write!(f, "invalid range: {} is greater than {}", start, end)
+            }
+        }
+    }
+}
+
+impl Error for ParseSetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ParseSetError::InvalidValue(ref err) => Some(err),
+            ParseSetError::InvalidRange(..) => None,
+        }
+    }
+}
+
 impl Set {
     /// Creates an empty set.
     ///
@@ -69,7 +235,50 @@ impl Set {
     /// ```
     #[inline]
     pub fn new() -> Set {
-        Set{map: Map::new()}
+        Set{map: Map::new(), length: 0}
+    }
+
+    /// Builds a set from values in strictly ascending order.
+    ///
+    /// Like `Map::from_sorted_iter`, this builds the trie from the bottom up in a single pass
+    /// rather than inserting the values one at a time.
+    ///
+    /// Returns an error holding the first value that is not greater than the value before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set = trie::Set::from_sorted_iter(vec![1, 3, 7, 1000]).unwrap();
+    /// assert_eq!(set.len(), 4);
+    /// assert!(set.contains(&7));
+    ///
+    /// let err = trie::Set::from_sorted_iter(vec![1, 7, 3]).unwrap_err();
+    /// assert_eq!(err.key(), 3);
+    /// ```
+    pub fn from_sorted_iter<I: IntoIterator<Item=usize>>(iter: I) -> Result<Set, UnsortedError> {
+        let mut builder = SortedBuilder::new();
+        for value in iter {
+            builder.push(value).map_err(|key| UnsortedError { key })?;
+        }
+        Ok(builder.finish())
+    }
+
+    /// Creates a set containing the values in each of the given ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set = trie::Set::from_ranges(vec![1..=5, 7..=7, 9..=12]);
+    /// assert_eq!(set.len(), 10);
+    /// assert_eq!(set.to_string(), "1-5,7,9-12");
+    /// ```
+    pub fn from_ranges<I, R>(ranges: I) -> Set
+        where I: IntoIterator<Item=R>, R: RangeBounds<usize> {
+        let mut set = Set::new();
+        for range in ranges {
+            set.insert_range(range);
+        }
+        set
     }
 
     /// Visits all values in reverse order. Aborts traversal when `f` returns `false`.
@@ -91,7 +300,19 @@ impl Set {
     /// ```
     #[inline]
     pub fn each_reverse<F>(&self, mut f: F) -> bool where F: FnMut(&usize) -> bool {
-        self.map.each_reverse(|k, _| f(k))
+        self.map.each_reverse(|&key, &word| {
+            if word == 0 {
+                let (first, last) = leaf_span(key, 0);
+                return (first..=last).rev().all(|value| f(&value));
+            }
+            let mut word = word;
+            while word != 0 {
+                let bit = 63 - word.leading_zeros() as usize;
+                word &= !(1 << bit);
+                if !f(&(key << WORD_SHIFT | bit)) { return false }
+            }
+            true
+        })
     }
 
     /// Gets an iterator over the values in the set, in sorted order.
@@ -112,7 +333,20 @@ impl Set {
     /// ```
     #[inline]
     pub fn iter(&self) -> Iter {
-        Iter { iter: self.map.iter() }
+        Iter { iter: self.map.iter(), bits: Bits::empty(), remaining: self.len() }
+    }
+
+    /// Gets an iterator over the maximal runs of consecutive values in the set, in sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = [1, 2, 3, 7, 9, 10].iter().cloned().collect();
+    /// let runs: Vec<_> = set.iter_runs().collect();
+    /// assert_eq!(runs, [1..=3, 7..=7, 9..=10]);
+    /// ```
+    pub fn iter_runs(&self) -> Runs<'_> {
+        Runs { iter: self.map.iter(), bits: Bits::empty(), pending: None }
     }
 
     /// Gets an iterator pointing to the first value that is not less than `val`.
@@ -127,7 +361,17 @@ impl Set {
     /// assert_eq!(set.lower_bound(10).next(), None);
     /// ```
     pub fn lower_bound(&self, val: usize) -> Range {
-        Range { iter: self.map.lower_bound(val) }
+        // A marker may be stored under a greater key than the word `val` is in.
+        let key = self.marker_for(val >> WORD_SHIFT).unwrap_or(val >> WORD_SHIFT);
+        let mut iter = self.map.lower_bound(key);
+        let bits = match iter.clone().next() {
+            Some((stored, &word)) if stored == key => {
+                iter.next();
+                Bits::new(key, word).skip_below(val)
+            }
+            _ => Bits::empty(),
+        };
+        Range { iter, bits }
     }
 
     /// Gets an iterator pointing to the first value that key is greater than `val`.
@@ -142,7 +386,66 @@ impl Set {
     /// assert_eq!(set.upper_bound(10).next(), None);
     /// ```
     pub fn upper_bound(&self, val: usize) -> Range {
-        Range { iter: self.map.upper_bound(val) }
+        match val.checked_add(1) {
+            Some(val) => self.lower_bound(val),
+            None => Range { iter: self.map.upper_bound(usize::MAX), bits: Bits::empty() },
+        }
+    }
+
+    /// Visits the values within `range` that are not in the set, in ascending order.
+    ///
+    /// Absent values are found from the gaps between the words stored in the trie, so empty
+    /// slots are skipped over rather than each value being tested with `contains`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let used: trie::Set = [8000, 8001, 8003].iter().cloned().collect();
+    /// let free: Vec<usize> = used.complement_in(8000..8006).collect();
+    /// assert_eq!(free, [8002, 8004, 8005]);
+    /// ```
+    pub fn complement_in<R: RangeBounds<usize>>(&self, range: R) -> Complement<'_> {
+        Complement { gaps: self.gaps_in(range), gap: None }
+    }
+
+    /// Returns the values within `range` that are not in the set as a new set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let used: trie::Set = [2, 3, 7].iter().cloned().collect();
+    /// let free = used.to_complement_in(0..10);
+    /// assert_eq!(free.to_string(), "0-1,4-6,8-9");
+    /// ```
+    pub fn to_complement_in<R: RangeBounds<usize>>(&self, range: R) -> Set {
+        Set::from_ranges(self.gaps_in(range))
+    }
+
+    /// Returns the smallest value not less than `val` that is not in the set, or `None` if
+    /// every value from `val` upwards is present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let used: trie::Set = [1, 2, 3, 5].iter().cloned().collect();
+    /// assert_eq!(used.first_absent_from(0), Some(0));
+    /// assert_eq!(used.first_absent_from(1), Some(4));
+    /// assert_eq!(used.first_absent_from(5), Some(6));
+    /// ```
+    pub fn first_absent_from(&self, val: usize) -> Option<usize> {
+        self.gaps_in(val..).next().map(|gap| *gap.start())
+    }
+
+    // Gets an iterator over the maximal runs of absent values within `range`.
+    fn gaps_in<R: RangeBounds<usize>>(&self, range: R) -> Gaps<'_> {
+        match inclusive_bounds(&range) {
+            Some((lo, hi)) => {
+                let Range { iter, bits } = self.lower_bound(lo);
+                let runs = Runs { iter: iter.0, bits, pending: None };
+                Gaps { runs, next: Some(lo), hi }
+            }
+            None => Gaps { runs: self.iter_runs(), next: None, hi: 0 },
+        }
     }
 
     /// Visits the values representing the difference, in ascending order.
@@ -233,8 +536,99 @@ impl Set {
         Union { a: self.iter().peekable(), b: other.iter().peekable() }
     }
 
+    /// Visits the values representing the union of all the given sets, in ascending order.
+    ///
+    /// The tries are walked together node by node, so a slot that is only populated in
+    /// one of the inputs is visited once rather than being merged element by element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// let b: trie::Set = [3, 4, 5].iter().cloned().collect();
+    /// let c: trie::Set = [5, 60, 700].iter().cloned().collect();
+    ///
+    /// let v: Vec<usize> = trie::Set::union_all(&[a, b, c]).collect();
+    /// assert_eq!(v, [1, 2, 3, 4, 5, 60, 700]);
+    /// ```
+    pub fn union_all<'a, I>(sets: I) -> UnionAll<'a> where I: IntoIterator<Item=&'a Set> {
+        UnionAll { walk: Walk::new(sets) }
+    }
+
+    /// Visits the values representing the intersection of all the given sets, in ascending
+    /// order. The intersection of no sets is empty.
+    ///
+    /// The tries are walked together node by node, and a child slot that is empty in any of
+    /// the inputs is skipped without looking at the other inputs' subtrees.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3, 4].iter().cloned().collect();
+    /// let b: trie::Set = [2, 3, 4, 5].iter().cloned().collect();
+    /// let c: trie::Set = [0, 3, 4, 8].iter().cloned().collect();
+    ///
+    /// let v: Vec<usize> = trie::Set::intersection_all(&[a, b, c]).collect();
+    /// assert_eq!(v, [3, 4]);
+    /// ```
+    pub fn intersection_all<'a, I>(sets: I) -> IntersectionAll<'a>
+        where I: IntoIterator<Item=&'a Set> {
+        IntersectionAll { walk: Walk::new(sets) }
+    }
+
+    /// Returns the union of all the given sets as a new set.
+    ///
+    /// Subtrees that are only present in one of the inputs are cloned wholesale.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// let b: trie::Set = [3, 4, 5].iter().cloned().collect();
+    /// let c: trie::Set = [5, 60, 700].iter().cloned().collect();
+    ///
+    /// let set = trie::Set::union_of(&[a, b, c]);
+    /// assert_eq!(set, [1, 2, 3, 4, 5, 60, 700].iter().cloned().collect());
+    /// ```
+    pub fn union_of<'a, I>(sets: I) -> Set where I: IntoIterator<Item=&'a Set> {
+        let cursors = Walk::roots(sets);
+        let mut set = Set::new();
+        let (words, length) = union_node(&cursors, 0, &mut set.map.nodes, &mut set.map.root);
+        set.map.length = words;
+        set.length = length;
+        set
+    }
+
+    /// Returns the intersection of all the given sets as a new set.
+    /// The intersection of no sets is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3, 4].iter().cloned().collect();
+    /// let b: trie::Set = [2, 3, 4, 5].iter().cloned().collect();
+    /// let c: trie::Set = [0, 3, 4, 8].iter().cloned().collect();
+    ///
+    /// let set = trie::Set::intersection_of(&[a, b, c]);
+    /// assert_eq!(set, [3, 4].iter().cloned().collect());
+    /// ```
+    pub fn intersection_of<'a, I>(sets: I) -> Set where I: IntoIterator<Item=&'a Set> {
+        let cursors = Walk::roots(sets);
+        let mut set = Set::new();
+        if !cursors.is_empty() {
+            let (words, length) =
+                intersection_node(&cursors, 0, &mut set.map.nodes, &mut set.map.root);
+            set.map.length = words;
+            set.length = length;
+        }
+        set
+    }
+
     /// Return the number of elements in the set
     ///
+    /// A set holding every `usize` has one more element than a `usize` can count, and reports
+    /// `usize::MAX`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -244,7 +638,9 @@ impl Set {
     /// assert_eq!(v.len(), 1);
     /// ```
     #[inline]
-    pub fn len(&self) -> usize { self.map.len() }
+    pub fn len(&self) -> usize {
+        if self.length == 0 && !self.map.is_empty() { usize::MAX } else { self.length }
+    }
 
     /// Returns true if the set contains no elements
     ///
@@ -269,7 +665,10 @@ impl Set {
     /// assert!(v.is_empty());
     /// ```
     #[inline]
-    pub fn clear(&mut self) { self.map.clear() }
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.length = 0;
+    }
 
     /// Returns `true` if the set contains a value.
     ///
@@ -282,12 +681,15 @@ impl Set {
     /// ```
     #[inline]
     pub fn contains(&self, value: &usize) -> bool {
-        self.map.contains_key(value)
+        let (key, bit) = split(*value);
+        Cursor::root(self).word(key, 0) & bit != 0
     }
 
     /// Returns `true` if the set has no elements in common with `other`.
     /// This is equivalent to checking for an empty intersection.
     ///
+    /// The two tries are compared node by node, skipping any slot that is empty in either set.
+    ///
     /// # Examples
     ///
     /// ```
@@ -302,11 +704,36 @@ impl Set {
     /// ```
     #[inline]
     pub fn is_disjoint(&self, other: &Set) -> bool {
-        self.iter().all(|v| !other.contains(&v))
+        self.is_disjoint_range(other, ..)
+    }
+
+    /// Returns `true` if the set has no elements in common with `other` within `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3, 10].iter().cloned().collect();
+    /// let b: trie::Set = [4, 5, 10].iter().cloned().collect();
+    ///
+    /// assert_eq!(a.is_disjoint(&b), false);
+    /// assert_eq!(a.is_disjoint_range(&b, ..10), true);
+    /// assert_eq!(a.is_disjoint_range(&b, 5..=10), false);
+    /// ```
+    pub fn is_disjoint_range<R>(&self, other: &Set, range: R) -> bool
+        where R: RangeBounds<usize> {
+        match inclusive_bounds(&range) {
+            Some((lo, hi)) => {
+                disjoint_cursor(Cursor::root(self), Cursor::root(other), 0, 0, lo, hi)
+            }
+            None => true,
+        }
     }
 
     /// Returns `true` if the set is a subset of another.
     ///
+    /// The two tries are compared node by node, returning as soon as a slot is found that is
+    /// populated in `self` but empty in `other`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -321,7 +748,27 @@ impl Set {
     /// ```
     #[inline]
     pub fn is_subset(&self, other: &Set) -> bool {
-        self.iter().all(|v| other.contains(&v))
+        self.len() <= other.len() && self.is_subset_range(other, ..)
+    }
+
+    /// Returns `true` if the elements of the set within `range` are all contained in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sup: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// let set: trie::Set = [2, 3, 4].iter().cloned().collect();
+    ///
+    /// assert_eq!(set.is_subset(&sup), false);
+    /// assert_eq!(set.is_subset_range(&sup, ..4), true);
+    /// assert_eq!(set.is_subset_range(&sup, 3..), false);
+    /// ```
+    pub fn is_subset_range<R>(&self, other: &Set, range: R) -> bool
+        where R: RangeBounds<usize> {
+        match inclusive_bounds(&range) {
+            Some((lo, hi)) => subset_cursor(Cursor::root(self), Cursor::root(other), 0, 0, lo, hi),
+            None => true,
+        }
     }
 
     /// Returns `true` if the set is a superset of another.
@@ -346,6 +793,23 @@ impl Set {
         other.is_subset(self)
     }
 
+    /// Returns `true` if the set contains every element of `other` within `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sub: trie::Set = [1, 2, 8].iter().cloned().collect();
+    /// let set: trie::Set = [0, 1, 2, 3].iter().cloned().collect();
+    ///
+    /// assert_eq!(set.is_superset(&sub), false);
+    /// assert_eq!(set.is_superset_range(&sub, 1..3), true);
+    /// ```
+    #[inline]
+    pub fn is_superset_range<R>(&self, other: &Set, range: R) -> bool
+        where R: RangeBounds<usize> {
+        other.is_subset_range(self, range)
+    }
+
     /// Adds a value to the set. Returns `true` if the value was not already
     /// present in the set.
     ///
@@ -360,7 +824,28 @@ impl Set {
     /// ```
     #[inline]
     pub fn insert(&mut self, value: usize) -> bool {
-        self.map.insert(value, ()).is_none()
+        let (key, bit) = split(value);
+        if !insert_bit(self.map.entry(key), bit) { return false }
+        self.length = self.length.wrapping_add(1);
+        true
+    }
+
+    /// Adds a value to the set like `insert`, but returns an error instead of aborting if the
+    /// memory needed to store it cannot be allocated. The set is left unchanged on error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut set = trie::Set::new();
+    ///
+    /// assert_eq!(set.try_insert(2), Ok(true));
+    /// assert_eq!(set.try_insert(2), Ok(false));
+    /// ```
+    pub fn try_insert(&mut self, value: usize) -> Result<bool, TryReserveError> {
+        let (key, bit) = split(value);
+        if !insert_bit(self.map.try_entry(key)?, bit) { return Ok(false) }
+        self.length = self.length.wrapping_add(1);
+        Ok(true)
     }
 
     /// Removes a value from the set. Returns `true` if the value was
@@ -377,59 +862,647 @@ impl Set {
     /// ```
     #[inline]
     pub fn remove(&mut self, value: &usize) -> bool {
-        self.map.remove(value).is_some()
-    }
-}
-
-impl iter::FromIterator<usize> for Set {
-    fn from_iter<I: IntoIterator<Item=usize>>(iter: I) -> Set {
-        let mut set = Set::new();
-        set.extend(iter);
-        set
-    }
-}
-
-impl Extend<usize> for Set {
-    fn extend<I: IntoIterator<Item=usize>>(&mut self, iter: I) {
-        for elem in iter {
-            self.insert(elem);
+        let (key, bit) = split(*value);
+        match self.map.entry(key) {
+            map::Occupied(mut entry) if *entry.get() != 0 => {
+                if *entry.get() & bit == 0 { return false }
+                *entry.get_mut() &= !bit;
+                if *entry.get() == 0 { entry.remove(); }
+                self.length = self.length.wrapping_sub(1);
+                return true;
+            }
+            map::Occupied(_) => {}
+            map::Vacant(ref entry) if !in_block(entry) => return false,
+            map::Vacant(_) => {}
         }
+        // The value is in a marked block, which has to be split up to remove it.
+        self.split_block(key);
+        self.remove(value)
     }
-}
 
-impl<'a, 'b> ops::BitOr<&'b Set> for &'a Set {
-    type Output = Set;
-
-    /// Returns the union of `self` and `rhs` as a new set.
+    /// Returns `value` if it is in the set.
     ///
-    /// # Example
+    /// # Examples
     ///
     /// ```
-    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
-    /// let b: trie::Set = [3, 4, 5].iter().cloned().collect();
-    ///
-    /// let set: trie::Set = &a | &b;
-    /// let v: Vec<usize> = set.iter().collect();
-    /// assert_eq!(v, [1, 2, 3, 4, 5]);
+    /// let set: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// assert_eq!(set.get(&2), Some(2));
+    /// assert_eq!(set.get(&4), None);
     /// ```
-    fn bitor(self, rhs: &Set) -> Set {
-        self.union(rhs).collect()
+    #[inline]
+    pub fn get(&self, value: &usize) -> Option<usize> {
+        if self.contains(value) { Some(*value) } else { None }
     }
-}
-
-impl<'a, 'b> ops::BitAnd<&'b Set> for &'a Set {
-    type Output = Set;
 
-    /// Returns the intersection of `self` and `rhs` as a new set.
+    /// Adds a value to the set, replacing the existing value, if any, that is equal to the
+    /// given one. Returns the replaced value.
     ///
-    /// # Example
+    /// # Examples
     ///
     /// ```
-    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
-    /// let b: trie::Set = [2, 3, 4].iter().cloned().collect();
-    ///
-    /// let set: trie::Set = &a & &b;
-    /// let v: Vec<usize> = set.iter().collect();
+    /// let mut set = trie::Set::new();
+    /// assert_eq!(set.replace(5), None);
+    /// assert_eq!(set.replace(5), Some(5));
+    /// assert_eq!(set.len(), 1);
+    /// ```
+    #[inline]
+    pub fn replace(&mut self, value: usize) -> Option<usize> {
+        if self.insert(value) { None } else { Some(value) }
+    }
+
+    /// Removes and returns the value in the set, if any, that is equal to the given one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut set: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// assert_eq!(set.take(&2), Some(2));
+    /// assert_eq!(set.take(&2), None);
+    /// ```
+    #[inline]
+    pub fn take(&mut self, value: &usize) -> Option<usize> {
+        if self.remove(value) { Some(*value) } else { None }
+    }
+
+    /// Gets the given value's corresponding entry in the set for in-place manipulation.
+    ///
+    /// The entry remembers the path to the value's word, so inserting or removing the value
+    /// and finding its neighbors do not search from the root again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::set::Entry;
+    ///
+    /// let mut set: trie::Set = [10, 20, 30].iter().cloned().collect();
+    ///
+    /// let entry = set.entry(25).insert();
+    /// assert_eq!(entry.prev_value(), Some(20));
+    /// assert_eq!(entry.next_value(), Some(30));
+    ///
+    /// match set.entry(10) {
+    ///     Entry::Occupied(entry) => assert_eq!(entry.remove(), 10),
+    ///     Entry::Vacant(_) => unreachable!(),
+    /// }
+    /// assert!(set.iter().eq(vec![20, 25, 30]));
+    /// ```
+    pub fn entry(&mut self, value: usize) -> Entry<'_> {
+        let (key, bit) = split(value);
+        self.split_block(key);
+        let Set { ref mut map, ref mut length } = *self;
+        match map.entry(key) {
+            map::Occupied(entry) if *entry.get() & bit != 0 => {
+                Occupied(OccupiedEntry { entry, length, value })
+            }
+            entry => Vacant(VacantEntry { entry, length, value }),
+        }
+    }
+
+    // Returns the key of the marker whose block holds word `key`, if there is one.
+    fn marker_for(&self, key: usize) -> Option<usize> {
+        match Cursor::root(self).leaf(key, 0) {
+            Some((stored, 0)) if leaf_bits(stored, 0, key) != 0 => Some(stored),
+            _ => None,
+        }
+    }
+
+    // Splits the marked block holding word `key`, if there is one, into smaller blocks until
+    // the word is stored on its own.
+    fn split_block(&mut self, key: usize) {
+        while let Some(marker) = self.marker_for(key) {
+            self.map.remove(&marker);
+            let (start, idx) = (block(marker).0, block_depth(marker) + 1);
+            for slot in 0..SIZE {
+                let (key, word) = full_slot(slot_bounds(start, slot, idx).0, idx);
+                self.map.insert(key, word);
+            }
+        }
+    }
+}
+
+// Sets `bit` in a word, given the word's entry in the map. Returns `false` if the bit was
+// already set, or the word is in a marked block.
+fn insert_bit(entry: map::Entry<u64>, bit: u64) -> bool {
+    match entry {
+        map::Occupied(mut entry) => {
+            let word = entry.get_mut();
+            if *word == 0 || *word & bit != 0 { return false }
+            *word |= bit;
+        }
+        map::Vacant(ref entry) if in_block(entry) => return false,
+        map::Vacant(entry) => { entry.insert(bit); }
+    }
+    true
+}
+
+// Returns true if the vacant word is in a block marked by the key that occupies its slot.
+fn in_block(entry: &map::VacantEntry<u64>) -> bool {
+    let key = entry.key();
+    entry.occupant().is_some_and(|(stored, &word)| word == 0 && leaf_bits(stored, 0, key) != 0)
+}
+
+/// A view into a single value of a set, which may be present or absent.
+pub enum Entry<'a> {
+    /// A value that is in the set.
+    Occupied(OccupiedEntry<'a>),
+    /// A value that is not in the set.
+    Vacant(VacantEntry<'a>),
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the value of the entry.
+    pub fn get(&self) -> usize {
+        match *self {
+            Occupied(ref entry) => entry.get(),
+            Vacant(ref entry) => entry.get(),
+        }
+    }
+
+    /// Adds the value to the set if it is absent, and returns an `OccupiedEntry`.
+    pub fn insert(self) -> OccupiedEntry<'a> {
+        match self {
+            Occupied(entry) => entry,
+            Vacant(entry) => entry.insert(),
+        }
+    }
+
+    /// Returns the smallest value in the set that is greater than the entry's value.
+    pub fn next_value(&self) -> Option<usize> {
+        match *self {
+            Occupied(ref entry) => entry.next_value(),
+            Vacant(ref entry) => entry.next_value(),
+        }
+    }
+
+    /// Returns the largest value in the set that is less than the entry's value.
+    pub fn prev_value(&self) -> Option<usize> {
+        match *self {
+            Occupied(ref entry) => entry.prev_value(),
+            Vacant(ref entry) => entry.prev_value(),
+        }
+    }
+}
+
+/// A view into a value that is in a set.
+pub struct OccupiedEntry<'a> {
+    entry: map::OccupiedEntry<'a, u64>,
+    length: &'a mut usize,
+    value: usize,
+}
+
+/// A view into a value that is not in a set.
+pub struct VacantEntry<'a> {
+    // The entry for the value's word, which is occupied if the word holds other values.
+    entry: map::Entry<'a, u64>,
+    length: &'a mut usize,
+    value: usize,
+}
+
+impl<'a> OccupiedEntry<'a> {
+    /// Returns the value of the entry.
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.value
+    }
+
+    /// Removes the value from the set, and returns it.
+    pub fn remove(mut self) -> usize {
+        let (_, bit) = split(self.value);
+        *self.entry.get_mut() &= !bit;
+        if *self.entry.get() == 0 {
+            self.entry.remove();
+        }
+        *self.length = self.length.wrapping_sub(1);
+        self.value
+    }
+
+    /// Returns the smallest value in the set that is greater than the entry's value.
+    pub fn next_value(&self) -> Option<usize> {
+        next_value(self.value, Some(*self.entry.get()), self.entry.neighbor(true))
+    }
+
+    /// Returns the largest value in the set that is less than the entry's value.
+    pub fn prev_value(&self) -> Option<usize> {
+        prev_value(self.value, Some(*self.entry.get()), self.entry.neighbor(false))
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    /// Returns the value of the entry.
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.value
+    }
+
+    /// Returns the value of the entry, giving up the entry.
+    #[inline]
+    pub fn into_value(self) -> usize {
+        self.value
+    }
+
+    /// Adds the value to the set, and returns an `OccupiedEntry`.
+    pub fn insert(self) -> OccupiedEntry<'a> {
+        let (_, bit) = split(self.value);
+        let entry = match self.entry {
+            map::Occupied(mut entry) => {
+                *entry.get_mut() |= bit;
+                entry
+            }
+            map::Vacant(entry) => entry.insert_entry(bit),
+        };
+        *self.length = self.length.wrapping_add(1);
+        OccupiedEntry { entry, length: self.length, value: self.value }
+    }
+
+    /// Returns the smallest value in the set that is greater than the entry's value.
+    pub fn next_value(&self) -> Option<usize> {
+        next_value(self.value, self.word(), self.entry.neighbor(true))
+    }
+
+    /// Returns the largest value in the set that is less than the entry's value.
+    pub fn prev_value(&self) -> Option<usize> {
+        prev_value(self.value, self.word(), self.entry.neighbor(false))
+    }
+
+    // The word holding the entry's value, if the set has one.
+    fn word(&self) -> Option<u64> {
+        match self.entry {
+            map::Occupied(ref entry) => Some(*entry.get()),
+            map::Vacant(_) => None,
+        }
+    }
+}
+
+// Returns the smallest value greater than `value` from its own word, or else from the next leaf.
+fn next_value(value: usize, word: Option<u64>, next: Option<(usize, &u64)>) -> Option<usize> {
+    let above = word.unwrap_or(0) & (!1 << (value & WORD_MASK));
+    if above != 0 {
+        return Some((value & !WORD_MASK) | above.trailing_zeros() as usize);
+    }
+    next.map(|(key, &word)| match word {
+        0 => leaf_span(key, 0).0,
+        _ => key << WORD_SHIFT | word.trailing_zeros() as usize,
+    })
+}
+
+// Returns the largest value less than `value` from its own word, or else from the previous leaf.
+fn prev_value(value: usize, word: Option<u64>, prev: Option<(usize, &u64)>) -> Option<usize> {
+    let below = word.unwrap_or(0) & ((1 << (value & WORD_MASK)) - 1);
+    if below != 0 {
+        return Some((value & !WORD_MASK) | (WORD_MASK - below.leading_zeros() as usize));
+    }
+    prev.map(|(key, &word)| match word {
+        0 => leaf_span(key, 0).1,
+        _ => key << WORD_SHIFT | (WORD_MASK - word.leading_zeros() as usize),
+    })
+}
+
+impl Set {
+    /// Adds every value in `range` to the set. Returns the number of values that were not
+    /// already present, or `usize::MAX` if every `usize` was added to an empty set.
+    ///
+    /// Slots that the range covers are filled with a single marker each in one pass, rather
+    /// than by descending from the root for each value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut set = trie::Set::new();
+    /// assert_eq!(set.insert_range(10..20), 10);
+    /// assert_eq!(set.insert_range(15..=25), 6);
+    /// assert_eq!(set.len(), 16);
+    /// assert_eq!(set.to_string(), "10-25");
+    /// ```
+    pub fn insert_range<R: RangeBounds<usize>>(&mut self, range: R) -> usize {
+        let (lo, hi) = match inclusive_bounds(&range) {
+            Some(bounds) => bounds,
+            None => return 0,
+        };
+        let empty = self.is_empty();
+        let map = &mut self.map;
+        let length = fill_node(&mut map.nodes, &mut map.root, 0, 0, lo, hi, &mut map.length);
+        self.length = self.length.wrapping_add(length);
+        if empty && length == 0 { usize::MAX } else { length }
+    }
+
+    /// Removes every value in `range` from the set. Returns the number of values that were
+    /// present, or `usize::MAX` if the set held every `usize` and is now empty.
+    ///
+    /// Subtrees that lie entirely within the range are dropped without being visited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut set: trie::Set = (0..100).collect();
+    /// assert_eq!(set.remove_range(10..90), 80);
+    /// assert_eq!(set.remove_range(..15), 10);
+    /// assert_eq!(set.to_string(), "90-99");
+    /// ```
+    pub fn remove_range<R: RangeBounds<usize>>(&mut self, range: R) -> usize {
+        let (lo, hi) = match inclusive_bounds(&range) {
+            Some(bounds) => bounds,
+            None => return 0,
+        };
+        let empty = self.is_empty();
+        let map = &mut self.map;
+        let length = clear_node(&mut map.nodes, &mut map.root, 0, 0, lo, hi, &mut map.length);
+        self.length = self.length.wrapping_sub(length);
+        if !empty && length == 0 && self.is_empty() { usize::MAX } else { length }
+    }
+}
+
+// Returns the range of word keys below child `slot` of a node that are within `[lo, hi]`, or
+// `None` if there are none. See `slot_bounds`.
+fn slot_keys(prefix: usize, slot: usize, idx: usize, lo: usize, hi: usize)
+    -> Option<(usize, usize)> {
+    let (start, end) = slot_bounds(prefix, slot, idx);
+    if end < lo >> WORD_SHIFT || start > hi >> WORD_SHIFT {
+        None
+    } else {
+        Some((start.max(lo >> WORD_SHIFT), end.min(hi >> WORD_SHIFT)))
+    }
+}
+
+// Sets the bits for the values within `[lo, hi]` below `node`, whose words all have keys
+// starting with `prefix`, where `idx` is the depth of its children. A slot that the range covers
+// is replaced with a marker. Adjusts `words` for the leaves added and removed, and returns the
+// number of values that were added, which wraps to zero if that is every value.
+fn fill_node(nodes: &mut Arena<u64>, node: &mut InternalNode<u64>, idx: usize, prefix: usize,
+             lo: usize, hi: usize, words: &mut usize) -> usize {
+    let mut length = 0usize;
+    for slot in 0..SIZE {
+        let (first, last) = match slot_keys(prefix, slot, idx, lo, hi) {
+            Some(keys) => keys,
+            None => continue,
+        };
+        let (start, end) = slot_bounds(prefix, slot, idx);
+        let child = &mut node.children[slot];
+        // The root's slots are too large to be marked.
+        if idx > 0 && idx < MAX_DEPTH - 1 &&
+           start << WORD_SHIFT >= lo && (end << WORD_SHIFT | WORD_MASK) <= hi {
+            let (key, word) = full_slot(start, idx);
+            let (old_words, old_length) = match mem::replace(child, External(key, word)) {
+                Internal(id) => {
+                    let old = subtree_len(nodes, &nodes[id]);
+                    nodes.release_subtree(id);
+                    old
+                }
+                External(key, word) => (1, leaf_len(key, word)),
+                Nothing => {
+                    node.count += 1;
+                    (0, 0)
+                }
+            };
+            *words = *words + 1 - old_words;
+            length = length.wrapping_add(leaf_len(key, word).wrapping_sub(old_length));
+            continue;
+        }
+        if let External(key, word) = *child {
+            if word == 0 {
+                let (block_start, block_end) = block(key);
+                if block_start <= first && last <= block_end {
+                    continue;
+                }
+            } else if first == last && key == first {
+                let filled = word | word_mask(key, lo, hi);
+                length += (filled ^ word).count_ones() as usize;
+                *child = External(key, filled);
+                continue;
+            }
+            // Push the leaf down a level so that the range can be filled around it.
+            *words += push_down(nodes, child, idx);
+        }
+        match *child {
+            Internal(id) => {
+                let mut below = nodes.take(id);
+                let below_length = fill_node(nodes, &mut below, idx + 1, start, lo, hi, words);
+                nodes[id] = below;
+                length = length.wrapping_add(below_length);
+            }
+            Nothing if first == last => {
+                let word = word_mask(first, lo, hi);
+                *words += 1;
+                length += word.count_ones() as usize;
+                *child = External(first, word);
+                node.count += 1;
+            }
+            Nothing => {
+                let mut below = InternalNode::new();
+                let below_length = fill_node(nodes, &mut below, idx + 1, start, lo, hi, words);
+                length = length.wrapping_add(below_length);
+                *child = Internal(nodes.push(below));
+                node.count += 1;
+            }
+            External(..) => unreachable!(),
+        }
+    }
+    node.update_full(nodes, idx);
+    length
+}
+
+// Clears the bits for the values within `[lo, hi]` below `node`, whose words all have keys
+// starting with `prefix`, where `idx` is the depth of its children. Adjusts `words` for the
+// leaves added and removed, and returns the number of values that were removed, which wraps to
+// zero if that is every value.
+fn clear_node(nodes: &mut Arena<u64>, node: &mut InternalNode<u64>, idx: usize, prefix: usize,
+              lo: usize, hi: usize, words: &mut usize) -> usize {
+    let mut length = 0usize;
+    for slot in 0..SIZE {
+        if slot_keys(prefix, slot, idx, lo, hi).is_none() {
+            continue;
+        }
+        let (start, end) = slot_bounds(prefix, slot, idx);
+        let covered = start << WORD_SHIFT >= lo && (end << WORD_SHIFT | WORD_MASK) <= hi;
+        let child = &mut node.children[slot];
+        if let External(key, 0) = *child {
+            let (first, last) = leaf_span(key, 0);
+            if last < lo || first > hi {
+                continue;
+            }
+            if !covered && (first < lo || last > hi) {
+                // Push the marker down until the range splits its block.
+                *words += push_down(nodes, child, idx);
+            }
+        }
+        let emptied = match *child {
+            Nothing => continue,
+            External(key, word) if !covered && word != 0 => {
+                let cleared = word & !word_mask(key, lo, hi);
+                length += (word ^ cleared).count_ones() as usize;
+                *child = External(key, cleared);
+                cleared == 0
+            }
+            Internal(id) if !covered => {
+                let mut below = nodes.take(id);
+                let below_length = clear_node(nodes, &mut below, idx + 1, start, lo, hi, words);
+                length = length.wrapping_add(below_length);
+                let emptied = below.count == 0;
+                nodes[id] = below;
+                if emptied { nodes.release(id); }
+                emptied
+            }
+            External(key, word) => {
+                length = length.wrapping_add(leaf_len(key, word));
+                true
+            }
+            Internal(id) => {
+                let (below_words, below_length) = subtree_len(nodes, &nodes[id]);
+                nodes.release_subtree(id);
+                *words -= below_words;
+                length = length.wrapping_add(below_length);
+                true
+            }
+        };
+        if emptied {
+            if let External(..) = *child { *words -= 1; }
+            *child = Nothing;
+            node.count -= 1;
+        }
+    }
+    node.update_full(nodes, idx);
+    length
+}
+
+// Replaces the leaf in `child`, at depth `idx`, with an internal node holding the same values
+// one level further down. A marker whose block is the whole slot is split into a leaf for each
+// slot below it. Returns the number of leaves added.
+fn push_down(nodes: &mut Arena<u64>, child: &mut TrieNode<u64>, idx: usize) -> usize {
+    let (key, word) = match *child {
+        External(key, word) => (key, word),
+        _ => unreachable!(),
+    };
+    let mut below = InternalNode::new();
+    let added = if word == 0 && block_depth(key) == idx {
+        let start = block(key).0;
+        for (slot, child) in below.children.iter_mut().enumerate() {
+            let (key, word) = full_slot(slot_bounds(start, slot, idx + 1).0, idx + 1);
+            *child = External(key, word);
+        }
+        SIZE - 1
+    } else {
+        below.children[chunk(key, idx + 1)] = External(key, word);
+        0
+    };
+    below.count = (added + 1) as u16;
+    *child = Internal(nodes.push(below));
+    added
+}
+
+/// Builds a set from values in strictly ascending order in a single pass, packing them into
+/// words before handing them to the map's sorted builder.
+pub(crate) struct SortedBuilder {
+    words: map::SortedBuilder<u64>,
+    // The word being filled, which is not in `words` yet.
+    key: usize,
+    word: u64,
+    last: Option<usize>,
+    length: usize,
+}
+
+impl SortedBuilder {
+    pub(crate) fn new() -> SortedBuilder {
+        SortedBuilder { words: map::SortedBuilder::new(), key: 0, word: 0, last: None, length: 0 }
+    }
+
+    /// Returns the last value that was pushed.
+    #[cfg(feature = "serde")]
+    pub(crate) fn last_value(&self) -> Option<usize> {
+        self.last
+    }
+
+    /// Adds a value. If it is not greater than the last value pushed, it is handed back and the
+    /// builder is left unchanged.
+    pub(crate) fn push(&mut self, value: usize) -> Result<(), usize> {
+        if self.last.is_some_and(|last| value <= last) {
+            return Err(value);
+        }
+        let (key, bit) = split(value);
+        if self.word != 0 && key != self.key {
+            let _ = self.words.push(self.key, self.word);
+            self.word = 0;
+        }
+        self.key = key;
+        self.word |= bit;
+        self.last = Some(value);
+        self.length += 1;
+        Ok(())
+    }
+
+    /// Finishes building the set.
+    pub(crate) fn finish(mut self) -> Set {
+        if self.word != 0 {
+            let _ = self.words.push(self.key, self.word);
+        }
+        Set { map: self.words.finish(), length: self.length }
+    }
+}
+
+impl iter::FromIterator<usize> for Set {
+    fn from_iter<I: IntoIterator<Item=usize>>(iter: I) -> Set {
+        let mut set = Set::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<usize> for Set {
+    fn extend<I: IntoIterator<Item=usize>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        if self.is_empty() {
+            // Build from the bottom up for as long as the values arrive in ascending order,
+            // then insert the rest one at a time.
+            let mut builder = SortedBuilder::new();
+            let mut unsorted = None;
+            for elem in iter.by_ref() {
+                if let Err(elem) = builder.push(elem) {
+                    unsorted = Some(elem);
+                    break;
+                }
+            }
+            *self = builder.finish();
+            if let Some(elem) = unsorted {
+                self.insert(elem);
+            }
+        }
+        for elem in iter {
+            self.insert(elem);
+        }
+    }
+}
+
+impl<'a, 'b> ops::BitOr<&'b Set> for &'a Set {
+    type Output = Set;
+
+    /// Returns the union of `self` and `rhs` as a new set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// let b: trie::Set = [3, 4, 5].iter().cloned().collect();
+    ///
+    /// let set: trie::Set = &a | &b;
+    /// let v: Vec<usize> = set.iter().collect();
+    /// assert_eq!(v, [1, 2, 3, 4, 5]);
+    /// ```
+    fn bitor(self, rhs: &Set) -> Set {
+        self.union(rhs).collect()
+    }
+}
+
+impl<'a, 'b> ops::BitAnd<&'b Set> for &'a Set {
+    type Output = Set;
+
+    /// Returns the intersection of `self` and `rhs` as a new set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let a: trie::Set = [1, 2, 3].iter().cloned().collect();
+    /// let b: trie::Set = [2, 3, 4].iter().cloned().collect();
+    ///
+    /// let set: trie::Set = &a & &b;
+    /// let v: Vec<usize> = set.iter().collect();
     /// assert_eq!(v, [2, 3]);
     /// ```
     fn bitand(self, rhs: &Set) -> Set {
@@ -477,16 +1550,211 @@ impl<'a, 'b> ops::Sub<&'b Set> for &'a Set {
     }
 }
 
+// Splits a value into the key of its word and its bit within the word.
+#[inline]
+fn split(value: usize) -> (usize, u64) {
+    (value >> WORD_SHIFT, 1 << (value & WORD_MASK))
+}
+
+// Returns the bits of word `key` that correspond to values within `[lo, hi]`.
+fn word_mask(key: usize, lo: usize, hi: usize) -> u64 {
+    if key < lo >> WORD_SHIFT || key > hi >> WORD_SHIFT {
+        return 0;
+    }
+    let first = if key == lo >> WORD_SHIFT { lo & WORD_MASK } else { 0 };
+    let last = if key == hi >> WORD_SHIFT { hi & WORD_MASK } else { WORD_MASK };
+    (!0 << first) & (!0 >> (WORD_MASK - last))
+}
+
+// A stored word of zero is a marker for a block of full words: the `SIZE.pow(n + 1)` keys that
+// start like the marker's key, where `n` is its lowest chunk. The block is the range of the slot
+// at depth `block_depth(key)`, which is never the root. No other key is stored within a block,
+// so its marker is at that depth or above it.
+
+// Returns the first and last keys of the block marked by `key`.
+fn block(key: usize) -> (usize, usize) {
+    let bits = SHIFT * ((key & MASK) + 1);
+    let start = key >> bits << bits;
+    (start, start | ((1 << bits) - 1))
+}
+
+// Returns the depth of the slot whose range is the block marked by `key`.
+fn block_depth(key: usize) -> usize {
+    MAX_DEPTH - 2 - (key & MASK)
+}
+
+// Returns the key and word of a leaf holding every value in the slot at depth `idx` whose range
+// starts at `start`: a full word at the bottom of the trie, or a marker above it.
+fn full_slot(start: usize, idx: usize) -> (usize, u64) {
+    if idx == MAX_DEPTH - 1 { (start, !0) } else { (start | (MAX_DEPTH - 2 - idx), 0) }
+}
+
+// Returns the first and last values that a leaf can hold.
+fn leaf_span(key: usize, word: u64) -> (usize, usize) {
+    let (first, last) = if word == 0 { block(key) } else { (key, key) };
+    (first << WORD_SHIFT, last << WORD_SHIFT | WORD_MASK)
+}
+
+// Returns the first and last keys that a leaf holds values for, or `None` if it is a marker for
+// a block larger than any slot below the root.
+pub(crate) fn leaf_keys(key: usize, word: u64) -> Option<(usize, usize)> {
+    match word {
+        0 if key & MASK > MAX_DEPTH - 3 => None,
+        0 => Some(block(key)),
+        _ => Some((key, key)),
+    }
+}
+
+// Returns the number of values in a leaf, which wraps to zero for a block of every value.
+pub(crate) fn leaf_len(key: usize, word: u64) -> usize {
+    match word {
+        0 => 1 << (WORD_SHIFT + SHIFT * ((key & MASK) + 1)),
+        _ => word.count_ones() as usize,
+    }
+}
+
+// Returns the bits of word `key` that are in a leaf.
+fn leaf_bits(stored: usize, word: u64, key: usize) -> u64 {
+    if word == 0 {
+        let (start, end) = block(stored);
+        if start <= key && key <= end { !0 } else { 0 }
+    } else if stored == key {
+        word
+    } else {
+        0
+    }
+}
+
+// Returns true if a leaf holds every value within `[lo, hi]`.
+fn leaf_covers(key: usize, word: u64, lo: usize, hi: usize) -> bool {
+    match word {
+        0 => {
+            let (first, last) = leaf_span(key, 0);
+            first <= lo && hi <= last
+        }
+        _ => {
+            lo >> WORD_SHIFT == key && hi >> WORD_SHIFT == key &&
+                word_mask(key, lo, hi) & !word == 0
+        }
+    }
+}
+
+// The values that remain to be yielded from a single leaf.
+#[derive(Clone)]
+pub(crate) enum Bits {
+    Word(usize, u64),
+    Block(RangeInclusive<usize>),
+}
+
+impl Bits {
+    pub(crate) fn new(key: usize, word: u64) -> Bits {
+        match word {
+            0 => {
+                let (first, last) = leaf_span(key, 0);
+                Bits::Block(first..=last)
+            }
+            _ => Bits::Word(key, word),
+        }
+    }
+
+    fn empty() -> Bits {
+        Bits::Word(0, 0)
+    }
+
+    // Drops the values less than `val`, which is within the leaf's span.
+    fn skip_below(self, val: usize) -> Bits {
+        match self {
+            Bits::Word(key, word) => Bits::Word(key, word & (!0 << (val & WORD_MASK))),
+            Bits::Block(values) => Bits::Block(val.max(*values.start())..=*values.end()),
+        }
+    }
+
+    // Takes the lowest run of consecutive values out of the leaf.
+    fn next_run(&mut self) -> Option<(usize, usize)> {
+        match *self {
+            Bits::Word(_, 0) => None,
+            Bits::Word(key, ref mut word) => {
+                let bit = word.trailing_zeros() as usize;
+                let ones = (*word >> bit).trailing_ones() as usize;
+                *word = match bit + ones {
+                    64 => 0,
+                    cleared => *word & (!0 << cleared),
+                };
+                let start = key << WORD_SHIFT | bit;
+                Some((start, start + (ones - 1)))
+            }
+            Bits::Block(ref values) if values.is_empty() => None,
+            Bits::Block(ref values) => {
+                let run = (*values.start(), *values.end());
+                *self = Bits::empty();
+                Some(run)
+            }
+        }
+    }
+}
+
+impl Iterator for Bits {
+    type Item = usize;
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        match *self {
+            Bits::Word(_, 0) => None,
+            Bits::Word(key, ref mut word) => {
+                let bit = word.trailing_zeros() as usize;
+                *word &= *word - 1;
+                Some(key << WORD_SHIFT | bit)
+            }
+            Bits::Block(ref mut values) => values.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            Bits::Word(_, word) => (word.count_ones() as usize, Some(word.count_ones() as usize)),
+            Bits::Block(ref values) => values.size_hint(),
+        }
+    }
+}
+
 /// A forward iterator over a set.
 #[derive(Clone)]
 pub struct Iter<'a> {
-    iter: map::Iter<'a, ()>
+    iter: map::Iter<'a, u64>,
+    bits: Bits,
+    remaining: usize,
 }
 
 /// A bounded forward iterator over a set.
 #[derive(Clone)]
 pub struct Range<'a> {
-    iter: map::Range<'a, ()>
+    iter: map::Range<'a, u64>,
+    bits: Bits,
+}
+
+/// An iterator over the maximal runs of consecutive values in a set.
+#[derive(Clone)]
+pub struct Runs<'a> {
+    iter: map::Iter<'a, u64>,
+    bits: Bits,
+    // The run that is being extended, which is yielded once a gap is found.
+    pending: Option<(usize, usize)>,
+}
+
+// An iterator over the maximal runs of values within `[next, hi]` that are not in a set.
+#[derive(Clone)]
+struct Gaps<'a> {
+    runs: Runs<'a>,
+    // The first value that has not been accounted for yet, or `None` when the iterator is
+    // exhausted.
+    next: Option<usize>,
+    hi: usize,
+}
+
+/// An iterator over the values within a range that are not in a set (in-order).
+#[derive(Clone)]
+pub struct Complement<'a> {
+    gaps: Gaps<'a>,
+    gap: Option<RangeInclusive<usize>>,
 }
 
 /// An iterator producing elements in the set difference (in-order).
@@ -517,6 +1785,375 @@ pub struct Union<'a> {
     b: Peekable<Iter<'a>>,
 }
 
+/// An iterator producing elements in the union of several sets (in-order).
+#[derive(Clone)]
+pub struct UnionAll<'a> {
+    walk: Walk<'a>,
+}
+
+/// An iterator producing elements in the intersection of several sets (in-order).
+#[derive(Clone)]
+pub struct IntersectionAll<'a> {
+    walk: Walk<'a>,
+}
+
+// A position in one of several tries that are being walked together.
+//
+// Inputs do not necessarily have the same shape: a word that is stored in an external node in one
+// trie may sit several levels further down in another. A `Leaf` stands in for such a word and
+// behaves like an internal node whose only non-empty child lies on the word's path.
+#[derive(Clone, Copy)]
+enum Cursor<'a> {
+    Node(&'a Arena<u64>, &'a InternalNode<u64>),
+    Leaf(usize, u64),
+}
+
+impl<'a> Cursor<'a> {
+    fn root(set: &'a Set) -> Cursor<'a> {
+        Cursor::Node(&set.map.nodes, &set.map.root)
+    }
+
+    // Returns the cursor for child `slot`, where `idx` is the depth of the children.
+    fn child(self, slot: usize, idx: usize) -> Option<Cursor<'a>> {
+        match self {
+            Cursor::Node(nodes, node) => match node.children[slot] {
+                Internal(id) => Some(Cursor::Node(nodes, &nodes[id])),
+                External(key, word) => Some(Cursor::Leaf(key, word)),
+                Nothing => None,
+            },
+            // Below a marker's own slot, every slot of its block is full.
+            Cursor::Leaf(key, 0) if idx > block_depth(key) => {
+                let (key, word) = full_slot(slot_bounds(block(key).0, slot, idx).0, idx);
+                Some(Cursor::Leaf(key, word))
+            }
+            Cursor::Leaf(key, _) if chunk(key, idx) == slot => Some(self),
+            Cursor::Leaf(..) => None,
+        }
+    }
+
+    // Returns the leaf on the path to word `key` below this cursor, if there is one, where `idx`
+    // is the depth of the children.
+    fn leaf(self, key: usize, idx: usize) -> Option<(usize, u64)> {
+        match self {
+            Cursor::Node(nodes, node) => {
+                let (mut child, mut idx) = (&node.children[chunk(key, idx)], idx + 1);
+                loop {
+                    match *child {
+                        Internal(id) => child = &nodes[id].children[chunk(key, idx)],
+                        External(stored, word) => return Some((stored, word)),
+                        Nothing => return None,
+                    }
+                    idx += 1;
+                }
+            }
+            Cursor::Leaf(stored, word) => Some((stored, word)),
+        }
+    }
+
+    // Returns the word for `key` below this cursor (zero if there is none, and full if it is in
+    // a marked block), where `idx` is the depth of the children.
+    fn word(self, key: usize, idx: usize) -> u64 {
+        self.leaf(key, idx).map_or(0, |(stored, word)| leaf_bits(stored, word, key))
+    }
+}
+
+// If every cursor is a leaf, and either they are all for the same word or one of them is a
+// marker whose block holds all the others, returns the key and word of their union.
+fn common_leaf(cursors: &[Cursor]) -> Option<(usize, u64)> {
+    let mut common = None;
+    for cursor in cursors {
+        let (key, word) = match *cursor {
+            Cursor::Leaf(key, word) => (key, word),
+            Cursor::Node(..) => return None,
+        };
+        common = match common {
+            None => Some((key, word)),
+            Some((other, union)) if word != 0 && union != 0 => {
+                if key != other { return None }
+                Some((key, union | word))
+            }
+            Some((other, union)) => {
+                let (first, last) = leaf_span(key, word);
+                let (other_first, other_last) = leaf_span(other, union);
+                if word == 0 && first <= other_first && other_last <= last {
+                    Some((key, word))
+                } else if union == 0 && other_first <= first && last <= other_last {
+                    Some((other, union))
+                } else {
+                    return None;
+                }
+            }
+        };
+    }
+    common
+}
+
+// Returns the intersection of the cursors when it is a single leaf or nothing, or `None` if the
+// cursors have to be descended into, where `idx` is the depth of the cursors' children. If any
+// of the cursors is a word, the intersection is the cursors' bits for that word. If they are all
+// markers, it is the smallest of their blocks when those are nested.
+fn first_leaf(cursors: &[Cursor], idx: usize) -> Option<Option<(usize, u64)>> {
+    let leaves = cursors.iter().filter_map(|c| match *c {
+        Cursor::Leaf(key, word) => Some((key, word)),
+        Cursor::Node(..) => None,
+    });
+    if let Some((key, _)) = leaves.clone().find(|&(_, word)| word != 0) {
+        let word = cursors.iter().fold(!0, |word, c| word & c.word(key, idx));
+        return Some(if word == 0 { None } else { Some((key, word)) });
+    }
+    if leaves.clone().count() < cursors.len() {
+        return None;
+    }
+    let smallest = leaves.clone().min_by_key(|&(key, _)| key & MASK)?;
+    let (first, last) = block(smallest.0);
+    let nested = leaves.map(|(key, _)| block(key))
+                       .all(|(start, end)| start <= first && last <= end);
+    Some(if nested { Some(smallest) } else { None })
+}
+
+// The cursors for one level of a multi-trie walk, and the next child slot to visit.
+#[derive(Clone)]
+struct Frame<'a> {
+    cursors: Vec<Cursor<'a>>,
+    slot: usize,
+}
+
+// A depth-first walk over several tries at once. The frame at index `i` of the stack holds
+// cursors whose children are at depth `i`. `bits` holds the values of the word that is
+// currently being yielded.
+#[derive(Clone)]
+struct Walk<'a> {
+    stack: Vec<Frame<'a>>,
+    bits: Bits,
+}
+
+impl<'a> Walk<'a> {
+    fn roots<I>(sets: I) -> Vec<Cursor<'a>> where I: IntoIterator<Item=&'a Set> {
+        sets.into_iter().map(Cursor::root).collect()
+    }
+
+    fn new<I>(sets: I) -> Walk<'a> where I: IntoIterator<Item=&'a Set> {
+        let cursors = Walk::roots(sets);
+        let stack = if cursors.is_empty() { vec![] } else { vec![Frame { cursors, slot: 0 }] };
+        Walk { stack, bits: Bits::empty() }
+    }
+
+    // Advances to the next child slot, popping exhausted frames. Returns the depth of the slot
+    // and the cursors of the inputs that have it, or `None` when the walk is complete.
+    // If `all` is true, slots that are missing from any input are skipped.
+    fn next_slot(&mut self, all: bool) -> Option<(usize, Vec<Cursor<'a>>)> {
+        loop {
+            let idx = self.stack.len().checked_sub(1)?;
+            let frame = self.stack.last_mut().unwrap();
+            if frame.slot == SIZE {
+                self.stack.pop();
+                continue;
+            }
+            let slot = frame.slot;
+            frame.slot += 1;
+
+            let children: Vec<_> = frame.cursors.iter()
+                                                .filter_map(|c| c.child(slot, idx))
+                                                .collect();
+            if children.is_empty() || (all && children.len() != frame.cursors.len()) {
+                continue;
+            }
+            return Some((idx, children));
+        }
+    }
+
+    fn push(&mut self, cursors: Vec<Cursor<'a>>) {
+        self.stack.push(Frame { cursors, slot: 0 });
+    }
+}
+
+// Fills `node` with the union of the children of `cursors`, where `idx` is the depth of the
+// children. Returns the number of leaves and the number of values below `node`.
+fn union_node(cursors: &[Cursor], idx: usize, nodes: &mut Arena<u64>, node: &mut InternalNode<u64>)
+    -> (usize, usize) {
+    let (mut words, mut length) = (0, 0usize);
+    for slot in 0..SIZE {
+        let children: Vec<_> = cursors.iter().filter_map(|c| c.child(slot, idx)).collect();
+        node.children[slot] = match (children.len(), common_leaf(&children)) {
+            (0, _) => continue,
+            (_, Some((key, word))) => {
+                words += 1;
+                length = length.wrapping_add(leaf_len(key, word));
+                External(key, word)
+            }
+            (1, None) => match children[0] {
+                Cursor::Node(from, x) => {
+                    let (child_words, child_length) = subtree_len(from, x);
+                    words += child_words;
+                    length = length.wrapping_add(child_length);
+                    Internal(nodes.copy_subtree(from, x))
+                }
+                Cursor::Leaf(..) => unreachable!(),
+            },
+            _ => {
+                let mut child = InternalNode::new();
+                let (child_words, child_length) =
+                    union_node(&children, idx + 1, nodes, &mut child);
+                words += child_words;
+                length = length.wrapping_add(child_length);
+                Internal(nodes.push(child))
+            }
+        };
+        node.count += 1;
+    }
+    node.update_full(nodes, idx);
+    (words, length)
+}
+
+// Fills `node` with the intersection of the children of `cursors`, where `idx` is the depth of
+// the children. Returns the number of leaves and the number of values below `node`.
+fn intersection_node(cursors: &[Cursor], idx: usize, nodes: &mut Arena<u64>,
+                     node: &mut InternalNode<u64>) -> (usize, usize) {
+    let (mut words, mut length) = (0, 0usize);
+    for slot in 0..SIZE {
+        let children: Vec<_> = cursors.iter().filter_map(|c| c.child(slot, idx)).collect();
+        if children.len() != cursors.len() {
+            continue;
+        }
+        node.children[slot] = match first_leaf(&children, idx + 1) {
+            Some(None) => continue,
+            Some(Some((key, word))) => {
+                words += 1;
+                length = length.wrapping_add(leaf_len(key, word));
+                External(key, word)
+            }
+            None => {
+                let mut child = InternalNode::new();
+                let (child_words, child_length) =
+                    intersection_node(&children, idx + 1, nodes, &mut child);
+                if child_words == 0 {
+                    continue;
+                }
+                words += child_words;
+                length = length.wrapping_add(child_length);
+                Internal(nodes.push(child))
+            }
+        };
+        node.count += 1;
+    }
+    node.update_full(nodes, idx);
+    (words, length)
+}
+
+// Converts `range` to inclusive bounds, or returns `None` if it is empty.
+fn inclusive_bounds<R: RangeBounds<usize>>(range: &R) -> Option<(usize, usize)> {
+    let lo = match range.start_bound() {
+        Bound::Included(&lo) => lo,
+        Bound::Excluded(&lo) => lo.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let hi = match range.end_bound() {
+        Bound::Included(&hi) => hi,
+        Bound::Excluded(&hi) => hi.checked_sub(1)?,
+        Bound::Unbounded => usize::MAX,
+    };
+    if lo <= hi { Some((lo, hi)) } else { None }
+}
+
+// Returns true if any value within `[lo, hi]` is below `cursor` but not in `except`, which is
+// the key and word of a leaf. The words below `cursor` all have keys starting with `prefix`, and
+// `idx` is the depth of its children.
+fn any_within(cursor: Cursor, idx: usize, prefix: usize, lo: usize, hi: usize,
+              except: Option<(usize, u64)>) -> bool {
+    match cursor {
+        Cursor::Leaf(key, word) if word != 0 => {
+            let word = match except {
+                Some((stored, bits)) => word & !leaf_bits(stored, bits, key),
+                None => word,
+            };
+            word & word_mask(key, lo, hi) != 0
+        }
+        Cursor::Leaf(key, _) => {
+            let (first, last) = leaf_span(key, 0);
+            let (first, last) = (first.max(lo), last.min(hi));
+            first <= last && !except.is_some_and(|(stored, bits)| {
+                leaf_covers(stored, bits, first, last)
+            })
+        }
+        Cursor::Node(..) => (0..SIZE).any(|slot| {
+            let (start, end) = slot_bounds(prefix, slot, idx);
+            end >= lo >> WORD_SHIFT && start <= hi >> WORD_SHIFT &&
+                cursor.child(slot, idx).is_some_and(|child| {
+                    any_within(child, idx + 1, start, lo, hi, except)
+                })
+        }),
+    }
+}
+
+// Returns true if every value within `[lo, hi]` that is below `a` is also below `b`. The words
+// below both cursors have keys starting with `prefix`, and `idx` is the depth of their children.
+fn subset_cursor(a: Cursor, b: Cursor, idx: usize, prefix: usize, lo: usize, hi: usize) -> bool {
+    match (a, b) {
+        (Cursor::Leaf(key, word), _) if word != 0 => {
+            word & word_mask(key, lo, hi) & !b.word(key, idx) == 0
+        }
+        (Cursor::Leaf(key, _), Cursor::Leaf(other, word)) => {
+            let (first, last) = leaf_span(key, 0);
+            let (first, last) = (first.max(lo), last.min(hi));
+            first > last || leaf_covers(other, word, first, last)
+        }
+        (Cursor::Node(..), Cursor::Leaf(key, word)) => {
+            !any_within(a, idx, prefix, lo, hi, Some((key, word)))
+        }
+        // A marker in `a` is compared with a node in `b` a slot at a time.
+        _ => (0..SIZE).all(|slot| {
+            let (start, end) = slot_bounds(prefix, slot, idx);
+            if end < lo >> WORD_SHIFT || start > hi >> WORD_SHIFT {
+                return true;
+            }
+            match (a.child(slot, idx), b.child(slot, idx)) {
+                (None, _) => true,
+                (Some(a), None) => !any_within(a, idx + 1, start, lo, hi, None),
+                (Some(a), Some(b)) => subset_cursor(a, b, idx + 1, start, lo, hi),
+            }
+        }),
+    }
+}
+
+// Returns true if no value within `[lo, hi]` is below both `a` and `b`. The words below both
+// cursors have keys starting with `prefix`, and `idx` is the depth of their children.
+fn disjoint_cursor(a: Cursor, b: Cursor, idx: usize, prefix: usize, lo: usize, hi: usize)
+    -> bool {
+    match (a, b) {
+        (Cursor::Leaf(key, word), other) | (other, Cursor::Leaf(key, word)) if word != 0 => {
+            word & word_mask(key, lo, hi) & other.word(key, idx) == 0
+        }
+        (Cursor::Leaf(key, _), other) | (other, Cursor::Leaf(key, _)) => {
+            let (first, last) = leaf_span(key, 0);
+            let (first, last) = (first.max(lo), last.min(hi));
+            first > last || !any_within(other, idx, prefix, first, last, None)
+        }
+        (Cursor::Node(..), Cursor::Node(..)) => (0..SIZE).all(|slot| {
+            let (start, end) = slot_bounds(prefix, slot, idx);
+            if end < lo >> WORD_SHIFT || start > hi >> WORD_SHIFT {
+                return true;
+            }
+            match (a.child(slot, idx), b.child(slot, idx)) {
+                (Some(a), Some(b)) => disjoint_cursor(a, b, idx + 1, start, lo, hi),
+                _ => true,
+            }
+        }),
+    }
+}
+
+// Returns the number of leaves and the number of values below `node`, which wraps to zero if
+// that is every value.
+fn subtree_len(nodes: &Arena<u64>, node: &InternalNode<u64>) -> (usize, usize) {
+    node.children.iter().fold((0, 0), |(words, length): (usize, usize), child| match *child {
+        Internal(id) => {
+            let (child_words, child_length) = subtree_len(nodes, &nodes[id]);
+            (words + child_words, length.wrapping_add(child_length))
+        }
+        External(key, word) => (words + 1, length.wrapping_add(leaf_len(key, word))),
+        Nothing => (words, length),
+    })
+}
+
 /// Compare `x` and `y`, but return `short` if x is None and `long` if y is None
 fn cmp_opt(x: Option<&usize>, y: Option<&usize>, short: Ordering, long: Ordering) -> Ordering {
     match (x, y) {
@@ -529,22 +2166,108 @@ fn cmp_opt(x: Option<&usize>, y: Option<&usize>, short: Ordering, long: Ordering
 impl<'a> Iterator for Iter<'a> {
     type Item = usize;
     fn next(&mut self) -> Option<usize> {
-        self.iter.next().map(|(key, _)| key)
+        loop {
+            if let Some(value) = self.bits.next() {
+                self.remaining = self.remaining.saturating_sub(1);
+                return Some(value);
+            }
+            let (key, &word) = self.iter.next()?;
+            self.bits = Bits::new(key, word);
+        }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.iter.size_hint()
+        (self.remaining, Some(self.remaining))
     }
 }
 
 impl<'a> ExactSizeIterator for Iter<'a> {
-    fn len(&self) -> usize { self.iter.len() }
+    fn len(&self) -> usize { self.remaining }
 }
 
 impl<'a> Iterator for Range<'a> {
     type Item = usize;
-    fn next(&mut self) -> Option<usize> { self.iter.next().map(|(key, _)| key) }
-    fn size_hint(&self) -> (usize, Option<usize>) { self.iter.size_hint() }
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            if let Some(value) = self.bits.next() {
+                return Some(value);
+            }
+            let (key, &word) = self.iter.next()?;
+            self.bits = Bits::new(key, word);
+        }
+    }
+
+    // Each of the remaining leaves may be a marker for any number of values.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.bits.size_hint().0, None)
+    }
+}
+
+impl<'a> Iterator for Runs<'a> {
+    type Item = RangeInclusive<usize>;
+    fn next(&mut self) -> Option<RangeInclusive<usize>> {
+        loop {
+            let (start, end) = match self.bits.next_run() {
+                Some(run) => run,
+                None => {
+                    match self.iter.next() {
+                        Some((key, &word)) => self.bits = Bits::new(key, word),
+                        None => return self.pending.take().map(|(start, end)| start..=end),
+                    }
+                    continue;
+                }
+            };
+
+            match self.pending {
+                Some((first, last)) if start - last == 1 => self.pending = Some((first, end)),
+                Some((first, last)) => {
+                    self.pending = Some((start, end));
+                    return Some(first..=last);
+                }
+                None => self.pending = Some((start, end)),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Gaps<'a> {
+    type Item = RangeInclusive<usize>;
+    fn next(&mut self) -> Option<RangeInclusive<usize>> {
+        loop {
+            let start = self.next?;
+            let run = match self.runs.next() {
+                Some(run) => run,
+                None => {
+                    self.next = None;
+                    return Some(start..=self.hi);
+                }
+            };
+            // Runs never overlap `next` from below, so they either begin a gap or extend
+            // from `start`.
+            let following = if *run.end() < self.hi { Some(run.end() + 1) } else { None };
+            if *run.start() > start {
+                if *run.start() > self.hi {
+                    self.next = None;
+                    return Some(start..=self.hi);
+                }
+                self.next = following;
+                return Some(start..=run.start() - 1);
+            }
+            self.next = following;
+        }
+    }
+}
+
+impl<'a> Iterator for Complement<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            if let Some(value) = self.gap.as_mut().and_then(|gap| gap.next()) {
+                return Some(value);
+            }
+            self.gap = Some(self.gaps.next()?);
+        }
+    }
 }
 
 impl<'a> Iterator for Difference<'a> {
@@ -603,6 +2326,39 @@ impl<'a> Iterator for Union<'a> {
     }
 }
 
+impl<'a> Iterator for UnionAll<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            if let Some(value) = self.walk.bits.next() {
+                return Some(value);
+            }
+            let (_, children) = self.walk.next_slot(false)?;
+            match common_leaf(&children) {
+                Some((key, word)) => self.walk.bits = Bits::new(key, word),
+                None => self.walk.push(children),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for IntersectionAll<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            if let Some(value) = self.walk.bits.next() {
+                return Some(value);
+            }
+            let (idx, children) = self.walk.next_slot(true)?;
+            match first_leaf(&children, idx + 1) {
+                Some(Some((key, word))) => self.walk.bits = Bits::new(key, word),
+                Some(None) => {}
+                None => self.walk.push(children),
+            }
+        }
+    }
+}
+
 impl<'a> IntoIterator for &'a Set {
     type Item = usize;
     type IntoIter = Iter<'a>;
@@ -611,8 +2367,18 @@ impl<'a> IntoIterator for &'a Set {
 
 #[cfg(test)]
 mod test {
-    use super::map::USIZE_BITS;
-    use super::Set;
+    use std::string::ToString;
+    use std::format;
+    use std::vec::Vec;
+    use core::cmp::Ordering::Equal;
+
+    use std::collections::BTreeSet;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use super::map::{Arena, InternalNode, USIZE_BITS};
+    use super::map::TrieNode::*;
+    use super::{ParseSetError, Set, leaf_len};
 
     #[test]
     fn test_sane_chunk() {
@@ -633,6 +2399,306 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_dense() {
+        let mut set: Set = (0..100_000).collect();
+        assert_eq!(set.len(), 100_000);
+        assert_eq!(set.map.len(), 100_000 / 64 + 1);
+        assert!(set.iter().eq(0..100_000));
+        assert_eq!(set.iter().len(), 100_000);
+
+        for x in (0..100_000).step_by(2) {
+            assert!(set.remove(&x));
+            assert!(!set.remove(&x));
+        }
+        assert_eq!(set.len(), 50_000);
+        assert!(set.iter().eq((1..100_000).step_by(2)));
+
+        for x in (1..64).step_by(2) {
+            assert!(set.remove(&x));
+        }
+        assert!(!set.map.contains_key(&0));
+        assert_eq!(set.iter().next(), Some(65));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let set: Set = [0, 63, 64, 127, 200, 1 << 40, usize::MAX].iter().cloned().collect();
+        let values: Vec<usize> = set.iter().collect();
+
+        for &x in values.iter().chain(&[1, 62, 65, 128, 199, 201, (1 << 40) - 1, usize::MAX - 1]) {
+            let lower: Vec<usize> = values.iter().cloned().filter(|&v| v >= x).collect();
+            let upper: Vec<usize> = values.iter().cloned().filter(|&v| v > x).collect();
+            assert_eq!(set.lower_bound(x).collect::<Vec<_>>(), lower);
+            assert_eq!(set.upper_bound(x).collect::<Vec<_>>(), upper);
+        }
+
+        let mut reversed = vec![];
+        set.each_reverse(|&x| { reversed.push(x); true });
+        assert!(reversed.into_iter().eq(values.into_iter().rev()));
+    }
+
+    fn check_integrity(set: &Set) {
+        // Returns the number of words, values and arena nodes below `node`.
+        fn check_node(nodes: &Arena<u64>, node: &InternalNode<u64>) -> (usize, usize, usize) {
+            let (mut count, mut words, mut length, mut reachable) = (0, 0, 0, 0);
+            for child in node.children.iter() {
+                match *child {
+                    Internal(id) => {
+                        let x = &nodes[id];
+                        assert!(x.count != 0);
+                        let (w, l, r) = check_node(nodes, x);
+                        words += w;
+                        length = l.wrapping_add(length);
+                        reachable += r + 1;
+                        count += 1;
+                    }
+                    External(key, word) => {
+                        words += 1;
+                        length = leaf_len(key, word).wrapping_add(length);
+                        count += 1;
+                    }
+                    Nothing => {}
+                }
+            }
+            assert_eq!(count, node.count);
+            (words, length, reachable)
+        }
+
+        assert_eq!(check_node(&set.map.nodes, &set.map.root),
+                   (set.map.len(), set.length, set.map.nodes.len()));
+        assert_eq!(set.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let ranges = [(5, 5), (0, 63), (60, 130), (1000, 5000), (4090, 4100),
+                      (1 << 20, (1 << 20) + 100_000), (usize::MAX - 70, usize::MAX)];
+        let mut set = Set::new();
+        let mut expected = BTreeSet::new();
+
+        for &(lo, hi) in ranges.iter() {
+            let added = (lo..=hi).filter(|&x| expected.insert(x)).count();
+            assert_eq!(set.insert_range(lo..=hi), added);
+            check_integrity(&set);
+            assert!(set.iter().eq(expected.iter().cloned()));
+        }
+        assert_eq!(set.insert_range(10..10), 0);
+
+        for &(lo, hi) in [(3, 62), (100, 4095), (usize::MAX, usize::MAX),
+                          ((1 << 20) + 64, (1 << 20) + 99_999), (0, 4090)].iter() {
+            let removed = (lo..=hi).filter(|x| expected.remove(x)).count();
+            assert_eq!(set.remove_range(lo..=hi), removed);
+            check_integrity(&set);
+            assert!(set.iter().eq(expected.iter().cloned()));
+        }
+
+        assert_eq!(set.remove_range(..), expected.len());
+        check_integrity(&set);
+        assert!(set.is_empty());
+        assert!(set.map.is_empty());
+    }
+
+    #[test]
+    fn test_full_range() {
+        let mut set = Set::new();
+        assert_eq!(set.insert_range(..), usize::MAX);
+        check_integrity(&set);
+        assert_eq!(set.len(), usize::MAX);
+        assert!(set.map.len() <= 4);
+        assert!(set.contains(&0) && set.contains(&(1 << 40)) && set.contains(&usize::MAX));
+        assert!(!set.insert(12345));
+        assert_eq!(set.insert_range(5..10), 0);
+        assert!(set.iter().take(3).eq(0..3));
+        assert_eq!(set.iter_runs().collect::<Vec<_>>(), [0..=usize::MAX]);
+        assert_eq!(set.to_string(), format!("0-{}", usize::MAX));
+        assert_eq!(set.first_absent_from(0), None);
+        assert_eq!(set.lower_bound(1 << 40).next(), Some(1 << 40));
+        let mut last = None;
+        set.each_reverse(|&x| { last = Some(x); false });
+        assert_eq!(last, Some(usize::MAX));
+
+        assert!(set.remove(&(1 << 40)));
+        check_integrity(&set);
+        assert_eq!(set.len(), usize::MAX);
+        assert!(!set.contains(&(1 << 40)));
+        assert_eq!(set.first_absent_from(0), Some(1 << 40));
+        assert_eq!(set.to_string(),
+                   format!("0-{},{}-{}", (1usize << 40) - 1, (1usize << 40) + 1, usize::MAX));
+        assert!(set.insert(1 << 40));
+        check_integrity(&set);
+        assert!(set == Set::from_ranges(Some(..)));
+
+        assert_eq!(set.remove_range(0..=usize::MAX), usize::MAX);
+        check_integrity(&set);
+        assert!(set.is_empty());
+        assert!(set.map.is_empty());
+    }
+
+    #[test]
+    fn test_large_ranges() {
+        let mut set = Set::new();
+        assert_eq!(set.insert_range(0..1 << 40), 1 << 40);
+        check_integrity(&set);
+        assert_eq!(set.insert_range(1 << 39..=1 << 40), 1);
+        assert_eq!(set.to_string(), format!("0-{}", 1usize << 40));
+        assert_eq!(set.remove_range(100..(1 << 40) - 100), (1 << 40) - 200);
+        check_integrity(&set);
+        assert_eq!(set.to_string(), format!("0-99,{}-{}", (1usize << 40) - 100, 1usize << 40));
+
+        let parsed: Set = format!("0-{}", usize::MAX).parse().unwrap();
+        check_integrity(&parsed);
+        assert_eq!(parsed.len(), usize::MAX);
+        assert!(parsed == Set::from_ranges(Some(..)));
+
+        let ranges = Set::from_ranges(vec![5..=usize::MAX, 0..=2]);
+        check_integrity(&ranges);
+        assert_eq!(ranges.len(), usize::MAX - 1);
+        assert_eq!(ranges.to_string(), format!("0-2,5-{}", usize::MAX));
+        assert!(ranges > parsed);
+        assert!(ranges.is_subset(&parsed) && !parsed.is_subset(&ranges));
+        assert!(ranges.is_disjoint_range(&Set::from_ranges(Some(3..=4)), ..));
+    }
+
+    #[test]
+    fn test_blocks() {
+        fn hash(set: &Set) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            set.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // Ranges that cover whole slots are stored as markers, which must behave like the words
+        // they stand for.
+        let ranges = [(1000, 70_000), (1 << 20, (1 << 20) + 300_000),
+                      (3 << 22, (3 << 22) + (1 << 18))];
+        let (mut blocks, mut words, mut expected) = (Set::new(), Set::new(), BTreeSet::new());
+        for &(lo, hi) in ranges.iter() {
+            blocks.insert_range(lo..=hi);
+            for x in lo..=hi {
+                words.insert(x);
+                expected.insert(x);
+            }
+        }
+        check_integrity(&blocks);
+        assert!(blocks.map.len() * 10 < words.map.len());
+        assert!(blocks == words);
+        assert_eq!(blocks.cmp(&words), Equal);
+        assert_eq!(hash(&blocks), hash(&words));
+        assert!(blocks.iter().eq(expected.iter().cloned()));
+        let mut reversed = vec![];
+        blocks.each_reverse(|&x| { reversed.push(x); true });
+        assert!(reversed.into_iter().eq(expected.iter().rev().cloned()));
+
+        let others = [(0..100_000).step_by(7).collect(),
+                      ((1 << 20)..(1 << 20) + 400_000).step_by(3).collect(),
+                      Set::from_ranges(vec![0..=5000, 60_000..=(1 << 20) + 70_000]),
+                      Set::from_ranges(vec![2000..=3000, (1 << 20) + 4096..=(1 << 20) + 8191])];
+        for other in others.iter() {
+            check_integrity(other);
+            let other_expected: BTreeSet<usize> = other.iter().collect();
+            let union = Set::union_of([&blocks, other]);
+            check_integrity(&union);
+            assert!(union.iter().eq(expected.union(&other_expected).cloned()));
+            assert!(Set::union_all([&blocks, other]).eq(union.iter()));
+            let intersection = Set::intersection_of([&blocks, other]);
+            check_integrity(&intersection);
+            assert!(intersection.iter().eq(expected.intersection(&other_expected).cloned()));
+            assert!(Set::intersection_all([other, &blocks]).eq(intersection.iter()));
+
+            assert_eq!(blocks.is_subset(other), expected.is_subset(&other_expected));
+            assert_eq!(other.is_subset(&blocks), other_expected.is_subset(&expected));
+            assert_eq!(blocks.is_disjoint(other), expected.is_disjoint(&other_expected));
+            for &(lo, hi) in [(0, 999), (1000, 5000), (2000, 3000), (1 << 20, (1 << 20) + 5000),
+                              (1 << 21, 1 << 23)].iter() {
+                let a: BTreeSet<_> = expected.range(lo..=hi).cloned().collect();
+                let b: BTreeSet<_> = other_expected.range(lo..=hi).cloned().collect();
+                assert_eq!(blocks.is_subset_range(other, lo..=hi), a.is_subset(&b));
+                assert_eq!(other.is_subset_range(&blocks, lo..=hi), b.is_subset(&a));
+                assert_eq!(blocks.is_disjoint_range(other, lo..=hi), a.is_disjoint(&b));
+            }
+        }
+
+        let x = (1 << 20) + 5000;
+        assert!(blocks.contains(&x));
+        assert_eq!(blocks.lower_bound(x).next(), Some(x));
+        assert_eq!(blocks.upper_bound(70_000).next(), Some(1 << 20));
+        let mut split = blocks.clone();
+        match split.entry(x) {
+            super::Occupied(entry) => {
+                assert_eq!((entry.prev_value(), entry.next_value()), (Some(x - 1), Some(x + 1)));
+                entry.remove();
+            }
+            super::Vacant(_) => panic!("{} is missing", x),
+        }
+        check_integrity(&split);
+        assert_eq!(split.entry(x).next_value(), Some(x + 1));
+        assert_eq!(split.entry(70_001).next_value(), Some(1 << 20));
+        assert_eq!(split.entry(70_001).prev_value(), Some(70_000));
+        assert!(split.remove(&2000));
+        assert!(!split.remove(&2000));
+        check_integrity(&split);
+        assert_eq!(split.len(), expected.len() - 2);
+        assert!(split.iter().eq(expected.iter().cloned().filter(|&v| v != x && v != 2000)));
+    }
+
+    #[test]
+    fn test_runs() {
+        let set = Set::from_ranges(vec![0..=0, 2..=63, 64..=64, 66..=200, 300..=300]);
+        let runs: Vec<_> = set.iter_runs().collect();
+        assert_eq!(runs, [0..=0, 2..=64, 66..=200, 300..=300]);
+        assert_eq!(Set::new().iter_runs().next(), None);
+
+        let full = Set::from_ranges(Some(usize::MAX - 200..=usize::MAX));
+        assert_eq!(full.iter_runs().collect::<Vec<_>>(), [usize::MAX - 200..=usize::MAX]);
+    }
+
+    #[test]
+    fn test_complement() {
+        let set = Set::from_ranges(vec![0..=3, 10..=200, 1000..=1000, usize::MAX - 5..=usize::MAX]);
+        let ranges = [(0, 0), (0, 20), (2, 11), (4, 9), (150, 5000), (199, 1001),
+                      (usize::MAX - 10, usize::MAX)];
+
+        for &(lo, hi) in ranges.iter() {
+            let expected: Vec<usize> = (lo..=hi).filter(|x| !set.contains(x)).collect();
+            assert_eq!(set.complement_in(lo..=hi).collect::<Vec<_>>(), expected);
+
+            let complement = set.to_complement_in(lo..=hi);
+            check_integrity(&complement);
+            assert!(complement.iter().eq(expected.iter().cloned()));
+        }
+        assert_eq!(set.complement_in(5..5).next(), None);
+        assert!(Set::new().complement_in(7..10).eq(7..10));
+
+        let small: Set = [1, 5, 1000].iter().cloned().collect();
+        let complement = small.to_complement_in(..);
+        check_integrity(&complement);
+        assert_eq!(complement.len(), usize::MAX - 2);
+        assert!(complement.iter_runs().eq(vec![0..=0, 2..=4, 6..=999, 1001..=usize::MAX]));
+        assert!(complement.is_disjoint(&small));
+        assert!(Set::union_of(vec![&complement, &small]) == Set::from_ranges(Some(..)));
+        assert!(complement.to_complement_in(..) == small);
+
+        assert_eq!(set.first_absent_from(0), Some(4));
+        assert_eq!(set.first_absent_from(5), Some(5));
+        assert_eq!(set.first_absent_from(10), Some(201));
+        assert_eq!(set.first_absent_from(1000), Some(1001));
+        assert_eq!(set.first_absent_from(usize::MAX - 6), Some(usize::MAX - 6));
+        assert_eq!(set.first_absent_from(usize::MAX - 5), None);
+    }
+
+    #[test]
+    fn test_display_parse() {
+        let set = Set::from_ranges(vec![1..=5, 7..=7, 9..=12, 64..=127]);
+        let s = set.to_string();
+        assert_eq!(s, "1-5,7,9-12,64-127");
+        assert_eq!(s.parse::<Set>(), Ok(set));
+
+        assert_eq!(" 3 , 1-2 ".parse::<Set>().unwrap().to_string(), "1-3");
+        assert_eq!("2-1".parse::<Set>(), Err(ParseSetError::InvalidRange(2, 1)));
+        assert!(matches!("1-".parse::<Set>(), Err(ParseSetError::InvalidValue(_))));
+    }
+
     #[test]
     fn test_from_iter() {
         let xs = [9, 8, 7, 6, 5, 4, 3, 2, 1];
@@ -644,6 +2710,21 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_from_sorted_iter() {
+        let values: Vec<usize> = (0..2000).map(|i| i * i * 31).collect();
+        let set = Set::from_sorted_iter(values.iter().cloned()).unwrap();
+        check_integrity(&set);
+        assert!(set.iter().eq(values.iter().cloned()));
+        assert_eq!(Set::from_sorted_iter(vec![3, 3]).unwrap_err().key(), 3);
+
+        // Extending an empty set builds the sorted prefix and inserts the rest.
+        let mut set = Set::new();
+        set.extend(vec![1, 2, 64, 200, 200, 5, !0]);
+        check_integrity(&set);
+        assert!(set.iter().eq(vec![1, 2, 5, 64, 200, !0]));
+    }
+
     #[test]
     fn test_debug() {
         let mut set = Set::new();
@@ -796,6 +2877,93 @@ mod test {
                     &[1, 3, 5, 9, 11, 13, 16, 19, 24]);
     }
 
+    fn multi_sets() -> Vec<Set> {
+        // Keys that share long prefixes end up at different depths in different sets.
+        vec![
+            [1, 2, 3, 17, 300, 4096, usize::MAX].iter().cloned().collect(),
+            [2, 3, 4, 17, 256, 4096].iter().cloned().collect(),
+            [0, 3, 17, 18, 4096, 4097, usize::MAX].iter().cloned().collect(),
+            (0..50).map(|x| x * 7).chain(vec![3, 4096]).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_union_all() {
+        let sets = multi_sets();
+        let mut expected: Vec<usize> = sets.iter().flat_map(|s| s.iter()).collect();
+        expected.sort();
+        expected.dedup();
+
+        let v: Vec<usize> = Set::union_all(&sets).collect();
+        assert_eq!(v, expected);
+
+        let set = Set::union_of(&sets);
+        assert_eq!(set.len(), expected.len());
+        assert_eq!(set.iter().collect::<Vec<_>>(), expected);
+        for x in expected.iter() {
+            assert!(set.contains(x));
+        }
+
+        assert_eq!(Set::union_all(&[]).next(), None);
+        assert!(Set::union_of(&[]).is_empty());
+        assert_eq!(Set::union_of(&sets[..1]), sets[0]);
+    }
+
+    #[test]
+    fn test_intersection_all() {
+        let sets = multi_sets();
+        let expected: Vec<usize> = sets[0].iter()
+                                          .filter(|x| sets.iter().all(|s| s.contains(x)))
+                                          .collect();
+        assert_eq!(expected, [3, 4096]);
+
+        let v: Vec<usize> = Set::intersection_all(&sets).collect();
+        assert_eq!(v, expected);
+
+        let set = Set::intersection_of(&sets);
+        assert_eq!(set.len(), expected.len());
+        assert_eq!(set.iter().collect::<Vec<_>>(), expected);
+
+        let v: Vec<usize> = Set::intersection_all(&sets[..3]).collect();
+        assert_eq!(v, [3, 17, 4096]);
+        assert_eq!(Set::intersection_of(&sets[..2]), &sets[0] & &sets[1]);
+
+        assert_eq!(Set::intersection_all(&[]).next(), None);
+        assert!(Set::intersection_of(&[]).is_empty());
+        assert!(Set::intersection_of(&[sets[0].clone(), Set::new()]).is_empty());
+    }
+
+    #[test]
+    fn test_subset_disjoint() {
+        let mut sets = multi_sets();
+        // Removal leaves internal nodes with a single external child behind.
+        let mut pruned: Set = [3, 17, 4096, 4097].iter().cloned().collect();
+        pruned.remove(&4097);
+        sets.push(pruned);
+        sets.push([3, 4096].iter().cloned().collect());
+        sets.push(Set::new());
+
+        let ranges = [(0, usize::MAX), (0, 16), (3, 3), (17, 4095), (4096, usize::MAX)];
+        for a in sets.iter() {
+            for b in sets.iter() {
+                assert_eq!(a.is_subset(b), a.iter().all(|x| b.contains(&x)));
+                assert_eq!(a.is_superset(b), b.iter().all(|x| a.contains(&x)));
+                assert_eq!(a.is_disjoint(b), a.iter().all(|x| !b.contains(&x)));
+
+                for &(lo, hi) in ranges.iter() {
+                    let mut within = a.iter().filter(|&x| lo <= x && x <= hi);
+                    assert_eq!(a.is_subset_range(b, lo..=hi),
+                               within.clone().all(|x| b.contains(&x)));
+                    assert_eq!(a.is_disjoint_range(b, lo..=hi),
+                               within.all(|x| !b.contains(&x)));
+                }
+            }
+        }
+
+        assert!(sets[0].is_subset_range(&sets[1], 5..5));
+        assert!(sets[0].is_disjoint_range(&sets[0], ..0));
+    }
+
     #[test]
     fn test_bit_or() {
         let a: Set = [1, 2, 3].iter().cloned().collect();
@@ -835,4 +3003,58 @@ mod test {
         let v: Vec<usize> = set.iter().collect();
         assert_eq!(v, [1, 2]);
     }
+
+    #[test]
+    fn test_entry() {
+        let values = [0, 3, 63, 64, 200, 1 << 20, !0 - 1];
+        let mut set: Set = values.iter().cloned().collect();
+        let expected: BTreeSet<usize> = values.iter().cloned().collect();
+
+        let probes = [0, 1, 3, 62, 63, 64, 65, 150, 200, 4096, 1 << 20, !0 - 1, !0];
+        for &value in probes.iter() {
+            let entry = set.entry(value);
+            assert_eq!(entry.get(), value);
+            assert_eq!(matches!(entry, super::Occupied(_)), expected.contains(&value));
+            let next = expected.range(value..).find(|&&v| v != value).cloned();
+            let prev = expected.range(..value).next_back().cloned();
+            assert_eq!(entry.next_value(), next, "next of {}", value);
+            assert_eq!(entry.prev_value(), prev, "prev of {}", value);
+        }
+
+        // Inserting through an entry, both into an existing word and a new one.
+        for &value in [1, 4096, !0].iter() {
+            let entry = set.entry(value).insert();
+            assert_eq!(entry.get(), value);
+        }
+        assert_eq!(set.len(), values.len() + 3);
+        let entry = set.entry(4096).insert();
+        assert_eq!((entry.prev_value(), entry.next_value()), (Some(200), Some(1 << 20)));
+
+        // Removing the last value of a word removes the word.
+        for &value in [200, 4096, 1 << 20, 1].iter() {
+            match set.entry(value) {
+                super::Occupied(entry) => assert_eq!(entry.remove(), value),
+                super::Vacant(_) => panic!("{} is missing", value),
+            }
+        }
+        assert!(set.iter().eq(vec![0, 3, 63, 64, !0 - 1, !0]));
+        assert_eq!(set.len(), 6);
+        assert_eq!(set.map.len(), 3);
+
+        let mut empty = Set::new();
+        let entry = empty.entry(5);
+        assert_eq!((entry.prev_value(), entry.next_value()), (None, None));
+    }
+
+    #[test]
+    fn test_replace_take_get() {
+        let mut set = Set::new();
+        assert_eq!(set.replace(7), None);
+        assert_eq!(set.replace(7), Some(7));
+        assert_eq!(set.get(&7), Some(7));
+        assert_eq!(set.get(&8), None);
+        assert_eq!(set.take(&8), None);
+        assert_eq!(set.take(&7), Some(7));
+        assert!(set.is_empty());
+    }
 }
@@ -0,0 +1,404 @@
+//! A map split into independently locked shards, based on a trie.
+//!
+//! The root of a trie has sixteen children, one for each value of the first 4-bit chunk of a
+//! key, and no key ever moves from one child to another. `ShardedMap` keeps each of those
+//! subtrees in a `Map` of its own behind its own lock, so threads working on keys with
+//! different first chunks never wait for each other. Because the shards partition the keys by
+//! their highest bits, visiting the shards in order visits the keys in order.
+//!
+//! A panic while a shard is locked does not poison the map. Shards are only changed by `Map`'s
+//! own methods, which leave a shard consistent even if a thread panics while holding its lock.
+
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::vec::Vec;
+use core::fmt::{self, Debug};
+use core::iter;
+use core::ops::{Deref, DerefMut};
+use core::slice;
+
+use super::map::{self, chunk, Map, SIZE};
+
+/// A map from `usize` keys to values that can be shared between threads, with a separate lock
+/// for each of the sixteen possible first chunks of a key.
+///
+/// `get`, `insert` and `remove` lock only the shard that holds the key. `read` locks every shard
+/// for reading and gives a consistent, ordered view of the whole map.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use trie::ShardedMap;
+///
+/// let map = ShardedMap::new();
+/// thread::scope(|s| {
+///     for t in 0..4 {
+///         let map = &map;
+///         s.spawn(move || {
+///             for key in (t * 100)..(t + 1) * 100 {
+///                 map.insert(key, key * 2);
+///             }
+///         });
+///     }
+/// });
+///
+/// assert_eq!(map.len(), 400);
+/// assert_eq!(*map.get(&150).unwrap(), 300);
+/// assert_eq!(map.remove(&150), Some(300));
+/// assert!(map.read().iter().map(|(key, _)| key).eq((0..400).filter(|&key| key != 150)));
+/// ```
+pub struct ShardedMap<T> {
+    // Shard `i` holds the keys whose first chunk is `i`, so its root has at most child `i`.
+    shards: [RwLock<Map<T>>; SIZE],
+}
+
+impl<T> Default for ShardedMap<T> {
+    #[inline]
+    fn default() -> ShardedMap<T> { ShardedMap::new() }
+}
+
+impl<T: Debug> Debug for ShardedMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.read().iter()).finish()
+    }
+}
+
+impl<T> ShardedMap<T> {
+    /// Creates an empty map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::ShardedMap<&str> = trie::ShardedMap::new();
+    /// assert!(map.is_empty());
+    /// ```
+    #[inline]
+    pub fn new() -> ShardedMap<T> {
+        ShardedMap { shards: Default::default() }
+    }
+
+    fn read_shard(&self, key: usize) -> RwLockReadGuard<'_, Map<T>> {
+        self.shards[chunk(key, 0)].read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_shard(&self, key: usize) -> RwLockWriteGuard<'_, Map<T>> {
+        self.shards[chunk(key, 0)].write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the number of elements in the map.
+    ///
+    /// The shards are counted one at a time, so while other threads are inserting or removing
+    /// keys the result may not match the length of the map at any single moment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// assert_eq!(map.len(), 0);
+    /// map.insert(1, "a");
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+
+    /// Returns true if the map contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns a reference to the value corresponding to the key. The key's shard stays locked
+    /// for reading until the reference is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// map.insert(1, "a");
+    /// assert_eq!(*map.get(&1).unwrap(), "a");
+    /// assert!(map.get(&2).is_none());
+    /// ```
+    pub fn get(&self, key: &usize) -> Option<Ref<'_, T>> {
+        let shard = self.read_shard(*key);
+        let value: *const T = shard.get(key)?;
+        Some(Ref { _shard: shard, value })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key. The key's shard stays
+    /// locked for writing until the reference is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// map.insert(1, "a");
+    /// *map.get_mut(&1).unwrap() = "b";
+    /// assert_eq!(*map.get(&1).unwrap(), "b");
+    /// ```
+    pub fn get_mut(&self, key: &usize) -> Option<RefMut<'_, T>> {
+        let mut shard = self.write_shard(*key);
+        let value: *mut T = shard.get_mut(key)?;
+        Some(RefMut { _shard: shard, value })
+    }
+
+    /// Returns true if the map contains a value for the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// map.insert(1, "a");
+    /// assert!(map.contains_key(&1));
+    /// assert!(!map.contains_key(&2));
+    /// ```
+    #[inline]
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.read_shard(*key).contains_key(key)
+    }
+
+    /// Inserts a key-value pair into the map. If the key already had a value present in the
+    /// map, that value is returned. Otherwise, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// assert_eq!(map.insert(37, "a"), None);
+    /// assert_eq!(map.insert(37, "b"), Some("a"));
+    /// ```
+    #[inline]
+    pub fn insert(&self, key: usize, value: T) -> Option<T> {
+        self.write_shard(key).insert(key, value)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in
+    /// the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// map.insert(1, "a");
+    /// assert_eq!(map.remove(&1), Some("a"));
+    /// assert_eq!(map.remove(&1), None);
+    /// ```
+    #[inline]
+    pub fn remove(&self, key: &usize) -> Option<T> {
+        self.write_shard(*key).remove(key)
+    }
+
+    /// Locks every shard for reading and returns a view of the whole map. Writers to any shard
+    /// wait until the view is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::ShardedMap<_> = vec![(3, "c"), (1, "a"), (!0, "z")].into_iter().collect();
+    /// let view = map.read();
+    /// assert_eq!(view.len(), 3);
+    /// let entries: Vec<_> = view.iter().collect();
+    /// assert_eq!(entries, [(1, &"a"), (3, &"c"), (!0, &"z")]);
+    /// ```
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        // Shards are always locked in the same order, so two views cannot deadlock.
+        let shards = self.shards.iter()
+            .map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        ReadGuard { shards }
+    }
+
+    /// Joins the shards back into a single `Map`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map = trie::ShardedMap::new();
+    /// map.insert(1, "a");
+    /// map.insert(!0, "z");
+    /// let map = map.into_map();
+    /// assert_eq!(map.len(), 2);
+    /// assert_eq!(map[&!0], "z");
+    /// ```
+    pub fn into_map(self) -> Map<T> {
+        let mut map = Map::new();
+        for (slot, shard) in IntoIterator::into_iter(self.shards).enumerate() {
+            let shard = shard.into_inner().unwrap_or_else(PoisonError::into_inner);
+            map.attach_slot(map::slot_bounds(0, slot, 0).0, 0, shard);
+        }
+        map
+    }
+}
+
+impl<T> From<Map<T>> for ShardedMap<T> {
+    /// Splits a map into shards, moving each of its root's subtrees into a shard.
+    fn from(mut map: Map<T>) -> ShardedMap<T> {
+        let sharded = ShardedMap::new();
+        for (slot, shard) in sharded.shards.iter().enumerate() {
+            *shard.write().unwrap() = map.detach_slot(map::slot_bounds(0, slot, 0).0, 0);
+        }
+        sharded
+    }
+}
+
+impl<T> iter::FromIterator<(usize, T)> for ShardedMap<T> {
+    fn from_iter<I: IntoIterator<Item=(usize, T)>>(iter: I) -> ShardedMap<T> {
+        ShardedMap::from(iter.into_iter().collect::<Map<T>>())
+    }
+}
+
+/// A reference to a value in a sharded map, which keeps its shard locked for reading.
+pub struct Ref<'a, T: 'a> {
+    _shard: RwLockReadGuard<'a, Map<T>>,
+    value: *const T,
+}
+
+impl<'a, T> Deref for Ref<'a, T> {
+    type Target = T;
+    // The value cannot move or be dropped while its shard is locked.
+    fn deref(&self) -> &T { unsafe { &*self.value } }
+}
+
+/// A mutable reference to a value in a sharded map, which keeps its shard locked for writing.
+pub struct RefMut<'a, T: 'a> {
+    _shard: RwLockWriteGuard<'a, Map<T>>,
+    value: *mut T,
+}
+
+impl<'a, T> Deref for RefMut<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.value } }
+}
+
+impl<'a, T> DerefMut for RefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.value } }
+}
+
+/// A view of a whole sharded map, which keeps every shard locked for reading.
+pub struct ReadGuard<'a, T: 'a> {
+    shards: Vec<RwLockReadGuard<'a, Map<T>>>,
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    /// Returns true if the map contains no elements.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get(&self, key: &usize) -> Option<&T> {
+        self.shards[chunk(*key, 0)].get(key)
+    }
+
+    /// Gets an iterator over the entries of the map, sorted by key.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { shards: self.shards.iter(), iter: None }
+    }
+}
+
+/// An iterator over the entries of a sharded map, sorted by key.
+pub struct Iter<'a, T: 'a> {
+    shards: slice::Iter<'a, RwLockReadGuard<'a, Map<T>>>,
+    iter: Option<map::Iter<'a, T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<(usize, &'a T)> {
+        loop {
+            if let Some(entry) = self.iter.as_mut().and_then(|iter| iter.next()) {
+                return Some(entry);
+            }
+            self.iter = Some(self.shards.next()?.iter());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::ShardedMap;
+    use crate::Map;
+    use crate::map::USIZE_BITS;
+
+    #[test]
+    fn test_basic() {
+        let map = ShardedMap::new();
+        let keys = [0, 1, 0x10, 1 << (USIZE_BITS - 4), !0, !0 - 1];
+        for &key in keys.iter() {
+            assert_eq!(map.insert(key, key), None);
+        }
+        assert_eq!(map.len(), keys.len());
+        for &key in keys.iter() {
+            assert_eq!(*map.get(&key).unwrap(), key);
+        }
+        *map.get_mut(&1).unwrap() += 100;
+        assert_eq!(map.insert(1, 2), Some(101));
+        assert_eq!(map.remove(&0x10), Some(0x10));
+        assert_eq!(map.remove(&0x10), None);
+        assert!(map.get(&0x10).is_none());
+
+        let view = map.read();
+        assert_eq!(view.len(), keys.len() - 1);
+        assert_eq!(view.get(&!0), Some(&!0));
+        assert_eq!(view.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+                   [0, 1, 1 << (USIZE_BITS - 4), !0 - 1, !0]);
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let keys = (0..1000).map(|i: usize| i.wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize));
+        let map: Map<usize> = keys.map(|key| (key, !key)).collect();
+        let sharded = ShardedMap::from(map.clone());
+        assert_eq!(sharded.len(), map.len());
+        assert!(sharded.read().iter().eq(map.iter()));
+
+        let joined = sharded.into_map();
+        assert_eq!(joined, map);
+        assert_eq!(joined.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_threads() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 2000;
+
+        let map = ShardedMap::new();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..KEYS {
+                        let key = (i * THREADS + t).wrapping_mul(0x9e37_79b9);
+                        map.insert(key, key);
+                        if i % 3 == 0 {
+                            assert_eq!(map.remove(&key), Some(key));
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let view = map.read();
+                    assert!(view.iter().all(|(key, &value)| key == value));
+                }
+            });
+        });
+
+        let mut expected = Vec::new();
+        for t in 0..THREADS {
+            for i in (0..KEYS).filter(|i| i % 3 != 0) {
+                expected.push((i * THREADS + t).wrapping_mul(0x9e37_79b9));
+            }
+        }
+        expected.sort();
+        assert_eq!(map.len(), expected.len());
+        assert_eq!(map.read().iter().map(|(key, _)| key).collect::<Vec<_>>(), expected);
+        assert_eq!(map.into_map().validate(), Ok(()));
+    }
+}
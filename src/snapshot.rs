@@ -0,0 +1,852 @@
+//! A compact binary snapshot format for `Map` and `Set`.
+//!
+//! `Map::write_to` and `Set::write_to` write a snapshot to any `Write`, and `Map::read_from` and
+//! `Set::read_from` read it back. The values of a map are encoded with the `Codec` trait, which
+//! is implemented for the primitive types, strings, and vectors, options, boxes and pairs of
+//! encodable types.
+//!
+//! Snapshots are read and written in small pieces, so files and sockets should be wrapped in a
+//! `BufReader` or `BufWriter`. A snapshot that is truncated or damaged is reported as a
+//! `SnapshotError` rather than a panic.
+//!
+//! # Format
+//!
+//! Integers marked as varints are unsigned LEB128: seven bits per byte, least significant group
+//! first, with the high bit set on every byte except the last.
+//!
+//! | Field    | Encoding                                                          |
+//! |----------|-------------------------------------------------------------------|
+//! | magic    | the four bytes `TRIE`                                             |
+//! | version  | one byte, currently `1`                                           |
+//! | kind     | one byte, `0` for a map and `1` for a set                         |
+//! | count    | varint, the number of entries in a map or of values in a set      |
+//! | body     | described below                                                   |
+//! | checksum | CRC-32 (IEEE) of all the preceding bytes, four bytes little-endian |
+//!
+//! Keys are written in ascending order as deltas: the first key as it is, and each later key as
+//! its distance from the previous one, minus one.
+//!
+//! A map body is `count` entries, each a key delta varint followed by the value as written by
+//! `Codec::encode`.
+//!
+//! A set body splits the values into blocks of 64, where block `k` holds the values from
+//! `64 * k` to `64 * k + 63`. It starts with a varint giving the number of non-empty blocks,
+//! followed by each of them in ascending order: the block number as a key delta varint, then one
+//! byte giving the number of values in the block, from 1 to 64. A sparse block with fewer than 8
+//! values follows this with one byte per value, giving its offset within the block in ascending
+//! order. A dense block follows it with a 64-bit bitmap, little-endian, in which bit `i` is set
+//! when offset `i` is present. A count of 0 stands for a run of full blocks, with nothing after
+//! it: if the low four bits of its block number are `n`, the run is every block whose number
+//! differs from it only in its low `4 * (n + 1)` bits.
+//!
+//! # Examples
+//!
+//! ```
+//! let map: trie::Map<String> = (0..100).map(|i| (i * 3, i.to_string())).collect();
+//!
+//! let mut bytes = Vec::new();
+//! map.write_to(&mut bytes).unwrap();
+//! assert_eq!(trie::Map::<String>::read_from(&mut &bytes[..]).unwrap(), map);
+//!
+//! // Damage is detected when reading.
+//! bytes[20] ^= 1;
+//! assert!(trie::Map::<String>::read_from(&mut &bytes[..]).is_err());
+//! ```
+
+use std::boxed::Box;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::string::String;
+use std::vec::Vec;
+use std::io::{self, Read, Write};
+
+use super::map::{self, Allocator, Map};
+use super::set::{Set, WORD_SHIFT, leaf_keys, leaf_len};
+
+const MAGIC: [u8; 4] = *b"TRIE";
+const VERSION: u8 = 1;
+
+const KIND_MAP: u8 = 0;
+const KIND_SET: u8 = 1;
+
+// Set blocks with at least this many values are written as a bitmap.
+const DENSE_BLOCK: u32 = 8;
+
+/// An error returned when reading a snapshot fails.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading from the underlying stream failed, or the stream ended early.
+    Io(io::Error),
+    /// The data does not start with the snapshot magic bytes.
+    BadMagic,
+    /// The snapshot was written in a format version this library can't read.
+    UnsupportedVersion(u8),
+    /// A set snapshot was read as a map, or a map snapshot as a set.
+    WrongKind,
+    /// The checksum stored in the snapshot doesn't match its contents.
+    ChecksumMismatch {
+        /// The checksum stored in the snapshot.
+        expected: u32,
+        /// The checksum of the data that was read.
+        found: u32,
+    },
+    /// The snapshot is malformed.
+    Corrupt(&'static str),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref err) => write!(f, "failed to read snapshot: {}", err),
+            SnapshotError::BadMagic => f.write_str("not a trie snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::WrongKind => f.write_str("snapshot holds a different collection type"),
+            SnapshotError::ChecksumMismatch { expected, found } => {
+                write!(f, "snapshot checksum mismatch: expected {:08x}, found {:08x}",
+                       expected, found)
+            }
+            SnapshotError::Corrupt(what) => write!(f, "corrupt snapshot: {}", what),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SnapshotError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+/// Converts values to and from bytes in snapshots.
+pub trait Codec: Sized {
+    /// Writes the value to `writer`.
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+
+    /// Reads a value written by `encode` from `reader`.
+    fn decode<R: Read>(reader: &mut R) -> Result<Self, SnapshotError>;
+}
+
+impl<T: Codec, A: Allocator + Clone> Map<T, A> {
+    /// Writes a snapshot of the map to `writer`, in the format described in the
+    /// [`snapshot`](../snapshot/index.html) module.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let map: trie::Map<u32> = [(1, 10), (2, 20), (1000, 30)].iter().cloned().collect();
+    ///
+    /// let mut bytes = Vec::new();
+    /// map.write_to(&mut bytes).unwrap();
+    /// assert_eq!(bytes.len(), 18);
+    /// ```
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut writer = Checksummed::new(writer);
+        write_header(&mut writer, KIND_MAP, self.len())?;
+        let mut prev = None;
+        for (key, value) in self.iter() {
+            write_varint(&mut writer, key_delta(prev, key))?;
+            value.encode(&mut writer)?;
+            prev = Some(key);
+        }
+        writer.write_checksum()
+    }
+}
+
+impl<T: Codec> Map<T> {
+    /// Reads a map from a snapshot written by `write_to`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trie::snapshot::SnapshotError;
+    ///
+    /// let map: trie::Map<u32> = [(1, 10), (2, 20), (1000, 30)].iter().cloned().collect();
+    /// let mut bytes = Vec::new();
+    /// map.write_to(&mut bytes).unwrap();
+    ///
+    /// assert_eq!(trie::Map::read_from(&mut &bytes[..]).unwrap(), map);
+    ///
+    /// match trie::Map::<u32>::read_from(&mut &bytes[..10]) {
+    ///     Err(SnapshotError::Io(_)) => {}
+    ///     other => panic!("unexpected result: {:?}", other),
+    /// }
+    /// ```
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Map<T>, SnapshotError> {
+        let mut reader = Checksummed::new(reader);
+        let count = read_header(&mut reader, KIND_MAP)?;
+        let mut builder = map::SortedBuilder::new();
+        let mut prev = None;
+        for _ in 0..count {
+            let key = read_key(&mut reader, prev)?;
+            let value = T::decode(&mut reader)?;
+            // Keys only ever ascend, so the builder always accepts them.
+            let pushed = builder.push(key, value);
+            debug_assert!(pushed.is_ok());
+            prev = Some(key);
+        }
+        reader.verify_checksum()?;
+        Ok(builder.finish())
+    }
+}
+
+impl Set {
+    /// Writes a snapshot of the set to `writer`, in the format described in the
+    /// [`snapshot`](../snapshot/index.html) module. Dense regions are written as bitmaps.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..10_000).collect();
+    ///
+    /// let mut bytes = Vec::new();
+    /// set.write_to(&mut bytes).unwrap();
+    /// assert!(bytes.len() < 2000);
+    /// ```
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut writer = Checksummed::new(writer);
+        write_header(&mut writer, KIND_SET, self.len())?;
+        write_varint(&mut writer, self.map.len() as u64)?;
+        let mut prev = None;
+        for (key, &word) in self.map.iter() {
+            write_varint(&mut writer, key_delta(prev, key))?;
+            let count = word.count_ones();
+            // A marker for a run of full blocks has a count of zero, and nothing follows it.
+            writer.write_all(&[count as u8])?;
+            if count >= DENSE_BLOCK {
+                writer.write_all(&word.to_le_bytes())?;
+            } else {
+                let mut bits = word;
+                while bits != 0 {
+                    writer.write_all(&[bits.trailing_zeros() as u8])?;
+                    bits &= bits - 1;
+                }
+            }
+            prev = Some(key);
+        }
+        writer.write_checksum()
+    }
+
+    /// Reads a set from a snapshot written by `write_to`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..1000).filter(|i| i % 3 != 0).collect();
+    /// let mut bytes = Vec::new();
+    /// set.write_to(&mut bytes).unwrap();
+    ///
+    /// assert_eq!(trie::Set::read_from(&mut &bytes[..]).unwrap(), set);
+    /// assert!(trie::Map::<u32>::read_from(&mut &bytes[..]).is_err());
+    /// ```
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Set, SnapshotError> {
+        let mut reader = Checksummed::new(reader);
+        let count = read_header(&mut reader, KIND_SET)?;
+        let blocks = read_usize(&mut reader)?;
+        let mut builder = map::SortedBuilder::new();
+        let mut length = 0usize;
+        let mut prev = None;
+        // The last block number that the previous block or run covers.
+        let mut last = None;
+        for _ in 0..blocks {
+            let key = read_key(&mut reader, prev)?;
+            if key > usize::MAX >> WORD_SHIFT {
+                return Err(SnapshotError::Corrupt("value out of range"));
+            }
+            let word = read_block(&mut reader)?;
+            let (first, end) = leaf_keys(key, word)
+                .ok_or(SnapshotError::Corrupt("invalid block size"))?;
+            if last.is_some_and(|last| first <= last) {
+                return Err(SnapshotError::Corrupt("overlapping blocks"));
+            }
+            length = length.wrapping_add(leaf_len(key, word));
+            let pushed = builder.push(key, word);
+            debug_assert!(pushed.is_ok());
+            prev = Some(key);
+            last = Some(end);
+        }
+        let set = Set { map: builder.finish(), length };
+        match set.len().cmp(&count) {
+            Ordering::Greater => return Err(SnapshotError::Corrupt("more values than expected")),
+            Ordering::Less => return Err(SnapshotError::Corrupt("fewer values than expected")),
+            Ordering::Equal => {}
+        }
+        reader.verify_checksum()?;
+        Ok(set)
+    }
+}
+
+fn read_block<R: Read>(reader: &mut R) -> Result<u64, SnapshotError> {
+    let count = u32::from(read_u8(reader)?);
+    if count > 64 {
+        return Err(SnapshotError::Corrupt("invalid block size"));
+    }
+    let word = if count == 0 {
+        0
+    } else if count >= DENSE_BLOCK {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        u64::from_le_bytes(bytes)
+    } else {
+        let mut word = 0u64;
+        for _ in 0..count {
+            let offset = read_u8(reader)?;
+            if offset >= 64 || word >> offset != 0 {
+                return Err(SnapshotError::Corrupt("invalid block offset"));
+            }
+            word |= 1 << offset;
+        }
+        word
+    };
+    if word.count_ones() != count {
+        return Err(SnapshotError::Corrupt("block size doesn't match its contents"));
+    }
+    Ok(word)
+}
+
+fn write_header<W: Write>(writer: &mut W, kind: u8, count: usize) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[VERSION, kind])?;
+    write_varint(writer, count as u64)
+}
+
+// Checks the header and returns the count that follows it.
+fn read_header<R: Read>(reader: &mut R, kind: u8) -> Result<usize, SnapshotError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = read_u8(reader)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    if read_u8(reader)? != kind {
+        return Err(SnapshotError::WrongKind);
+    }
+    read_usize(reader)
+}
+
+fn key_delta(prev: Option<usize>, key: usize) -> u64 {
+    match prev {
+        Some(prev) => (key - prev - 1) as u64,
+        None => key as u64,
+    }
+}
+
+fn read_key<R: Read>(reader: &mut R, prev: Option<usize>) -> Result<usize, SnapshotError> {
+    let delta = read_usize(reader)?;
+    match prev {
+        Some(prev) => prev.checked_add(delta).and_then(|key| key.checked_add(1))
+                          .ok_or(SnapshotError::Corrupt("key out of range")),
+        None => Ok(delta),
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    while n >= 0x80 {
+        bytes[len] = n as u8 | 0x80;
+        n >>= 7;
+        len += 1;
+    }
+    bytes[len] = n as u8;
+    writer.write_all(&bytes[..=len])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, SnapshotError> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        if shift == 63 && byte > 1 {
+            break;
+        }
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(SnapshotError::Corrupt("integer out of range"))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize, SnapshotError> {
+    usize::try_from(read_varint(reader)?)
+        .map_err(|_| SnapshotError::Corrupt("integer out of range"))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, SnapshotError> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+// Passes reads or writes through to a stream, keeping a CRC-32 of the bytes that went by.
+struct Checksummed<S> {
+    inner: S,
+    crc: u32,
+}
+
+impl<S> Checksummed<S> {
+    fn new(inner: S) -> Checksummed<S> {
+        Checksummed { inner, crc: !0 }
+    }
+}
+
+impl<W: Write> Checksummed<W> {
+    // Writes the checksum of everything written so far.
+    fn write_checksum(mut self) -> io::Result<()> {
+        self.inner.write_all(&(!self.crc).to_le_bytes())
+    }
+}
+
+impl<R: Read> Checksummed<R> {
+    // Reads the stored checksum and compares it with that of everything read so far.
+    fn verify_checksum(mut self) -> Result<(), SnapshotError> {
+        let mut bytes = [0; 4];
+        self.inner.read_exact(&mut bytes)?;
+        let expected = u32::from_le_bytes(bytes);
+        let found = !self.crc;
+        if expected != found {
+            return Err(SnapshotError::ChecksumMismatch { expected, found });
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+impl Codec for u8 {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[*self])
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<u8, SnapshotError> {
+        read_u8(reader)
+    }
+}
+
+impl Codec for i8 {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[*self as u8])
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<i8, SnapshotError> {
+        Ok(read_u8(reader)? as i8)
+    }
+}
+
+macro_rules! unsigned_codec {
+    ($($t:ty),*) => {$(
+        impl Codec for $t {
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                write_varint(writer, *self as u64)
+            }
+
+            fn decode<R: Read>(reader: &mut R) -> Result<$t, SnapshotError> {
+                <$t>::try_from(read_varint(reader)?)
+                    .map_err(|_| SnapshotError::Corrupt("integer out of range"))
+            }
+        }
+    )*}
+}
+
+unsigned_codec!(u16, u32, u64, usize);
+
+// Signed integers are zigzag encoded, so that small negative numbers stay short.
+macro_rules! signed_codec {
+    ($($t:ty),*) => {$(
+        impl Codec for $t {
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                let n = *self as i64;
+                write_varint(writer, ((n << 1) ^ (n >> 63)) as u64)
+            }
+
+            fn decode<R: Read>(reader: &mut R) -> Result<$t, SnapshotError> {
+                let n = read_varint(reader)?;
+                <$t>::try_from((n >> 1) as i64 ^ -((n & 1) as i64))
+                    .map_err(|_| SnapshotError::Corrupt("integer out of range"))
+            }
+        }
+    )*}
+}
+
+signed_codec!(i16, i32, i64, isize);
+
+impl Codec for f32 {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bits().to_le_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<f32, SnapshotError> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(f32::from_bits(u32::from_le_bytes(bytes)))
+    }
+}
+
+impl Codec for f64 {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bits().to_le_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<f64, SnapshotError> {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(f64::from_bits(u64::from_le_bytes(bytes)))
+    }
+}
+
+impl Codec for bool {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[*self as u8])
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<bool, SnapshotError> {
+        match read_u8(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Corrupt("invalid bool")),
+        }
+    }
+}
+
+impl Codec for char {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_varint(writer, u64::from(*self as u32))
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<char, SnapshotError> {
+        u32::try_from(read_varint(reader)?).ok().and_then(char::from_u32)
+            .ok_or(SnapshotError::Corrupt("invalid char"))
+    }
+}
+
+impl Codec for () {
+    fn encode<W: Write>(&self, _: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn decode<R: Read>(_: &mut R) -> Result<(), SnapshotError> {
+        Ok(())
+    }
+}
+
+impl Codec for String {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_varint(writer, self.len() as u64)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<String, SnapshotError> {
+        let len = read_varint(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        String::from_utf8(bytes).map_err(|_| SnapshotError::Corrupt("invalid UTF-8"))
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_varint(writer, self.len() as u64)?;
+        self.iter().try_for_each(|elt| elt.encode(writer))
+    }
+
+    // The length isn't trusted for preallocation, since a damaged one could be enormous.
+    fn decode<R: Read>(reader: &mut R) -> Result<Vec<T>, SnapshotError> {
+        let len = read_usize(reader)?;
+        (0..len).map(|_| T::decode(reader)).collect()
+    }
+}
+
+impl<T: Codec> Codec for Option<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            Some(ref value) => {
+                writer.write_all(&[1])?;
+                value.encode(writer)
+            }
+            None => writer.write_all(&[0]),
+        }
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Option<T>, SnapshotError> {
+        match read_u8(reader)? {
+            0 => Ok(None),
+            1 => T::decode(reader).map(Some),
+            _ => Err(SnapshotError::Corrupt("invalid option tag")),
+        }
+    }
+}
+
+impl<T: Codec> Codec for Box<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (**self).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Box<T>, SnapshotError> {
+        T::decode(reader).map(Box::new)
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B) {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> Result<(A, B), SnapshotError> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::boxed::Box;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    use std::fmt::Debug;
+    use std::io::ErrorKind;
+
+    use super::{Codec, SnapshotError, crc32_update};
+    use super::super::{Map, Set};
+
+    fn round_trip<T: Codec + Debug + PartialEq>(value: T) {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).unwrap();
+        let mut reader = &bytes[..];
+        assert_eq!(T::decode(&mut reader).unwrap(), value);
+        assert!(reader.is_empty());
+    }
+
+    fn map_bytes<T: Codec>(map: &Map<T>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn set_bytes(set: &Set) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        set.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_codec() {
+        round_trip(0u8);
+        round_trip(-128i8);
+        round_trip(u64::MAX);
+        round_trip(usize::MAX);
+        round_trip(i64::MIN);
+        round_trip(-1i32);
+        round_trip(i16::MAX);
+        round_trip(1.5f64);
+        round_trip(true);
+        round_trip('\u{1f600}');
+        round_trip(String::from("snapshot"));
+        round_trip(vec![Some(1u32), None, Some(300)]);
+        round_trip((Box::new(7usize), ()));
+
+        let mut bytes = Vec::new();
+        300u64.encode(&mut bytes).unwrap();
+        assert_eq!(bytes, [0xac, 0x02]);
+        assert!(u8::decode(&mut &[][..]).is_err());
+        match bool::decode(&mut &[2][..]) {
+            Err(SnapshotError::Corrupt(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match u64::decode(&mut &[0xff; 11][..]) {
+            Err(SnapshotError::Corrupt(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match String::decode(&mut &[5, b'a'][..]) {
+            Err(SnapshotError::Io(ref err)) if err.kind() == ErrorKind::UnexpectedEof => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_map_format() {
+        let map: Map<u32> = [(1, 10), (2, 20), (1000, 30)].iter().cloned().collect();
+        let bytes = map_bytes(&map);
+        assert_eq!(&bytes[..bytes.len() - 4],
+                   &[b'T', b'R', b'I', b'E', 1, 0, 3, 1, 10, 0, 20, 0xe5, 0x07, 30][..]);
+        let crc = !crc32_update(!0, &bytes[..bytes.len() - 4]);
+        assert_eq!(&bytes[bytes.len() - 4..], &crc.to_le_bytes()[..]);
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let empty: Map<String> = Map::new();
+        assert_eq!(Map::read_from(&mut &map_bytes(&empty)[..]).unwrap(), empty);
+
+        let map: Map<String> = (0..5000).map(|i| (i * 7919 % 100_003, i.to_string()))
+            .chain(vec![(usize::MAX, "max".to_string())])
+            .collect();
+        let bytes = map_bytes(&map);
+        let read = Map::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(read, map);
+        assert_eq!(read.len(), map.len());
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api2")]
+    fn test_map_allocator() {
+        use super::super::allocator::Forwarding;
+
+        let mut map = Map::new_in(Forwarding);
+        for i in 0..1000u32 {
+            map.insert(i as usize * 7, i);
+        }
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        let read: Map<u32> = Map::read_from(&mut &bytes[..]).unwrap();
+        assert!(read == map);
+    }
+
+    #[test]
+    fn test_set_round_trip() {
+        let sets: Vec<Set> = vec![
+            Set::new(),
+            [0, 5, 63, 64, 1000, usize::MAX].iter().cloned().collect(),
+            (0..100_000).collect(),
+            (0..100_000).filter(|i| i % 13 == 0).collect(),
+            (usize::MAX - 200..=usize::MAX).collect(),
+        ];
+        for set in sets {
+            let bytes = set_bytes(&set);
+            let read = Set::read_from(&mut &bytes[..]).unwrap();
+            assert_eq!(read, set);
+            assert_eq!(read.len(), set.len());
+        }
+
+        // Ranges that cover whole slots are written as a marker for each slot.
+        let ranges = [Set::from_ranges(Some(..)),
+                      Set::from_ranges(vec![100..=1 << 30, usize::MAX - 5000..=usize::MAX])];
+        for set in ranges.iter() {
+            let bytes = set_bytes(set);
+            assert!(bytes.len() < 1000);
+            let read = Set::read_from(&mut &bytes[..]).unwrap();
+            assert!(read == *set);
+            assert_eq!(read.len(), set.len());
+            assert_eq!(read.validate(), Ok(()));
+        }
+
+        // Full blocks take a few bytes each, rather than a varint per value.
+        let dense: Set = (0..64 * 1000).collect();
+        assert!(set_bytes(&dense).len() < 10 * 1000 + 20);
+        let sparse: Set = (0..1000).map(|i| i * 1000).collect();
+        assert!(set_bytes(&sparse).len() < 4 * 1000 + 20);
+    }
+
+    #[test]
+    fn test_errors() {
+        let map: Map<u32> = [(1, 10), (2, 20), (1000, 30)].iter().cloned().collect();
+        let bytes = map_bytes(&map);
+
+        match Map::<u32>::read_from(&mut &b"TREE"[..]) {
+            Err(SnapshotError::BadMagic) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        match Map::<u32>::read_from(&mut &newer[..]) {
+            Err(SnapshotError::UnsupportedVersion(2)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Set::read_from(&mut &bytes[..]) {
+            Err(SnapshotError::WrongKind) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut damaged = bytes.clone();
+        damaged[13] ^= 0x40;
+        match Map::<u32>::read_from(&mut &damaged[..]) {
+            Err(SnapshotError::ChecksumMismatch { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        for len in 0..bytes.len() {
+            assert!(Map::<u32>::read_from(&mut &bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_corruption() {
+        // No single damaged bit may go unnoticed or cause a panic.
+        let map: Map<Vec<u8>> = (0..20).map(|i| (i * i * 31, vec![i as u8; i % 4])).collect();
+        let set: Set = (0..300).filter(|i| i % 7 != 0 || *i > 200).collect();
+        let map_bytes = map_bytes(&map);
+        let set_bytes = set_bytes(&set);
+        for i in 0..map_bytes.len() * 8 {
+            let mut damaged = map_bytes.clone();
+            damaged[i / 8] ^= 1 << (i % 8);
+            assert!(Map::<Vec<u8>>::read_from(&mut &damaged[..]).is_err());
+        }
+        for i in 0..set_bytes.len() * 8 {
+            let mut damaged = set_bytes.clone();
+            damaged[i / 8] ^= 1 << (i % 8);
+            assert!(Set::read_from(&mut &damaged[..]).is_err());
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Statistics about the shape and memory use of maps and sets.
+//!
+//! The depth of a key in a trie depends on how many leading chunks it shares with other keys, not
+//! on how many keys there are. Keys that differ only in their last few bits, such as small
+//! integers, are stored at the bottom of long chains of internal nodes that each have a single
+//! child. `Map::stats` reports how many keys sit at each depth and how many such nodes there are,
+//! so that the cost of a key distribution can be measured rather than guessed.
+
+use alloc::vec::Vec;
+
+use super::Set;
+use super::map::{Allocator, Arena, InternalNode, Map};
+use super::map::TrieNode::*;
+
+/// Statistics about the shape and memory use of a map or set, returned by `Map::stats` and
+/// `Set::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of internal nodes, including the root.
+    pub internal_nodes: usize,
+    /// The number of external nodes. There is one for each key of a map, and one for each
+    /// 64-value word of a set.
+    pub external_nodes: usize,
+    /// `depths[i]` is the number of external nodes at depth `i`, where the children of the root
+    /// are at depth 0. The last entry is never zero.
+    pub depths: Vec<usize>,
+    /// The number of internal nodes other than the root that have a single child.
+    pub single_child_nodes: usize,
+    /// The number of bytes of heap memory used for internal nodes, including spare capacity and
+    /// nodes that were freed for reuse. The root is stored inline and is not counted.
+    pub node_bytes: usize,
+    /// The number of bytes of heap memory owned by the values, as reported by the function
+    /// passed to `Map::stats_with`. This is zero for `Map::stats` and `Set::stats`.
+    pub value_bytes: usize,
+}
+
+impl Stats {
+    /// Returns the average number of non-empty children of an internal node, out of 16.
+    pub fn average_fill(&self) -> f64 {
+        (self.internal_nodes - 1 + self.external_nodes) as f64 / self.internal_nodes as f64
+    }
+
+    /// Returns the deepest depth at which there is an external node, or `None` if there are
+    /// none.
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depths.len().checked_sub(1)
+    }
+
+    /// Returns the estimated number of bytes of heap memory used in total.
+    pub fn heap_size(&self) -> usize {
+        self.node_bytes + self.value_bytes
+    }
+}
+
+impl<T, A: Allocator + Clone> Map<T, A> {
+    /// Returns statistics about the shape of the map and the memory used by its nodes.
+    ///
+    /// This visits every node, so it takes time proportional to the size of the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // 1 and 2 differ only in their last chunk, so they sit at the bottom of a chain.
+    /// let mut map = trie::Map::new();
+    /// map.insert(1, "a");
+    /// map.insert(2, "b");
+    ///
+    /// let last = trie::map::USIZE_BITS / 4 - 1;
+    /// let stats = map.stats();
+    /// assert_eq!(stats.external_nodes, 2);
+    /// assert_eq!(stats.max_depth(), Some(last));
+    /// assert_eq!(stats.single_child_nodes, last - 1);
+    /// ```
+    pub fn stats(&self) -> Stats {
+        self.stats_with(|_| 0)
+    }
+
+    /// Returns statistics like `stats`, with `value_bytes` set to the sum of `heap_size` over
+    /// the values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut map = trie::Map::new();
+    /// map.insert(1, vec![0u8; 100]);
+    /// map.insert(2, vec![0u8; 50]);
+    ///
+    /// let stats = map.stats_with(|value| value.capacity());
+    /// assert_eq!(stats.value_bytes, 150);
+    /// ```
+    pub fn stats_with<F>(&self, mut heap_size: F) -> Stats where F: FnMut(&T) -> usize {
+        let mut stats = Stats { node_bytes: self.nodes.heap_size(), ..Stats::default() };
+        visit(&self.nodes, &self.root, 0, &mut stats, &mut heap_size);
+        stats
+    }
+}
+
+impl Set {
+    /// Returns statistics about the shape of the set's trie and the memory used by its nodes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let set: trie::Set = (0..1000).collect();
+    ///
+    /// // Values are stored 64 to a word.
+    /// assert_eq!(set.stats().external_nodes, 16);
+    /// ```
+    pub fn stats(&self) -> Stats {
+        self.map.stats()
+    }
+}
+
+// Adds `node`, whose children are at depth `idx`, and everything below it to `stats`.
+fn visit<T, A, F>(nodes: &Arena<T, A>, node: &InternalNode<T>, idx: usize, stats: &mut Stats,
+                  heap_size: &mut F)
+    where A: Allocator + Clone, F: FnMut(&T) -> usize {
+    stats.internal_nodes += 1;
+    if idx > 0 && node.count == 1 {
+        stats.single_child_nodes += 1;
+    }
+    for child in node.children.iter() {
+        match *child {
+            Internal(id) => visit(nodes, &nodes[id], idx + 1, stats, heap_size),
+            External(_, ref value) => {
+                stats.external_nodes += 1;
+                stats.value_bytes += heap_size(value);
+                if stats.depths.len() <= idx {
+                    stats.depths.resize(idx + 1, 0);
+                }
+                stats.depths[idx] += 1;
+            }
+            Nothing => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::super::{Map, Set};
+    use super::super::map::{InternalNode, MAX_DEPTH, USIZE_BITS};
+
+    #[test]
+    fn test_stats() {
+        let empty = Map::<u8>::new().stats();
+        assert_eq!((empty.internal_nodes, empty.external_nodes), (1, 0));
+        assert_eq!((empty.average_fill(), empty.max_depth()), (0.0, None));
+
+        // Spread out keys all fit in the root.
+        let map: Map<usize> = (0..16).map(|i| (i << (USIZE_BITS - 4), i)).collect();
+        let stats = map.stats();
+        assert_eq!(stats.depths, [16]);
+        assert_eq!((stats.internal_nodes, stats.average_fill()), (1, 16.0));
+
+        // Consecutive small keys fill the bottom of a single chain.
+        let map: Map<usize> = (0..256).map(|i| (i, i)).collect();
+        let stats = map.stats();
+        let mut depths = vec![0; MAX_DEPTH - 1];
+        depths.push(256);
+        assert_eq!(stats.depths, depths);
+        assert_eq!(stats.internal_nodes, 1 + (MAX_DEPTH - 2) + 16);
+        assert_eq!(stats.single_child_nodes, MAX_DEPTH - 3);
+        let nodes = stats.internal_nodes - 1;
+        assert!(stats.node_bytes >= nodes * mem::size_of::<InternalNode<usize>>());
+        assert_eq!(stats.heap_size(), stats.node_bytes);
+
+        let set: Set = (0..256).collect();
+        assert_eq!(set.stats().external_nodes, 4);
+    }
+}